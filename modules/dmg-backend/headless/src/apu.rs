@@ -0,0 +1,275 @@
+use dmg_lib::apu::{
+    device::{Audio, Sample},
+    samples::SamplesMutex,
+    Apu,
+};
+use std::io::{self, Seek, SeekFrom, Write};
+
+// WAVE_FORMAT_PCM
+const FORMAT_PCM: u16 = 0x1;
+// WAVE_FORMAT_IEEE_FLOAT
+const FORMAT_FLOAT: u16 = 0x3;
+
+/// Samples that can be stored in a WAV file.
+pub trait WavSample: Sample {
+    /// WAV format tag.
+    const FORMAT: u16;
+    /// Bits per sample.
+    const BITS: u16;
+
+    /// Write the sample in little endian.
+    fn write_le<W: Write>(self, write: &mut W) -> io::Result<()>;
+}
+
+impl WavSample for i16 {
+    const FORMAT: u16 = FORMAT_PCM;
+    const BITS: u16 = 16;
+
+    fn write_le<W: Write>(self, write: &mut W) -> io::Result<()> {
+        write.write_all(&self.to_le_bytes())
+    }
+}
+
+// 16bit WAV samples are signed, so unsigned samples are re-centered.
+impl WavSample for u16 {
+    const FORMAT: u16 = FORMAT_PCM;
+    const BITS: u16 = 16;
+
+    fn write_le<W: Write>(self, write: &mut W) -> io::Result<()> {
+        write.write_all(&(self ^ 0x8000).to_le_bytes())
+    }
+}
+
+impl WavSample for f32 {
+    const FORMAT: u16 = FORMAT_FLOAT;
+    const BITS: u16 = 32;
+
+    fn write_le<W: Write>(self, write: &mut W) -> io::Result<()> {
+        write.write_all(&self.to_le_bytes())
+    }
+}
+
+/// PCM WAV file writer.
+///
+/// The header is written when the writer is created, and the chunk sizes are
+/// filled in by [`WavWriter::finish`].
+///
+/// [`WavWriter::finish`]: #
+pub struct WavWriter<W: Write + Seek, S: WavSample> {
+    write: W,
+    channels: u16,
+    len: u32,
+    _phantom: std::marker::PhantomData<S>,
+}
+
+impl<W: Write + Seek, S: WavSample> WavWriter<W, S> {
+    /// Write the WAV header for the given sample rate and number of channels.
+    pub fn new(mut write: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let block_align = channels * S::BITS / 8;
+        let byte_rate = sample_rate * u32::from(block_align);
+
+        write.write_all(b"RIFF")?;
+        write.write_all(&0u32.to_le_bytes())?; // filled in by finish
+        write.write_all(b"WAVE")?;
+        write.write_all(b"fmt ")?;
+        write.write_all(&16u32.to_le_bytes())?;
+        write.write_all(&S::FORMAT.to_le_bytes())?;
+        write.write_all(&channels.to_le_bytes())?;
+        write.write_all(&sample_rate.to_le_bytes())?;
+        write.write_all(&byte_rate.to_le_bytes())?;
+        write.write_all(&block_align.to_le_bytes())?;
+        write.write_all(&S::BITS.to_le_bytes())?;
+        write.write_all(b"data")?;
+        write.write_all(&0u32.to_le_bytes())?; // filled in by finish
+
+        Ok(Self { write,
+                  channels,
+                  len: 0,
+                  _phantom: std::marker::PhantomData })
+    }
+
+    /// Returns the number of channels.
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Write a single sample. Samples of multi-channel files are interleaved.
+    pub fn write_sample(&mut self, sample: S) -> io::Result<()> {
+        sample.write_le(&mut self.write)?;
+        self.len += u32::from(S::BITS / 8);
+        Ok(())
+    }
+
+    /// Fill in the chunk sizes of the header and return the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write.seek(SeekFrom::Start(4))?;
+        self.write.write_all(&(36 + self.len).to_le_bytes())?;
+        self.write.seek(SeekFrom::Start(40))?;
+        self.write.write_all(&self.len.to_le_bytes())?;
+        self.write.seek(SeekFrom::End(0))?;
+        self.write.flush()?;
+        Ok(self.write)
+    }
+}
+
+/// Records the output of the APU into WAV files.
+///
+/// The mixed output is written using the sample rate and number of channels of
+/// the audio device. Optionally, the output of each channel (before panning and
+/// mute/solo) can be written into four mono stem files.
+///
+/// Samples are generated by the APU as the emulation runs, so
+/// [`WavRecorder::record`] should be called often enough (i.e. once per frame)
+/// to not lose any of them.
+///
+/// [`WavRecorder::record`]: #
+pub struct WavRecorder<D: Audio, W: Write + Seek>
+    where D::Sample: WavSample
+{
+    samples: SamplesMutex<D>,
+    mix: WavWriter<W, D::Sample>,
+    stems: Option<[WavWriter<W, D::Sample>; 4]>,
+}
+
+impl<D: Audio, W: Write + Seek> WavRecorder<D, W> where D::Sample: WavSample
+{
+    /// Record the mixed output of the APU.
+    pub fn new(apu: &Apu<D>, mix: W) -> io::Result<Self> {
+        let channels = if D::mono() { 1 } else { 2 };
+        Ok(Self { samples: apu.samples(),
+                  mix: WavWriter::new(mix, D::sample_rate() as u32, channels)?,
+                  stems: None })
+    }
+
+    /// Also record the output of each channel, in [`Channel::ALL`] order.
+    ///
+    /// [`Channel::ALL`]: #
+    pub fn with_stems(mut self, stems: [W; 4]) -> io::Result<Self> {
+        let rate = D::sample_rate() as u32;
+        let [ch0, ch1, ch2, ch3] = stems;
+        self.stems = Some([WavWriter::new(ch0, rate, 1)?,
+                           WavWriter::new(ch1, rate, 1)?,
+                           WavWriter::new(ch2, rate, 1)?,
+                           WavWriter::new(ch3, rate, 1)?]);
+        Ok(self)
+    }
+
+    /// Write the samples generated since the last call.
+    pub fn record(&mut self) -> io::Result<()> {
+        for tap in self.samples.lock_taps() {
+            if self.mix.channels() == 1 {
+                self.mix.write_sample(tap.mix[0])?;
+            } else {
+                self.mix.write_sample(tap.mix[0])?;
+                self.mix.write_sample(tap.mix[1])?;
+            }
+            if let Some(stems) = &mut self.stems {
+                for (stem, sample) in stems.iter_mut().zip(tap.channels.iter()) {
+                    stem.write_sample(*sample)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record any pending samples and finish the WAV files.
+    ///
+    /// Returns the mix writer, and the stem writers if stems were recorded.
+    pub fn finish(mut self) -> io::Result<(W, Option<[W; 4]>)> {
+        self.record()?;
+        let mix = self.mix.finish()?;
+        let stems = match self.stems {
+            Some([ch0, ch1, ch2, ch3]) => {
+                Some([ch0.finish()?, ch1.finish()?, ch2.finish()?, ch3.finish()?])
+            }
+            None => None,
+        };
+        Ok((mix, stems))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::apu::WavRecorder;
+    use dmg_lib::{
+        apu::{
+            device::{Mono44100, Stereo44100},
+            Apu,
+        },
+        device::Device,
+    };
+    use std::io::Cursor;
+
+    fn u16_at(wav: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([wav[offset], wav[offset + 1]])
+    }
+
+    fn u32_at(wav: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([wav[offset], wav[offset + 1], wav[offset + 2], wav[offset + 3]])
+    }
+
+    #[test]
+    fn header() {
+        let apu = Apu::<Stereo44100<i16>>::default();
+        let mut recorder = WavRecorder::new(&apu, Cursor::new(Vec::new())).unwrap();
+
+        // one second worth of emulation, in 1/60 second steps
+        for _ in 0..60 {
+            apu.lock().step(4_194_304 / 60);
+            recorder.record().unwrap();
+        }
+
+        let (mix, stems) = recorder.finish().unwrap();
+        let wav = mix.into_inner();
+        assert!(stems.is_none());
+
+        assert_eq!(b"RIFF", &wav[0..4]);
+        assert_eq!(wav.len() as u32 - 8, u32_at(&wav, 4));
+        assert_eq!(b"WAVE", &wav[8..12]);
+        assert_eq!(1, u16_at(&wav, 20)); // PCM
+        assert_eq!(2, u16_at(&wav, 22)); // stereo
+        assert_eq!(44100, u32_at(&wav, 24));
+        assert_eq!(44100 * 4, u32_at(&wav, 28));
+        assert_eq!(4, u16_at(&wav, 32));
+        assert_eq!(16, u16_at(&wav, 34));
+        assert_eq!(b"data", &wav[36..40]);
+        assert_eq!(wav.len() as u32 - 44, u32_at(&wav, 40));
+
+        // the APU generates samples at the rate of the device
+        let frames = u32_at(&wav, 40) / 4;
+        assert!((44099..=44100).contains(&frames), "{}", frames);
+    }
+
+    #[test]
+    fn stems() {
+        let mut apu = Apu::<Mono44100<f32>>::default();
+
+        // Channel 2 square wave, routed to both terminals
+        apu.write(0xff26, 0x80);
+        apu.write(0xff25, 0x22);
+        apu.write(0xff17, 0xf0);
+        apu.write(0xff19, 0x87);
+
+        let streams = [Cursor::new(Vec::new()),
+                       Cursor::new(Vec::new()),
+                       Cursor::new(Vec::new()),
+                       Cursor::new(Vec::new())];
+        let mut recorder = WavRecorder::new(&apu, Cursor::new(Vec::new())).unwrap()
+                                                                           .with_stems(streams)
+                                                                           .unwrap();
+        apu.lock().step(4_194_304 / 60);
+        recorder.record().unwrap();
+
+        let (mix, stems) = recorder.finish().unwrap();
+        let mix = mix.into_inner();
+        let [ch0, ch1, _, _] = stems.unwrap();
+        let (ch0, ch1) = (ch0.into_inner(), ch1.into_inner());
+
+        assert_eq!(1, u16_at(&mix, 22)); // mono
+        assert_eq!(3, u16_at(&mix, 20)); // float
+        assert_eq!(mix.len(), ch1.len());
+        assert_eq!(&mix[44..], &ch1[44..]);
+        assert!(ch0[44..].iter().all(|b| *b == 0));
+        assert!(ch1[44..].iter().any(|b| *b != 0));
+    }
+}
@@ -1,2 +1,31 @@
+//! Backend without any window or audio device, for tests and tools.
+//!
+//! - `video` feature: [`HeadlessVideo`] keeps the last frame in memory, to be
+//!   hashed (see [`frame_hash`]) or saved as a PNG or PPM screenshot. It can
+//!   wrap the video output of a frontend to take screenshots of it.
+//! - `audio` feature: [`WavRecorder`] records the output of the APU into WAV
+//!   files.
+//!
+//! ```
+//! use dmg_backend_headless::HeadlessVideo;
+//! use dmg_lib::Builder;
+//!
+//! let mut dmg = Builder::default().video(HeadlessVideo::new()).build();
+//! dmg.emulate_frame();
+//!
+//! let video = dmg.mmu().ppu().video();
+//! println!("frame {}: {:016x}", video.frames(), video.frame_hash());
+//! ```
+//!
+//! [`HeadlessVideo`]: #
+//! [`frame_hash`]: #
+//! [`WavRecorder`]: #
+#[cfg(feature = "audio")]
+pub mod apu;
 #[cfg(feature = "video")]
 pub mod ppu;
+
+#[cfg(feature = "audio")]
+pub use apu::{WavRecorder, WavSample, WavWriter};
+#[cfg(feature = "video")]
+pub use ppu::{frame_hash, HeadlessVideo};
@@ -1,30 +1,261 @@
-use dmg_lib::ppu::{palette::Color, Video};
-use std::mem;
+use dmg_lib::{
+    ppu::{palette::Color, Video, LCD_HEIGHT, LCD_WIDTH},
+    sgb::{SGB_HEIGHT, SGB_WIDTH},
+    state,
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    mem,
+    path::Path,
+};
 
-pub type Buffer = [[Color; 160]; 144];
+pub type Buffer = [[Color; LCD_WIDTH]; LCD_HEIGHT];
+pub type SgbBuffer = [[Color; SGB_WIDTH]; SGB_HEIGHT];
 
-pub struct HeadlessVideo {
+/// Video output that keeps the last frame in memory.
+///
+/// Frames are drawn into a back buffer, which is swapped with the front buffer
+/// once complete, so [`HeadlessVideo::buffer`] always returns a whole frame.
+/// Screenshots include the SGB border, if it's being drawn.
+///
+/// It can also wrap another video output (see [`HeadlessVideo::wrap`]), to
+/// capture the frames shown by a frontend.
+///
+/// [`HeadlessVideo::buffer`]: #
+/// [`HeadlessVideo::wrap`]: #
+pub struct HeadlessVideo<V = ()> {
+    inner: V,
     front: Box<Buffer>,
     back: Box<Buffer>,
+    sgb: Box<SgbBuffer>,
+    border: bool,
+    frames: u64,
+}
+
+impl Default for HeadlessVideo {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl HeadlessVideo {
     pub fn new() -> Self {
-        Self { front: Box::new([[[0, 0, 0]; 160]; 144]),
-               back: Box::new([[[0, 0, 0]; 160]; 144]) }
+        Self::wrap(())
+    }
+}
+
+impl<V> HeadlessVideo<V> {
+    /// Keep the frames drawn to the given video output.
+    pub fn wrap(inner: V) -> Self {
+        Self { inner,
+               front: Box::new([[[0, 0, 0]; LCD_WIDTH]; LCD_HEIGHT]),
+               back: Box::new([[[0, 0, 0]; LCD_WIDTH]; LCD_HEIGHT]),
+               sgb: Box::new([[[0, 0, 0]; SGB_WIDTH]; SGB_HEIGHT]),
+               border: false,
+               frames: 0 }
     }
 
+    pub fn inner(&self) -> &V {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut V {
+        &mut self.inner
+    }
+
+    /// Returns the last frame.
     pub fn buffer(&self) -> &Buffer {
         self.front.as_ref()
     }
 
+    /// Returns the number of frames drawn.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Returns the pixels of the last frame as RGB bytes, row by row.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.front.iter().flatten().flatten().copied().collect()
+    }
+
+    /// Hash of the last frame (see [`frame_hash`]).
+    ///
+    /// [`frame_hash`]: #
+    pub fn frame_hash(&self) -> u64 {
+        frame_hash(self.buffer())
+    }
+
+    /// Returns the last SGB frame (the border and the screen), if the border is
+    /// being drawn.
+    pub fn sgb_buffer(&self) -> Option<&SgbBuffer> {
+        if self.border {
+            Some(self.sgb.as_ref())
+        } else {
+            None
+        }
+    }
+
+    /// Write the last frame as a binary PPM image.
+    pub fn write_ppm<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let (width, height, pixels) = self.screenshot();
+        write!(out, "P6\n{} {}\n255\n", width, height)?;
+        out.write_all(&pixels)?;
+        out.flush()
+    }
+
+    /// Write the last frame as a PNG image.
+    pub fn write_png<W: Write>(&self, out: W) -> io::Result<()> {
+        let (width, height, pixels) = self.screenshot();
+        let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
+        Ok(())
+    }
+
+    /// Write the last frame to a file, as a PNG image if the path ends with
+    /// `.png`, or as a PPM image otherwise.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let png = path.as_ref().extension().is_some_and(|ext| ext == "png");
+        let file = BufWriter::new(File::create(path)?);
+        if png {
+            self.write_png(file)
+        } else {
+            self.write_ppm(file)
+        }
+    }
+
+    // Size & RGB bytes of the image written by the screenshots.
+    fn screenshot(&self) -> (usize, usize, Vec<u8>) {
+        match self.sgb_buffer() {
+            Some(sgb) => (SGB_WIDTH, SGB_HEIGHT, sgb.iter().flatten().flatten().copied().collect()),
+            None => (LCD_WIDTH, LCD_HEIGHT, self.to_rgb()),
+        }
+    }
+
     fn swap(&mut self) {
         mem::swap(&mut self.front, &mut self.back);
     }
 }
 
-impl Video for HeadlessVideo {
-    fn draw_video(&mut self, pixels: &[[[u8; 3]; 160]; 144]) {
-        unimplemented!()
+impl<V: Video> Video for HeadlessVideo<V> {
+    fn draw_video(&mut self, pixels: &Buffer) {
+        self.back.copy_from_slice(pixels);
+        self.swap();
+        self.border = false;
+        self.frames += 1;
+        self.inner.draw_video(pixels);
+    }
+
+    // Called after draw_video, on the frames with the SGB border.
+    fn draw_sgb_video(&mut self, pixels: &SgbBuffer) {
+        self.sgb.copy_from_slice(pixels);
+        self.border = true;
+        self.inner.draw_sgb_video(pixels);
+    }
+}
+
+/// Hash of the RGB bytes of a frame (see [`state::hash`]), to compare the
+/// output against known values.
+///
+/// [`state::hash`]: #
+pub fn frame_hash(buffer: &Buffer) -> u64 {
+    let bytes: Vec<_> = buffer.iter().flatten().flatten().copied().collect();
+    state::hash(&bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ppu::{frame_hash, HeadlessVideo};
+    use dmg_lib::{cartridge::Rom, ppu::Video, Builder};
+
+    #[test]
+    fn frames() {
+        let mut video = HeadlessVideo::new();
+        let mut pixels = Box::new([[[0, 0, 0]; 160]; 144]);
+        // must not change between versions
+        assert_eq!(0x740b_d8a5_762c_3b25, frame_hash(&pixels));
+
+        pixels[0][0] = [0xff, 0x80, 0x00];
+        video.draw_video(&pixels);
+        assert_eq!(1, video.frames());
+        assert_eq!([0xff, 0x80, 0x00], video.buffer()[0][0]);
+
+        let hash = video.frame_hash();
+        assert_eq!(hash, frame_hash(&pixels));
+        pixels[143][159] = [0, 0, 1];
+        assert_ne!(hash, frame_hash(&pixels));
+        // the front buffer is only replaced by complete frames
+        assert_eq!(hash, video.frame_hash());
+
+        let mut ppm = Vec::new();
+        video.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n160 144\n255\n\xff\x80\x00"));
+        assert_eq!(15 + 160 * 144 * 3, ppm.len());
+
+        let mut png = Vec::new();
+        video.write_png(&mut png).unwrap();
+        assert!(png.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn sgb() {
+        let mut video = HeadlessVideo::new();
+        let pixels = Box::new([[[0, 0, 0]; 160]; 144]);
+        video.draw_video(&pixels);
+        video.draw_sgb_video(&Box::new([[[0xff, 0, 0]; 256]; 224]));
+        assert_eq!(Some([0xff, 0, 0]), video.sgb_buffer().map(|sgb| sgb[0][0]));
+
+        let mut ppm = Vec::new();
+        video.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n256 224\n255\n\xff\x00\x00"));
+
+        // the border is gone if the next frame doesn't draw it
+        video.draw_video(&pixels);
+        assert_eq!(None, video.sgb_buffer());
+    }
+
+    #[test]
+    fn wrap() {
+        let mut video = HeadlessVideo::wrap(HeadlessVideo::new());
+        let mut pixels = Box::new([[[0, 0, 0]; 160]; 144]);
+        pixels[0][0] = [0xff, 0x80, 0x00];
+        video.draw_video(&pixels);
+        assert_eq!(1, video.inner().frames());
+        assert_eq!(video.buffer(), video.inner().buffer());
+    }
+
+    #[test]
+    fn emulator() {
+        let mut rom = vec![0u8; 0x8000];
+        // 0100: NOP
+        // 0101: JP $0150
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xc3, 0x50, 0x01]);
+        // 0150: XOR A
+        // 0151: LDH ($40),A
+        // 0153: LD HL,$8000
+        // 0156: LD A,L
+        // 0157: XOR H
+        // 0158: LD (HL+),A
+        // 0159: LD A,H
+        // 015A: CP $98
+        // 015C: JR NZ,$0156
+        // 015E: LD A,$91
+        // 0160: LDH ($40),A
+        // 0162: JR $0162
+        rom[0x0150..0x0164].copy_from_slice(&[0xaf, 0xe0, 0x40, 0x21, 0x00, 0x80, 0x7d, 0xac,
+                                              0x22, 0x7c, 0xfe, 0x98, 0x20, 0xf8, 0x3e, 0x91,
+                                              0xe0, 0x40, 0x18, 0xfe]);
+        let mut dmg = Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                                        .video(HeadlessVideo::new())
+                                        .build();
+        // filling the tile data takes a few frames
+        for _ in 0..10 {
+            dmg.emulate_frame();
+        }
+
+        // must not change between versions
+        assert_eq!(0x8aff_2180_8005_4a55, dmg.mmu().ppu().video().frame_hash());
     }
 }
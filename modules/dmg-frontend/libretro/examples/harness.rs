@@ -0,0 +1,149 @@
+//! Minimal libretro frontend, to test the core without RetroArch.
+//!
+//! ```text
+//! cargo build -p dmg-frontend-libretro
+//! cargo run -p dmg-frontend-libretro --example harness -- \
+//!     target/debug/libdmg_frontend_libretro.so <rom> [frames]
+//! ```
+use dmg_frontend_libretro::sys::*;
+use dmg_lib::state;
+use libloading::{Library, Symbol};
+use std::{
+    env, fs,
+    os::raw::{c_int, c_uint, c_void},
+    process, ptr, slice,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+static FRAMES: AtomicUsize = AtomicUsize::new(0);
+static SAMPLES: AtomicUsize = AtomicUsize::new(0);
+// Hash of the last frame.
+static HASH: AtomicU64 = AtomicU64::new(0);
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const c_int) == PIXEL_FORMAT_XRGB8888,
+        ENVIRONMENT_SET_VARIABLES | ENVIRONMENT_SET_INPUT_DESCRIPTORS => true,
+        ENVIRONMENT_SET_GEOMETRY => {
+            let geometry = &*(data as *const GameGeometry);
+            println!("Geometry: {}x{}", geometry.base_width, geometry.base_height);
+            true
+        }
+        ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = false;
+            true
+        }
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void,
+                                   width: c_uint,
+                                   height: c_uint,
+                                   pitch: usize) {
+    let frame = slice::from_raw_parts(data as *const u8, pitch * height as usize);
+    let pixels: Vec<_> = frame.chunks(pitch)
+                              .flat_map(|row| &row[..width as usize * 4])
+                              .copied()
+                              .collect();
+    HASH.store(state::hash(&pixels), Ordering::SeqCst);
+    FRAMES.fetch_add(1, Ordering::SeqCst);
+}
+
+unsafe extern "C" fn audio_sample(_: i16, _: i16) {
+    SAMPLES.fetch_add(1, Ordering::SeqCst);
+}
+
+unsafe extern "C" fn audio_sample_batch(_: *const i16, frames: usize) -> usize {
+    SAMPLES.fetch_add(frames, Ordering::SeqCst);
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(_: c_uint, _: c_uint, _: c_uint, _: c_uint) -> i16 {
+    0
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("Usage: harness <core> <rom> [frames]");
+        process::exit(1);
+    }
+    let rom = fs::read(&args[1]).expect("Error reading ROM");
+    let frames: usize = args.get(2)
+                            .map(|frames| frames.parse().expect("Invalid number of frames"))
+                            .unwrap_or(600);
+
+    unsafe {
+        let core = Library::new(&args[0]).expect("Error loading core");
+        macro_rules! sym {
+            ($name:ident: $ty:ty) => {
+                let $name: Symbol<$ty> = core.get(concat!(stringify!($name), "\0").as_bytes())
+                                             .expect(stringify!($name));
+            };
+        }
+        sym!(retro_api_version: unsafe extern "C" fn() -> c_uint);
+        sym!(retro_set_environment: unsafe extern "C" fn(EnvironmentFn));
+        sym!(retro_set_video_refresh: unsafe extern "C" fn(VideoRefreshFn));
+        sym!(retro_set_audio_sample: unsafe extern "C" fn(AudioSampleFn));
+        sym!(retro_set_audio_sample_batch: unsafe extern "C" fn(AudioSampleBatchFn));
+        sym!(retro_set_input_poll: unsafe extern "C" fn(InputPollFn));
+        sym!(retro_set_input_state: unsafe extern "C" fn(InputStateFn));
+        sym!(retro_init: unsafe extern "C" fn());
+        sym!(retro_deinit: unsafe extern "C" fn());
+        sym!(retro_get_system_av_info: unsafe extern "C" fn(*mut SystemAvInfo));
+        sym!(retro_load_game: unsafe extern "C" fn(*const GameInfo) -> bool);
+        sym!(retro_unload_game: unsafe extern "C" fn());
+        sym!(retro_run: unsafe extern "C" fn());
+        sym!(retro_serialize_size: unsafe extern "C" fn() -> usize);
+        sym!(retro_serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool);
+        sym!(retro_unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool);
+        sym!(retro_get_memory_size: unsafe extern "C" fn(c_uint) -> usize);
+
+        assert_eq!(API_VERSION, retro_api_version());
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample(audio_sample);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        let game = GameInfo { path: ptr::null(),
+                              data: rom.as_ptr() as _,
+                              size: rom.len(),
+                              meta: ptr::null() };
+        if !retro_load_game(&game) {
+            eprintln!("Error loading game");
+            process::exit(1);
+        }
+        let mut info = std::mem::zeroed::<SystemAvInfo>();
+        retro_get_system_av_info(&mut info);
+        println!("{:.4} fps, {}Hz", info.timing.fps, info.timing.sample_rate);
+
+        for _ in 0..frames {
+            retro_run();
+        }
+
+        // restoring a savestate must reproduce the same frame
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(retro_serialize(state.as_mut_ptr() as _, state.len()));
+        retro_run();
+        let hash = HASH.load(Ordering::SeqCst);
+        assert!(retro_unserialize(state.as_ptr() as _, state.len()));
+        retro_run();
+        assert_eq!(hash, HASH.load(Ordering::SeqCst), "Savestate mismatch");
+
+        println!("Frames: {}", FRAMES.load(Ordering::SeqCst));
+        println!("Audio frames: {}", SAMPLES.load(Ordering::SeqCst));
+        println!("Savestate: {} bytes", state.len());
+        println!("Battery RAM: {} bytes",
+                 retro_get_memory_size(MEMORY_SAVE_RAM));
+        println!("Frame hash: {:016x}", hash);
+
+        retro_unload_game();
+        retro_deinit();
+    }
+}
@@ -0,0 +1,599 @@
+//! libretro core.
+//!
+//! Builds the emulator as a dynamic library implementing the [libretro] API,
+//! so games can be played in RetroArch (or any other libretro frontend):
+//!
+//! - Video in XRGB8888, or RGB565 if the frontend doesn't support it. The SGB
+//!   border is shown when emulating the Super Game Boy.
+//! - Stereo audio at 44100Hz.
+//! - Joypad input on the first port.
+//! - Savestates, battery RAM (`.srm` files) & cheats.
+//! - Core options to pick the model and the palette of monochrome games.
+//!
+//! The `harness` example loads the core and runs a ROM without a frontend, and
+//! the `dylib` test checks that the built library can be loaded the same way.
+//!
+//! [libretro]: https://www.libretro.com
+#![allow(clippy::missing_safety_doc)] // called by the libretro frontend
+use crate::sys::*;
+use dmg_lib::{
+    apu::device::Stereo44100,
+    cartridge::{self, Cartridge},
+    joypad::{Btn, Dir},
+    ppu::{
+        palette::{self, Color},
+        Video, LCD_HEIGHT, LCD_WIDTH,
+    },
+    sgb::{SGB_HEIGHT, SGB_WIDTH},
+    Builder, GameBoy, Model,
+};
+use std::{
+    cell::{Cell, RefCell},
+    ffi::CStr,
+    os::raw::{c_char, c_int, c_uint, c_void},
+    ptr, slice,
+};
+
+pub mod sys;
+
+// 4MHz clock, 70224 cycles per frame.
+const FPS: f64 = 4_194_304.0 / 70_224.0;
+const SAMPLE_RATE: f64 = 44_100.0;
+
+// Core options.
+const MODEL: &[u8] = b"dmg_model\0";
+const PALETTE: &[u8] = b"dmg_palette\0";
+
+// Joypad buttons: libretro id, bit of the key (see `Joypad::keys`) & name.
+const KEYS: [(c_uint, u8, &[u8]); 8] =
+    [(DEVICE_ID_JOYPAD_A, Btn::A as u8, b"A\0"),
+     (DEVICE_ID_JOYPAD_B, Btn::B as u8, b"B\0"),
+     (DEVICE_ID_JOYPAD_SELECT, Btn::Select as u8, b"Select\0"),
+     (DEVICE_ID_JOYPAD_START, Btn::Start as u8, b"Start\0"),
+     (DEVICE_ID_JOYPAD_RIGHT, (Dir::Right as u8) << 4, b"Right\0"),
+     (DEVICE_ID_JOYPAD_LEFT, (Dir::Left as u8) << 4, b"Left\0"),
+     (DEVICE_ID_JOYPAD_UP, (Dir::Up as u8) << 4, b"Up\0"),
+     (DEVICE_ID_JOYPAD_DOWN, (Dir::Down as u8) << 4, b"Down\0")];
+
+type Emulator = GameBoy<Box<dyn Cartridge>, Screen, Stereo44100<i16>>;
+
+// Last frame, in XRGB8888.
+struct Screen {
+    pixels: Vec<u32>,
+    width: usize,
+    height: usize,
+}
+
+impl Screen {
+    fn new() -> Self {
+        Self { pixels: vec![0; SGB_WIDTH * SGB_HEIGHT],
+               width: LCD_WIDTH,
+               height: LCD_HEIGHT }
+    }
+
+    fn draw<'a>(&mut self, width: usize, height: usize, pixels: impl Iterator<Item = &'a Color>) {
+        self.width = width;
+        self.height = height;
+        for (pixel, [r, g, b]) in self.pixels.iter_mut().zip(pixels) {
+            *pixel = u32::from(*r) << 16 | u32::from(*g) << 8 | u32::from(*b);
+        }
+    }
+}
+
+impl Video for Screen {
+    fn draw_video(&mut self, pixels: &[[Color; LCD_WIDTH]; LCD_HEIGHT]) {
+        self.draw(LCD_WIDTH, LCD_HEIGHT, pixels.iter().flatten());
+    }
+
+    fn draw_sgb_video(&mut self, pixels: &[[Color; SGB_WIDTH]; SGB_HEIGHT]) {
+        self.draw(SGB_WIDTH, SGB_HEIGHT, pixels.iter().flatten());
+    }
+}
+
+struct Core {
+    rom: Vec<u8>,
+    dmg: Emulator,
+    // Pixel format accepted by the frontend.
+    format: c_int,
+    rgb565: Vec<u16>,
+    samples: Vec<i16>,
+    // Size of the last frame (changes when the SGB border is shown).
+    size: (usize, usize),
+}
+
+impl Core {
+    unsafe fn refresh_video(&mut self) {
+        let screen = self.dmg.mmu().ppu().video();
+        let (width, height) = (screen.width, screen.height);
+        if (width, height) != self.size {
+            self.size = (width, height);
+            environment(ENVIRONMENT_SET_GEOMETRY, &mut geometry(width, height));
+        }
+
+        let refresh = match CALLBACKS.0.video_refresh.get() {
+            Some(refresh) => refresh,
+            None => return,
+        };
+        let pixels = &screen.pixels[..width * height];
+        if self.format == PIXEL_FORMAT_RGB565 {
+            self.rgb565.clear();
+            self.rgb565
+                .extend(pixels.iter().map(|pixel| rgb565(*pixel)));
+            refresh(self.rgb565.as_ptr() as _,
+                    width as _,
+                    height as _,
+                    width * 2);
+        } else {
+            refresh(pixels.as_ptr() as _, width as _, height as _, width * 4);
+        }
+    }
+
+    unsafe fn send_audio(&mut self) {
+        let samples = self.dmg.mmu().apu().samples();
+        self.samples.clear();
+        for tap in samples.lock_taps() {
+            self.samples.extend_from_slice(&tap.mix);
+        }
+
+        let batch = match CALLBACKS.0.audio_sample_batch.get() {
+            Some(batch) => batch,
+            None => return,
+        };
+        let mut frames = &self.samples[..];
+        while !frames.is_empty() {
+            let written = batch(frames.as_ptr(), frames.len() / 2);
+            if written == 0 {
+                break;
+            }
+            frames = &frames[(written * 2).min(frames.len())..];
+        }
+    }
+}
+
+struct Callbacks {
+    environment: Cell<Option<EnvironmentFn>>,
+    video_refresh: Cell<Option<VideoRefreshFn>>,
+    audio_sample_batch: Cell<Option<AudioSampleBatchFn>>,
+    input_poll: Cell<Option<InputPollFn>>,
+    input_state: Cell<Option<InputStateFn>>,
+}
+
+// The libretro API is called from a single thread, so the state of the core is
+// kept in globals.
+struct Global<T>(T);
+
+unsafe impl<T> Sync for Global<T> {}
+
+static CALLBACKS: Global<Callbacks> = Global(Callbacks { environment: Cell::new(None),
+                                                         video_refresh: Cell::new(None),
+                                                         audio_sample_batch: Cell::new(None),
+                                                         input_poll: Cell::new(None),
+                                                         input_state: Cell::new(None) });
+
+static CORE: Global<RefCell<Option<Core>>> = Global(RefCell::new(None));
+
+// Run a closure on the loaded game, or return the default if there isn't one.
+fn with_core<R>(default: R, f: impl FnOnce(&mut Core) -> R) -> R {
+    match CORE.0.borrow_mut().as_mut() {
+        Some(core) => f(core),
+        None => default,
+    }
+}
+
+unsafe fn environment<T>(cmd: c_uint, data: &mut T) -> bool {
+    match CALLBACKS.0.environment.get() {
+        Some(environment) => environment(cmd, data as *mut T as *mut c_void),
+        None => false,
+    }
+}
+
+// Value of a core option.
+unsafe fn variable(key: &[u8]) -> Option<String> {
+    let mut variable = Variable { key: key.as_ptr() as _,
+                                  value: ptr::null() };
+    if environment(ENVIRONMENT_GET_VARIABLE, &mut variable) && !variable.value.is_null() {
+        CStr::from_ptr(variable.value).to_str()
+                                      .ok()
+                                      .map(String::from)
+    } else {
+        None
+    }
+}
+
+// Build the emulator with the model of the core options. If the cartridge can't
+// run on it, the model is picked from the cartridge header.
+unsafe fn build(rom: &[u8]) -> Option<Emulator> {
+    let model = match variable(MODEL).as_deref() {
+        Some("DMG") => Some(Model::Dmg),
+        Some("MGB") => Some(Model::Mgb),
+        Some("SGB") => Some(Model::Sgb),
+        Some("SGB2") => Some(Model::Sgb2),
+        Some("CGB") => Some(Model::Cgb),
+        Some("AGB") => Some(Model::Agb),
+        _ => None,
+    };
+    let build = |model: Option<Model>| {
+        let builder = Builder::default().audio::<Stereo44100<i16>>()
+                                        .video(Screen::new())
+                                        .cartridge(cartridge::from_bytes(rom).ok()?);
+        match model {
+            Some(model) => builder.model(model),
+            None => builder.auto_mode(),
+        }.try_build()
+         .ok()
+    };
+    let mut dmg = build(model).or_else(|| build(None))?;
+    set_palette(&mut dmg);
+    Some(dmg)
+}
+
+unsafe fn set_palette(dmg: &mut Emulator) {
+    let pal = match variable(PALETTE).as_deref() {
+        Some("Grayscale") => palette::GRAYSCALE,
+        Some("Muddy sand") => palette::MUDDYSAND,
+        _ => palette::DMG,
+    };
+    dmg.mmu_mut().ppu_mut().pal_mut().set_color_pal(pal);
+}
+
+fn geometry(width: usize, height: usize) -> GameGeometry {
+    GameGeometry { base_width: width as _,
+                   base_height: height as _,
+                   max_width: SGB_WIDTH as _,
+                   max_height: SGB_HEIGHT as _,
+                   aspect_ratio: width as f32 / height as f32 }
+}
+
+fn rgb565(pixel: u32) -> u16 {
+    let (r, g, b) = ((pixel >> 16) & 0xff, (pixel >> 8) & 0xff, pixel & 0xff);
+    ((r >> 3) << 11 | (g >> 2) << 5 | (b >> 3)) as u16
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(environment_fn: Option<EnvironmentFn>) {
+    CALLBACKS.0.environment.set(environment_fn);
+    let mut variables =
+        [Variable { key: MODEL.as_ptr() as _,
+                    value: b"Model (restart); Auto|DMG|MGB|SGB|SGB2|CGB|AGB\0".as_ptr() as _ },
+         Variable { key: PALETTE.as_ptr() as _,
+                    value: b"Palette (monochrome games); DMG|Grayscale|Muddy sand\0".as_ptr()
+                           as _ },
+         Variable { key: ptr::null(),
+                    value: ptr::null() }];
+    environment(ENVIRONMENT_SET_VARIABLES, &mut variables);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: Option<VideoRefreshFn>) {
+    CALLBACKS.0.video_refresh.set(video_refresh);
+}
+
+/// Unused, samples are sent with the batch callback.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_: Option<AudioSampleFn>) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: Option<AudioSampleBatchFn>) {
+    CALLBACKS.0.audio_sample_batch.set(audio_sample_batch);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: Option<InputPollFn>) {
+    CALLBACKS.0.input_poll.set(input_poll);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: Option<InputStateFn>) {
+    CALLBACKS.0.input_state.set(input_state);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.0.borrow_mut().take();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo { library_name: b"dmg\0".as_ptr() as _,
+                         library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as _,
+                         valid_extensions: b"gb|gbc\0".as_ptr() as _,
+                         need_fullpath: false,
+                         block_extract: false };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    *info = SystemAvInfo { geometry: geometry(LCD_WIDTH, LCD_HEIGHT),
+                           timing: SystemTiming { fps: FPS,
+                                                  sample_rate: SAMPLE_RATE } };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+
+    let mut format = PIXEL_FORMAT_XRGB8888;
+    if !environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format) {
+        format = PIXEL_FORMAT_RGB565;
+        environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut format);
+    }
+
+    let mut descriptors: Vec<_> =
+        KEYS.iter()
+            .map(|(id, _, name)| InputDescriptor { port: 0,
+                                                   device: DEVICE_JOYPAD,
+                                                   index: 0,
+                                                   id: *id,
+                                                   description: name.as_ptr() as _ })
+            .collect();
+    descriptors.push(InputDescriptor { port: 0,
+                                       device: 0,
+                                       index: 0,
+                                       id: 0,
+                                       description: ptr::null() });
+    environment(ENVIRONMENT_SET_INPUT_DESCRIPTORS, &mut descriptors[0]);
+
+    let dmg = match build(&rom) {
+        Some(dmg) => dmg,
+        None => return false,
+    };
+    *CORE.0.borrow_mut() = Some(Core { rom,
+                                       dmg,
+                                       format,
+                                       rgb565: Vec::new(),
+                                       samples: Vec::new(),
+                                       size: (LCD_WIDTH, LCD_HEIGHT) });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_type: c_uint,
+                                          _info: *const GameInfo,
+                                          _num: usize)
+                                          -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.0.borrow_mut().take();
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    REGION_NTSC
+}
+
+/// Restart the game, keeping the battery RAM & the cheats.
+#[no_mangle]
+pub unsafe extern "C" fn retro_reset() {
+    with_core((), |core| {
+        if let Some(mut dmg) = build(&core.rom) {
+            let mmu = dmg.mmu_mut();
+            mmu.cartridge_mut()
+               .ram_mut()
+               .copy_from_slice(core.dmg.mmu().cartridge().ram());
+            *mmu.cheats_mut() = core.dmg.mmu().cheats().clone();
+            core.dmg = dmg;
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_run() {
+    with_core((), |core| {
+        let mut updated = false;
+        if environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated) && updated {
+            set_palette(&mut core.dmg);
+        }
+
+        if let Some(input_poll) = CALLBACKS.0.input_poll.get() {
+            input_poll();
+        }
+        if let Some(input_state) = CALLBACKS.0.input_state.get() {
+            let keys = KEYS.iter()
+                           .filter(|(id, _, _)| input_state(0, DEVICE_JOYPAD, 0, *id) != 0)
+                           .fold(0, |keys, (_, key, _)| keys | key);
+            core.dmg.mmu_mut().joypad_mut().set_keys(0, keys);
+        }
+
+        core.dmg.emulate_frame();
+        core.refresh_video();
+        core.send_audio();
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(0, |core| core.dmg.save_state().len())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        let state = core.dmg.save_state();
+        if data.is_null() || state.len() > size {
+            return false;
+        }
+        slice::from_raw_parts_mut(data as *mut u8, state.len()).copy_from_slice(&state);
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    with_core(false, |core| {
+        !data.is_null()
+        && core.dmg
+               .load_state(slice::from_raw_parts(data as *const u8, size))
+               .is_ok()
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core((), |core| core.dmg.mmu_mut().cheats_mut().clear())
+}
+
+/// Add a cheat. Several codes can be joined with `+`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+    let code = CStr::from_ptr(code).to_string_lossy();
+    with_core((), |core| {
+        let cheats = core.dmg.mmu_mut().cheats_mut();
+        for code in code.split('+').filter(|code| !code.trim().is_empty()) {
+            if let Ok(id) = cheats.add(code) {
+                cheats.set_enabled(id, enabled);
+            }
+        }
+    })
+}
+
+/// Battery RAM of the cartridge (`MEMORY_SAVE_RAM`).
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_core(ptr::null_mut(), |core| {
+        let ram = core.dmg.mmu_mut().cartridge_mut().ram_mut();
+        if id == MEMORY_SAVE_RAM && !ram.is_empty() {
+            ram.as_mut_ptr() as _
+        } else {
+            ptr::null_mut()
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_core(0, |core| match id {
+        MEMORY_SAVE_RAM => core.dmg.mmu().cartridge().ram().len(),
+        _ => 0,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{sys::*, *};
+    use dmg_lib::Mode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static FRAMES: AtomicUsize = AtomicUsize::new(0);
+    static SAMPLES: AtomicUsize = AtomicUsize::new(0);
+
+    // Only accepts RGB565, and picks the DMG.
+    unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+        match cmd {
+            ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const c_int) == PIXEL_FORMAT_RGB565,
+            ENVIRONMENT_GET_VARIABLE => {
+                let variable = &mut *(data as *mut Variable);
+                if CStr::from_ptr(variable.key).to_bytes_with_nul() == MODEL {
+                    variable.value = b"DMG\0".as_ptr() as _;
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+
+    unsafe extern "C" fn video_refresh(_: *const c_void,
+                                       width: c_uint,
+                                       height: c_uint,
+                                       pitch: usize) {
+        assert_eq!((160, 144, 320), (width, height, pitch));
+        FRAMES.fetch_add(1, Ordering::SeqCst);
+    }
+
+    unsafe extern "C" fn audio_sample_batch(_: *const i16, frames: usize) -> usize {
+        SAMPLES.fetch_add(frames, Ordering::SeqCst);
+        frames
+    }
+
+    unsafe extern "C" fn input_poll() {}
+
+    unsafe extern "C" fn input_state(_: c_uint, _: c_uint, _: c_uint, id: c_uint) -> i16 {
+        (id == DEVICE_ID_JOYPAD_START) as i16
+    }
+
+    #[test]
+    fn core() {
+        let mut rom = vec![0u8; 0x8000];
+        // CGB support, MBC5+RAM+BATTERY, 8KB of RAM
+        rom[0x0143] = 0x80;
+        rom[0x0147] = 0x1b;
+        rom[0x0149] = 0x02;
+        // 0100: LD A,$0A
+        // 0102: LD ($0000),A
+        // 0105: LD HL,$A000
+        // 0108: INC A
+        // 0109: LD (HL),A
+        // 010A: INC L
+        // 010B: JR $0108
+        rom[0x0100..0x010d].copy_from_slice(&[0x3e, 0x0a, 0xea, 0x00, 0x00, 0x21, 0x00, 0xa0,
+                                              0x3c, 0x77, 0x2c, 0x18, 0xfb]);
+
+        unsafe {
+            retro_set_environment(Some(environment));
+            retro_set_video_refresh(Some(video_refresh));
+            retro_set_audio_sample_batch(Some(audio_sample_batch));
+            retro_set_input_poll(Some(input_poll));
+            retro_set_input_state(Some(input_state));
+            retro_init();
+
+            let game = GameInfo { path: ptr::null(),
+                                  data: rom.as_ptr() as _,
+                                  size: rom.len(),
+                                  meta: ptr::null() };
+            assert!(retro_load_game(&game));
+            for _ in 0..10 {
+                retro_run();
+            }
+            assert_eq!(10, FRAMES.load(Ordering::SeqCst));
+            // ~735 stereo frames per video frame
+            assert!((7300..7450).contains(&SAMPLES.load(Ordering::SeqCst)));
+            with_core((), |core| {
+                assert_eq!(Mode::GB, core.dmg.mmu().mode());
+                assert_eq!(Btn::Start as u8, core.dmg.mmu().joypad().keys(0));
+            });
+
+            assert_eq!(0x2000, retro_get_memory_size(MEMORY_SAVE_RAM));
+            let ram = retro_get_memory_data(MEMORY_SAVE_RAM) as *const u8;
+            let byte = *ram;
+            assert_ne!(0, byte);
+
+            let mut state = vec![0; retro_serialize_size()];
+            assert!(retro_serialize(state.as_mut_ptr() as _, state.len()));
+            retro_run();
+            assert!(retro_unserialize(state.as_ptr() as _, state.len()));
+            let mut again = vec![0; state.len()];
+            assert!(retro_serialize(again.as_mut_ptr() as _, again.len()));
+            assert_eq!(state, again);
+
+            // the battery RAM survives a reset
+            retro_reset();
+            let ram = retro_get_memory_data(MEMORY_SAVE_RAM) as *const u8;
+            assert_eq!(byte, *ram);
+
+            retro_unload_game();
+            assert!(retro_get_memory_data(MEMORY_SAVE_RAM).is_null());
+            retro_deinit();
+        }
+    }
+}
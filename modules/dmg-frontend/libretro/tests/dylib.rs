@@ -0,0 +1,93 @@
+use dmg_frontend_libretro::sys::*;
+use libloading::{Library, Symbol};
+use std::{
+    env,
+    os::raw::{c_uint, c_void},
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static FRAMES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe extern "C" fn environment(_: c_uint, _: *mut c_void) -> bool {
+    false
+}
+
+unsafe extern "C" fn video_refresh(_: *const c_void, _: c_uint, _: c_uint, _: usize) {
+    FRAMES.fetch_add(1, Ordering::SeqCst);
+}
+
+unsafe extern "C" fn audio_sample(_: i16, _: i16) {}
+
+unsafe extern "C" fn audio_sample_batch(_: *const i16, frames: usize) -> usize {
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(_: c_uint, _: c_uint, _: c_uint, _: c_uint) -> i16 {
+    0
+}
+
+// Load the core built by cargo (next to the test executable), the way a
+// libretro frontend does.
+#[test]
+fn dylib() {
+    let exe = env::current_exe().unwrap();
+    let path = exe.with_file_name(libloading::library_filename("dmg_frontend_libretro"));
+
+    let mut rom = vec![0u8; 0x8000];
+    // 0100: JR $0100
+    rom[0x0100..0x0102].copy_from_slice(&[0x18, 0xfe]);
+
+    unsafe {
+        let core = Library::new(&path).expect("Error loading core");
+        macro_rules! sym {
+            ($name:ident: $ty:ty) => {
+                let $name: Symbol<$ty> = core.get(concat!(stringify!($name), "\0").as_bytes())
+                                             .expect(stringify!($name));
+            };
+        }
+        sym!(retro_api_version: unsafe extern "C" fn() -> c_uint);
+        sym!(retro_set_environment: unsafe extern "C" fn(EnvironmentFn));
+        sym!(retro_set_video_refresh: unsafe extern "C" fn(VideoRefreshFn));
+        sym!(retro_set_audio_sample: unsafe extern "C" fn(AudioSampleFn));
+        sym!(retro_set_audio_sample_batch: unsafe extern "C" fn(AudioSampleBatchFn));
+        sym!(retro_set_input_poll: unsafe extern "C" fn(InputPollFn));
+        sym!(retro_set_input_state: unsafe extern "C" fn(InputStateFn));
+        sym!(retro_init: unsafe extern "C" fn());
+        sym!(retro_deinit: unsafe extern "C" fn());
+        sym!(retro_load_game: unsafe extern "C" fn(*const GameInfo) -> bool);
+        sym!(retro_unload_game: unsafe extern "C" fn());
+        sym!(retro_run: unsafe extern "C" fn());
+        sym!(retro_serialize_size: unsafe extern "C" fn() -> usize);
+        sym!(retro_serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool);
+        sym!(retro_unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool);
+
+        assert_eq!(API_VERSION, retro_api_version());
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample(audio_sample);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        let game = GameInfo { path: ptr::null(),
+                              data: rom.as_ptr() as _,
+                              size: rom.len(),
+                              meta: ptr::null() };
+        assert!(retro_load_game(&game));
+        for _ in 0..10 {
+            retro_run();
+        }
+        assert_eq!(10, FRAMES.load(Ordering::SeqCst));
+
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(retro_serialize(state.as_mut_ptr() as _, state.len()));
+        assert!(retro_unserialize(state.as_ptr() as _, state.len()));
+
+        retro_unload_game();
+        retro_deinit();
+    }
+}
@@ -1,27 +1,46 @@
+use dmg_backend_headless::HeadlessVideo;
 use dmg_backend_sdl2::ppu::SdlVideo;
 use dmg_lib::{
     apu::device::{Audio, Stereo44100},
     cartridge,
     cartridge::{Cartridge, Mbc1, Mbc3, Mbc5},
     joypad::{Btn, Dir, Joypad, Key},
+    movie::{Movie, Status},
     ppu::{palette::*, Video},
     Builder, GameBoy,
 };
+use dmg_script::Script;
 use sdl2::{
     event::{Event, WindowEvent},
     keyboard::Scancode,
     EventPump,
 };
 use std::{
-    thread,
+    env, fs, process, thread,
     time::{Duration, Instant},
 };
 
 const SCALE: u32 = 4;
 
+// F1 starts recording (stops if already recording), F2 plays the movie back, F3
+// stops the movie.
+const MOVIE_PATH: &str = "movie.dmv";
+
 static ROM: &[u8] = include_bytes!("../roms/tetris.gb");
 
+const USAGE: &str = "Usage: dmg-frontend-native [--script <file.rhai>]";
+
 fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let script = match &args[..] {
+        [] => None,
+        [flag, path] if flag == "--script" => Some(path.clone()),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(1)
+        }
+    };
+
     let sdl = sdl2::init().unwrap();
     let canvas = sdl.video()
                     .unwrap()
@@ -33,32 +52,75 @@ fn main() {
                     .build()
                     .expect("Error creating SDL canvas");
 
-    let mut emulator = Builder::default().video(SdlVideo::new(canvas))
-                                         .cartridge(cartridge::from_bytes(ROM).unwrap())
-                                         .gb_mode()
-                                         .build();
+    let builder = Builder::default().cartridge(cartridge::from_bytes(ROM).unwrap());
+    let mut pump = sdl.event_pump().unwrap();
+
+    match script {
+        // the script takes screenshots of the frames shown in the window
+        Some(path) => {
+            let emulator = build(builder.video(HeadlessVideo::wrap(SdlVideo::new(canvas))));
+            let mut script = Script::from_file(emulator, path).unwrap_or_else(|err| {
+                                                                  eprintln!("{}", err);
+                                                                  process::exit(1)
+                                                              });
+            run(&mut pump, |pump| {
+                if handle_input(pump, &mut *script.emulator_mut()) {
+                    return false;
+                }
+                if let Err(err) = script.emulate_frame() {
+                    eprintln!("{}", err);
+                    return false;
+                }
+                script.emulator_mut()
+                      .mmu_mut()
+                      .ppu_mut()
+                      .video_mut()
+                      .inner_mut()
+                      .canvas_mut()
+                      .present();
+                !script.finished()
+            });
+        }
+        None => {
+            let mut emulator = build(builder.video(SdlVideo::new(canvas)));
+            run(&mut pump, |pump| {
+                if handle_input(pump, &mut emulator) {
+                    return false;
+                }
+                emulator.emulate_frame();
+                emulator.mmu_mut()
+                        .ppu_mut()
+                        .video_mut()
+                        .canvas_mut()
+                        .present();
+                true
+            });
+        }
+    }
+}
+
+fn build<C: Cartridge, V: Video, D: Audio>(builder: Builder<C, V, D>) -> GameBoy<C, V, D> {
+    let mut emulator = builder.try_build().unwrap_or_else(|err| {
+                                              eprintln!("{}", err);
+                                              process::exit(1)
+                                          });
 
     // set-up custom 4 color palette
     emulator.mmu_mut().ppu_mut().pal_mut().set_color_pal(DMG);
+    emulator
+}
 
-    let mut pump = sdl.event_pump().unwrap();
-
+// Run `frame` at 60 frames per second, until it returns false.
+fn run(pump: &mut EventPump, mut frame: impl FnMut(&mut EventPump) -> bool) {
     let mut carry = Duration::new(0, 0);
 
     loop {
         let time = Instant::now();
 
-        if handle_input(&mut pump, &mut emulator) {
+        if !frame(pump) {
             break;
         }
 
-        emulator.emulate_frame();
-        emulator.mmu_mut()
-                .ppu_mut()
-                .video_mut()
-                .canvas_mut()
-                .present();
-
         let elapsed = time.elapsed() + carry;
         let sleep = Duration::new(0, 1_000_000_000 / 60);
         if elapsed < sleep {
@@ -73,21 +135,35 @@ fn main() {
 fn handle_input(pump: &mut EventPump,
                 dmg: &mut GameBoy<impl Cartridge, impl Video, impl Audio>)
                 -> bool {
-    let joypad = dmg.mmu_mut().joypad_mut();
     for event in pump.poll_iter() {
         match event {
             Event::Window { win_event: WindowEvent::Close,
                             .. } => return true,
             Event::KeyDown { scancode: Some(Scancode::S),
                              .. } => unimplemented!("screenshot"),
+            Event::KeyDown { scancode: Some(Scancode::F1),
+                             repeat: false,
+                             .. } => {
+                if dmg.movie_status().is_some() {
+                    stop_movie(dmg);
+                } else {
+                    dmg.start_recording(ROM, true);
+                }
+            }
+            Event::KeyDown { scancode: Some(Scancode::F2),
+                             repeat: false,
+                             .. } => play_movie(dmg),
+            Event::KeyDown { scancode: Some(Scancode::F3),
+                             repeat: false,
+                             .. } => stop_movie(dmg),
             Event::KeyDown { scancode: Some(s), .. } => {
                 if let Some(key) = map_scancode(s) {
-                    joypad.press(key)
+                    dmg.mmu_mut().joypad_mut().press(key)
                 }
             }
             Event::KeyUp { scancode: Some(s), .. } => {
                 if let Some(key) = map_scancode(s) {
-                    joypad.release(key)
+                    dmg.mmu_mut().joypad_mut().release(key)
                 }
             }
             _ => {}
@@ -96,6 +172,29 @@ fn handle_input(pump: &mut EventPump,
     false
 }
 
+fn play_movie(dmg: &mut GameBoy<impl Cartridge, impl Video, impl Audio>) {
+    let movie = fs::read(MOVIE_PATH).map_err(|err| err.to_string())
+                                    .and_then(|data| {
+                                        Movie::from_bytes(&data).map_err(|err| err.to_string())
+                                    })
+                                    .and_then(|movie| {
+                                        dmg.play_movie(ROM, movie).map_err(|err| err.to_string())
+                                    });
+    if let Err(err) = movie {
+        eprintln!("Error playing movie: {}", err);
+    }
+}
+
+// Stop the movie. Recordings are written to MOVIE_PATH.
+fn stop_movie(dmg: &mut GameBoy<impl Cartridge, impl Video, impl Audio>) {
+    let recording = matches!(dmg.movie_status(), Some(Status::Recording(_)));
+    if let Some(movie) = dmg.stop_movie().filter(|_| recording) {
+        if let Err(err) = fs::write(MOVIE_PATH, movie.to_bytes()) {
+            eprintln!("Error writing movie: {}", err);
+        }
+    }
+}
+
 fn map_scancode(scancode: Scancode) -> Option<Key> {
     match scancode {
         Scancode::Z => Some(Key::Btn(Btn::A)),
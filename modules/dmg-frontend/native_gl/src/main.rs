@@ -1,9 +1,14 @@
 use dmg_backend_gl::ppu::{shader::lcd::Lcd, GLVideo};
 use dmg_lib::{
-    apu::device::Audio,
+    apu::{device::Audio, Channel},
     cartridge,
     cartridge::Cartridge,
+    disasm,
     joypad::{Btn, Dir, Key},
+    movie::{Movie, Status},
+    profile::Profiler,
+    search::{Compare, Kind, Search},
+    symbols::{self, Symbols},
     ppu::{
         palette::{DMG, GRAYSCALE},
         reg::{TileDataAddr, TileMapAddr},
@@ -21,10 +26,17 @@ use sdl2::{
     EventPump,
 };
 use std::{
-    ptr, thread,
+    fs, ptr, thread,
     time::{Duration, Instant},
 };
 
+static ROM: &[u8] = include_bytes!("../../native/roms/tetris.gb");
+
+const MOVIE_PATH: &str = "movie.dmv";
+
+// RGBDS symbols of the ROM, if the file exists.
+const SYM_PATH: &str = "tetris.sym";
+
 struct PPUUi {
     display: bool,
     palette: bool,
@@ -36,6 +48,13 @@ struct PPUUi {
 
 struct Cpu {
     registers: bool,
+    disassembly: bool,
+    call_stack: bool,
+    ram_search: bool,
+}
+
+struct Apu {
+    channels: bool,
 }
 
 fn main() {
@@ -46,7 +65,12 @@ fn main() {
                           window: false,
                           shader: false };
 
-    let mut cpu = Cpu { registers: false };
+    let mut cpu = Cpu { registers: false,
+                        disassembly: false,
+                        call_stack: false,
+                        ram_search: false };
+
+    let mut apu = Apu { channels: false };
 
     let sdl = sdl2::init().expect("Error initializing SDL");
     let video = sdl.video().expect("Error initializing video");
@@ -83,12 +107,19 @@ fn main() {
     let mut imgui_sdl = ImguiSdl2::new(&mut imgui, &window);
 
     let mut emulator = Builder::default()
-        .cartridge(cartridge::from_bytes(include_bytes!("../../native/roms/tetris.gb")).expect("Error creating cartridge"))
+        .cartridge(cartridge::from_bytes(ROM).expect("Error creating cartridge"))
         .video(GLVideo::new(Lcd::default()))
-        .gb_mode()
         .build();
     emulator.mmu_mut().ppu_mut().pal_mut().set_color_pal(DMG);
 
+    let mut symbols = Symbols::new();
+    if let Ok(text) = fs::read_to_string(SYM_PATH) {
+        symbols.parse_sym(&text).expect("Error parsing symbol file");
+    }
+
+    let mut search = Search::new(emulator.mmu(), Kind::U8);
+    let mut search_value = 0;
+
     let mut event_pump = sdl.event_pump().expect("Error creating event pump");
     loop {
         let time = Instant::now();
@@ -107,9 +138,14 @@ fn main() {
 
         imgui_sdl.prepare_frame(imgui.io_mut(), &window, &event_pump.mouse_state());
         let ui = imgui.frame();
+        let movie_status = emulator.movie_status();
+        let (mut record, mut play, mut stop) = (false, false, false);
         ui.main_menu_bar(|| {
               ui.menu(imgui::im_str!("cpu"), true, || {
                     ui.checkbox(imgui::im_str!("Registers"), &mut cpu.registers);
+                    ui.checkbox(imgui::im_str!("Disassembly"), &mut cpu.disassembly);
+                    ui.checkbox(imgui::im_str!("Call stack"), &mut cpu.call_stack);
+                    ui.checkbox(imgui::im_str!("RAM search"), &mut cpu.ram_search);
                 });
               ui.menu(imgui::im_str!("ppu"), true, || {
                     ui.checkbox(imgui::im_str!("Display"), &mut ppu.display);
@@ -119,7 +155,45 @@ fn main() {
                     ui.checkbox(imgui::im_str!("Window"), &mut ppu.window);
                     ui.checkbox(imgui::im_str!("Shader"), &mut ppu.shader);
                 });
+              ui.menu(imgui::im_str!("apu"), true, || {
+                    ui.checkbox(imgui::im_str!("Channels"), &mut apu.channels);
+                });
+              ui.menu(imgui::im_str!("movie"), true, || {
+                    let idle = movie_status.is_none();
+                    record = imgui::MenuItem::new(imgui::im_str!("Record")).enabled(idle).build(&ui);
+                    play = imgui::MenuItem::new(imgui::im_str!("Play")).enabled(idle).build(&ui);
+                    stop = imgui::MenuItem::new(imgui::im_str!("Stop")).enabled(!idle).build(&ui);
+                    match movie_status {
+                        Some(Status::Recording(frame)) => ui.text(format!("Recording (frame {})", frame)),
+                        Some(Status::Playing(frame)) => ui.text(format!("Playing (frame {})", frame)),
+                        Some(Status::Finished) => ui.text("Finished"),
+                        Some(Status::Desync(frame)) => ui.text(format!("Desync at frame {}", frame)),
+                        None => {}
+                    }
+                });
           });
+        if record {
+            emulator.start_recording(ROM, true);
+        }
+        if play {
+            match fs::read(MOVIE_PATH).map(|data| Movie::from_bytes(&data)) {
+                Ok(Ok(movie)) => {
+                    if let Err(err) = emulator.play_movie(ROM, movie) {
+                        eprintln!("Error playing movie: {}", err);
+                    }
+                }
+                Ok(Err(err)) => eprintln!("Error loading movie: {}", err),
+                Err(err) => eprintln!("Error reading `{}`: {}", MOVIE_PATH, err),
+            }
+        }
+        if stop {
+            let recording = matches!(movie_status, Some(Status::Recording(_)));
+            if let Some(movie) = emulator.stop_movie().filter(|_| recording) {
+                if let Err(err) = fs::write(MOVIE_PATH, movie.to_bytes()) {
+                    eprintln!("Error writing `{}`: {}", MOVIE_PATH, err);
+                }
+            }
+        }
         if cpu.registers {
             #[rustfmt::skip]
             imgui::Window::new(imgui::im_str!("Registers"))
@@ -134,6 +208,130 @@ fn main() {
                     imgui::InputInt::new(&ui, imgui::im_str!("SP"), &mut (emulator.cpu().reg().sp as _)).chars_hexadecimal(true).build();
                 });
         }
+        if cpu.disassembly {
+            let pc = emulator.cpu().reg().pc;
+            #[rustfmt::skip]
+            imgui::Window::new(imgui::im_str!("Disassembly"))
+                .size([240.0, 320.0], imgui::Condition::FirstUseEver)
+                .build(&ui, || {
+                    let rom_bank = emulator.mmu().cartridge().rom_bank();
+                    let wram_bank = symbols::bank(emulator.mmu(), 0xd000);
+                    for (addr, instr) in disasm::iter(&emulator.mmu().peeker(), pc).take(32) {
+                        let bank = symbols::bank(emulator.mmu(), addr);
+                        if let Some(label) = symbols.label(bank, addr) {
+                            ui.text(format!("{}:", label));
+                        }
+                        ui.text(format!("{:04X}  {}", addr, instr.with_symbols(&symbols, rom_bank, wram_bank)));
+                    }
+                });
+        }
+        // the call stack is tracked by the profiler, only while the window is open
+        if cpu.call_stack != emulator.mmu().profiler().is_some() {
+            if cpu.call_stack {
+                emulator.mmu_mut().start_profile(Profiler::new());
+            } else {
+                emulator.mmu_mut().stop_profile();
+            }
+        }
+        if let Some(profiler) = emulator.mmu().profiler().filter(|_| cpu.call_stack) {
+            #[rustfmt::skip]
+            imgui::Window::new(imgui::im_str!("Call stack"))
+                .size([240.0, 320.0], imgui::Condition::FirstUseEver)
+                .build(&ui, || {
+                    for (bank, addr) in profiler.call_stack().into_iter().rev() {
+                        ui.text(format!("{:02X}:{:04X}  {}", bank, addr, symbols.format(bank, addr)));
+                    }
+                });
+        }
+        if cpu.ram_search {
+            #[rustfmt::skip]
+            imgui::Window::new(imgui::im_str!("RAM search"))
+                .size([240.0, 320.0], imgui::Condition::FirstUseEver)
+                .build(&ui, || {
+                    let names: Vec<_> = Kind::ALL.iter().map(|kind| imgui::ImString::new(kind.to_string())).collect();
+                    let names: Vec<_> = names.iter().map(|name| name.as_ref()).collect();
+                    let mut kind = Kind::ALL.iter().position(|kind| *kind == search.kind()).unwrap_or(0);
+                    if imgui::ComboBox::new(imgui::im_str!("Kind")).build_simple_string(&ui, &mut kind, &names) {
+                        search = Search::new(emulator.mmu(), Kind::ALL[kind]);
+                    }
+                    if ui.button(imgui::im_str!("Reset"), [0.0, 0.0]) {
+                        search = Search::new(emulator.mmu(), search.kind());
+                    }
+                    let mut compare = None;
+                    let buttons = [(imgui::im_str!("Equal"), Compare::Equal),
+                                   (imgui::im_str!("Changed"), Compare::Changed),
+                                   (imgui::im_str!("Increased"), Compare::Increased),
+                                   (imgui::im_str!("Decreased"), Compare::Decreased)];
+                    for (i, (label, cmp)) in buttons.iter().enumerate() {
+                        if i > 0 {
+                            ui.same_line(0.0);
+                        }
+                        if ui.button(label, [0.0, 0.0]) {
+                            compare = Some(*cmp);
+                        }
+                    }
+                    imgui::InputInt::new(&ui, imgui::im_str!("##value"), &mut search_value).build();
+                    ui.same_line(0.0);
+                    if ui.button(imgui::im_str!("Equal to"), [0.0, 0.0]) {
+                        compare = Some(Compare::Value(search_value.max(0) as u32));
+                    }
+                    if let Some(compare) = compare {
+                        search.filter(emulator.mmu(), compare);
+                    }
+                    ui.separator();
+                    ui.text(format!("{} candidates", search.len()));
+                    for &(bank, addr) in search.candidates().iter().take(32) {
+                        let value = search.value((bank, addr)).unwrap_or(0);
+                        ui.text(format!("{:02X}:{:04X}  {}", bank, addr, value));
+                    }
+                });
+        }
+        if apu.channels {
+            #[rustfmt::skip]
+            imgui::Window::new(imgui::im_str!("Channels"))
+                .always_auto_resize(true)
+                .resizable(false)
+                .build(&ui, || {
+                    let mut inner = emulator.mmu().apu().lock();
+                    for (i, ch) in Channel::ALL.iter().copied().enumerate() {
+                        let state = inner.channel_state(ch);
+                        if i > 0 {
+                            ui.separator();
+                        }
+                        ui.text(format!("{:?} (ON: {}, DAC: {})", ch, state.enabled, state.dac));
+                        let mut muted = inner.is_muted(ch);
+                        if ui.checkbox(&imgui::im_str!("Mute##{}", i), &mut muted) {
+                            inner.set_muted(ch, muted);
+                        }
+                        ui.same_line(0.0);
+                        let mut solo = inner.is_solo(ch);
+                        if ui.checkbox(&imgui::im_str!("Solo##{}", i), &mut solo) {
+                            inner.set_solo(ch, solo);
+                        }
+                        ui.text(format!("Frequency: {:.2} Hz", state.frequency));
+                        ui.text(format!("Volume: {}", state.volume));
+                        ui.text(format!("Length: {} (enabled: {})", state.length, state.length_enabled));
+                        if let Some(duty) = state.duty {
+                            ui.text(format!("Duty: {}%", [12.5, 25.0, 50.0, 75.0][duty as usize]));
+                        }
+                        if let Some(env) = state.envelope {
+                            let dir = if env.increase { "+" } else { "-" };
+                            ui.text(format!("Envelope: {} (period: {})", dir, env.period));
+                        }
+                        if let Some(sweep) = state.sweep {
+                            let dir = if sweep.negate { "-" } else { "+" };
+                            ui.text(format!("Sweep: {} (period: {}, shift: {}, shadow: {:03x})", dir, sweep.period, sweep.shift, sweep.shadow));
+                        }
+                        if let Some(width) = state.lfsr_width {
+                            ui.text(format!("LFSR: {} bits", width));
+                        }
+                        if let Some(wave_ram) = state.wave_ram {
+                            let samples: Vec<f32> = wave_ram.iter().flat_map(|b| vec![(b >> 4) as f32, (b & 0xf) as f32]).collect();
+                            ui.plot_lines(&imgui::im_str!("Wave RAM##{}", i), &samples).scale_min(0.0).scale_max(15.0).graph_size([128.0, 32.0]).build();
+                        }
+                    }
+                });
+        }
         if ppu.display {
             imgui::Window::new(imgui::im_str!("Display")).always_auto_resize(true)
                                                          .resizable(false)
@@ -1,23 +1,76 @@
-use crate::{apu::samples::SamplesMutex, device::Device, CLOCK};
+use crate::{
+    apu::{
+        channel::{ChannelState, Noise, Square, Wave},
+        samples::{Frame, SamplesMutex},
+    },
+    clock::Clock,
+    device::Device,
+    state::{Error, Reader, State, Writer},
+    CLOCK,
+};
 use device::Audio;
 use std::{
+    collections::VecDeque,
     marker::PhantomData,
     sync::{Arc, Mutex, MutexGuard},
 };
 
+pub mod channel;
 pub mod device;
 pub mod samples;
 
+/// APU sound channels.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Channel {
+    /// Sound Channel 1 - Tone & Sweep
+    Square1 = 0x1,
+    /// Sound Channel 2 - Tone
+    Square2 = 0x2,
+    /// Sound Channel 3 - Wave Output
+    Wave = 0x4,
+    /// Sound Channel 4 - Noise
+    Noise = 0x8,
+}
+
+impl Channel {
+    /// The four channels, in register order.
+    pub const ALL: [Channel; 4] = [Channel::Square1,
+                                   Channel::Square2,
+                                   Channel::Wave,
+                                   Channel::Noise];
+}
+
 pub struct ApuInner<D: Audio> {
     _phantom: PhantomData<D>,
 
+    // Output frames generated at the sample rate of the audio device, waiting to
+    // be consumed. Up to one second worth of frames is kept. Samples and taps
+    // are queued separately, so both can be consumed at once (i.e. playing and
+    // recording the output).
     sample: u64,
+    pub(crate) frames: VecDeque<Frame>,
+    pub(crate) taps: VecDeque<Frame>,
 
     pub(crate) ch0: Option<f64>,
     pub(crate) ch1: Option<f64>,
     pub(crate) ch2: Option<f64>,
     pub(crate) ch3: Option<f64>,
 
+    // Channels muted and soloed by the user (one bit per channel, see Channel). These masks are
+    // applied on top of NR51, so games can't override them.
+    mute: u8,
+    solo: u8,
+
+    // Internal channel state
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    // Frame sequencer (512Hz). Clocks length counters, envelopes and the sweep unit.
+    frame_seq: Clock,
+    frame_step: u8,
+
     // Sound Channel 1 - Tone & Sweep
     nr10: u8,
     nr11: u8,
@@ -63,7 +116,228 @@ pub struct ApuInner<D: Audio> {
 
 // FIXME don't inline so much (channels 1 & 2 share some behaviour)
 impl<D: Audio> ApuInner<D> {
-    pub fn step(&mut self, cycles: u64) {}
+    pub fn step(&mut self, cycles: u64) {
+        if self.nr52 & 0x80 != 0 {
+            self.step_channels(cycles);
+        }
+        self.step_samples(cycles);
+    }
+
+    fn step_channels(&mut self, cycles: u64) {
+        for _ in 0..self.frame_seq.step(cycles) {
+            self.clock_frame_sequencer();
+        }
+
+        let freq1 = channel::frequency(self.nr13, self.nr14);
+        let freq2 = channel::frequency(self.nr23, self.nr24);
+        let freq3 = channel::frequency(self.nr33, self.nr34);
+        self.square1.step(cycles, freq1);
+        self.square2.step(cycles, freq2);
+        self.wave.step(cycles, freq3);
+        self.noise.step(cycles, self.nr43);
+
+        self.ch0 = if self.square1.enabled {
+            Some(self.square1.output(self.nr11))
+        } else {
+            None
+        };
+        self.ch1 = if self.square2.enabled {
+            Some(self.square2.output(self.nr21))
+        } else {
+            None
+        };
+        self.ch2 = if self.wave.enabled {
+            Some(self.wave.output(self.nr32, &self.wave_ram))
+        } else {
+            None
+        };
+        self.ch3 = if self.noise.enabled {
+            Some(self.noise.output())
+        } else {
+            None
+        };
+    }
+
+    // Generate output frames at the sample rate of the device.
+    fn step_samples(&mut self, cycles: u64) {
+        let rate = D::sample_rate();
+        if rate == 0 {
+            return;
+        }
+        self.sample += cycles * rate;
+        while self.sample >= CLOCK {
+            self.sample -= CLOCK;
+            let frame = Frame { mix: self.mix(),
+                                channels: [self.ch0, self.ch1, self.ch2, self.ch3] };
+            for queue in [&mut self.frames, &mut self.taps].iter_mut() {
+                if queue.len() as u64 >= rate {
+                    queue.pop_front();
+                }
+                queue.push_back(frame);
+            }
+        }
+    }
+
+    // Step  Length Ctr  Vol Env     Sweep
+    // ---------------------------------------
+    // 0     Clock       -           -
+    // 1     -           -           -
+    // 2     Clock       -           Clock
+    // 3     -           -           -
+    // 4     Clock       -           -
+    // 5     -           -           -
+    // 6     Clock       -           Clock
+    // 7     -           Clock       -
+    fn clock_frame_sequencer(&mut self) {
+        let step = self.frame_step;
+        self.frame_step = (self.frame_step + 1) & 0x7;
+
+        if step & 0x1 == 0 {
+            self.square1.clock_length(self.nr14);
+            self.square2.clock_length(self.nr24);
+            self.wave.clock_length(self.nr34);
+            self.noise.clock_length(self.nr44);
+        }
+        if step == 2 || step == 6 {
+            if let Some(freq) = self.square1.clock_sweep(self.nr10) {
+                self.nr13 = freq as u8;
+                self.nr14 = (self.nr14 & 0xf8) | (freq >> 8) as u8 & 0x7;
+            }
+        }
+        if step == 7 {
+            self.square1.clock_envelope(self.nr12);
+            self.square2.clock_envelope(self.nr22);
+            self.noise.clock_envelope(self.nr42);
+        }
+    }
+
+    /// Returns a snapshot of the internal state of a channel.
+    pub fn channel_state(&self, ch: Channel) -> ChannelState {
+        match ch {
+            Channel::Square1 => {
+                let regs = [self.nr10, self.nr11, self.nr12, self.nr13, self.nr14];
+                self.square1.state(ch, regs)
+            }
+            Channel::Square2 => {
+                let regs = [0, self.nr21, self.nr22, self.nr23, self.nr24];
+                self.square2.state(ch, regs)
+            }
+            Channel::Wave => {
+                let regs = [self.nr30, self.nr31, self.nr32, self.nr33, self.nr34];
+                self.wave.state(regs, &self.wave_ram)
+            }
+            Channel::Noise => {
+                let regs = [0, self.nr41, self.nr42, self.nr43, self.nr44];
+                self.noise.state(regs)
+            }
+        }
+    }
+
+    // NR52 channel ON flags
+    fn status(&self) -> u8 {
+        let mut status = 0;
+        if self.square1.enabled {
+            status |= Channel::Square1 as u8;
+        }
+        if self.square2.enabled {
+            status |= Channel::Square2 as u8;
+        }
+        if self.wave.enabled {
+            status |= Channel::Wave as u8;
+        }
+        if self.noise.enabled {
+            status |= Channel::Noise as u8;
+        }
+        status
+    }
+
+    /// Mute or unmute a channel.
+    pub fn set_muted(&mut self, ch: Channel, muted: bool) {
+        if muted {
+            self.mute |= ch as u8;
+        } else {
+            self.mute &= !(ch as u8);
+        }
+    }
+
+    /// Returns true if the channel has been muted with [`ApuInner::set_muted`].
+    ///
+    /// [`ApuInner::set_muted`]: #
+    pub fn is_muted(&self, ch: Channel) -> bool {
+        self.mute & (ch as u8) != 0
+    }
+
+    /// Add or remove a channel from the solo set. While there is at least one
+    /// soloed channel, the rest of the channels are not mixed.
+    pub fn set_solo(&mut self, ch: Channel, solo: bool) {
+        if solo {
+            self.solo |= ch as u8;
+        } else {
+            self.solo &= !(ch as u8);
+        }
+    }
+
+    /// Returns true if the channel is in the solo set.
+    pub fn is_solo(&self, ch: Channel) -> bool {
+        self.solo & (ch as u8) != 0
+    }
+
+    /// Returns true if the channel makes it to the mixed output (regardless of
+    /// NR51 panning), after applying the mute and solo masks.
+    pub fn is_audible(&self, ch: Channel) -> bool {
+        self.channel_mask() & (ch as u8) != 0
+    }
+
+    /// Returns the current output of a channel, or `None` if the channel is
+    /// not producing sound. Taps are not affected by NR51, mute or solo.
+    pub fn tap(&self, ch: Channel) -> Option<f64> {
+        match ch {
+            Channel::Square1 => self.ch0,
+            Channel::Square2 => self.ch1,
+            Channel::Wave => self.ch2,
+            Channel::Noise => self.ch3,
+        }
+    }
+
+    // Mask of the channels that are mixed (one bit per channel).
+    fn channel_mask(&self) -> u8 {
+        if self.solo != 0 {
+            self.solo & 0xf
+        } else {
+            !self.mute & 0xf
+        }
+    }
+
+    // Mix the output of the four channels into the two output terminals (SO1,
+    // SO2). Output values are within the [-1.0, 1.0] range.
+    pub(crate) fn mix(&self) -> [f64; 2] {
+        let mut so: [f64; 2] = [0.0; 2];
+        let mut count: [u32; 2] = [0; 2];
+
+        let mask = self.channel_mask();
+        let nr51 = self.nr51 & (mask | mask << 4);
+        for (ch, channel) in Channel::ALL.iter().copied().enumerate() {
+            let so1_bit = 1 << (ch as u8);
+            let so2_bit = 1 << (4 + ch as u8);
+            let sample = self.tap(channel).unwrap_or(0.0);
+            if nr51 & so1_bit != 0 {
+                so[0] += sample;
+                count[0] += 1;
+            }
+            if nr51 & so2_bit != 0 {
+                so[1] += sample;
+                count[1] += 1;
+            }
+        }
+
+        if count[0] > 0 {
+            so[0] /= count[0] as f64;
+        }
+        if count[1] > 0 {
+            so[1] /= count[1] as f64;
+        }
+        so
+    }
 
     // clear APU registers except NR52's high bit
     fn power_off(&mut self) {
@@ -92,6 +366,17 @@ impl<D: Audio> ApuInner<D> {
         self.nr50 = 0;
         self.nr51 = 0;
         self.nr52 &= 0x80;
+
+        self.square1 = Square::default();
+        self.square2 = Square::default();
+        self.wave = Wave::default();
+        self.noise = Noise::default();
+        self.frame_step = 0;
+
+        self.ch0 = None;
+        self.ch1 = None;
+        self.ch2 = None;
+        self.ch3 = None;
     }
 }
 
@@ -103,12 +388,24 @@ impl<D: Audio> Default for Apu<D> {
     fn default() -> Self {
         let inner = ApuInner { _phantom: PhantomData,
                                sample: 0,
+                               frames: VecDeque::new(),
+                               taps: VecDeque::new(),
 
                                ch0: None,
                                ch1: None,
                                ch2: None,
                                ch3: None,
 
+                               mute: 0,
+                               solo: 0,
+
+                               square1: Square::default(),
+                               square2: Square::default(),
+                               wave: Wave::default(),
+                               noise: Noise::default(),
+                               frame_seq: Clock::new(CLOCK, 512),
+                               frame_step: 0,
+
                                nr10: 0,
                                nr11: 0,
                                nr12: 0,
@@ -141,10 +438,21 @@ impl<D: Audio> Default for Apu<D> {
 
 impl<D: Audio> Apu<D> {
     /// Return audio samples iterator.
+    ///
+    /// Samples are generated at the rate of the audio device (see
+    /// [`Audio::sample_rate`]) as the emulation runs, and buffered until they
+    /// are consumed.
+    ///
+    /// [`Audio::sample_rate`]: #
     pub fn samples(&self) -> SamplesMutex<D> {
         SamplesMutex::new(&self.inner)
     }
 
+    /// Returns a snapshot of the internal state of a channel.
+    pub fn channel_state(&self, ch: Channel) -> ChannelState {
+        self.lock().channel_state(ch)
+    }
+
     pub fn lock(&self) -> MutexGuard<ApuInner<D>> {
         match self.inner.lock() {
             Ok(guard) => guard,
@@ -153,6 +461,61 @@ impl<D: Audio> Apu<D> {
     }
 }
 
+// Buffered output frames aren't part of the state. The channel outputs are
+// recomputed on the next step.
+impl<D: Audio> State for Apu<D> {
+    fn save_state(&self, state: &mut Writer) {
+        let apu = self.lock();
+        apu.square1.save_state(state);
+        apu.square2.save_state(state);
+        apu.wave.save_state(state);
+        apu.noise.save_state(state);
+        apu.frame_seq.save_state(state);
+        state.u8(apu.frame_step);
+        state.bytes(&[apu.nr10, apu.nr11, apu.nr12, apu.nr13, apu.nr14, apu.nr21, apu.nr22,
+                      apu.nr23, apu.nr24, apu.nr30, apu.nr31, apu.nr32, apu.nr33, apu.nr34,
+                      apu.nr41, apu.nr42, apu.nr43, apu.nr44, apu.nr50, apu.nr51, apu.nr52]);
+        state.bytes(&apu.wave_ram);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        let mut apu = self.lock();
+        apu.square1.load_state(state)?;
+        apu.square2.load_state(state)?;
+        apu.wave.load_state(state)?;
+        apu.noise.load_state(state)?;
+        apu.frame_seq.load_state(state)?;
+        apu.frame_step = state.u8()? & 0x7;
+        let mut data = [0; 21];
+        state.bytes(&mut data)?;
+        let [nr10, nr11, nr12, nr13, nr14, nr21, nr22, nr23, nr24, nr30, nr31, nr32, nr33, nr34, nr41, nr42, nr43, nr44, nr50, nr51, nr52] =
+            data;
+        apu.nr10 = nr10;
+        apu.nr11 = nr11;
+        apu.nr12 = nr12;
+        apu.nr13 = nr13;
+        apu.nr14 = nr14;
+        apu.nr21 = nr21;
+        apu.nr22 = nr22;
+        apu.nr23 = nr23;
+        apu.nr24 = nr24;
+        apu.nr30 = nr30;
+        apu.nr31 = nr31;
+        apu.nr32 = nr32;
+        apu.nr33 = nr33;
+        apu.nr34 = nr34;
+        apu.nr41 = nr41;
+        apu.nr42 = nr42;
+        apu.nr43 = nr43;
+        apu.nr44 = nr44;
+        apu.nr50 = nr50;
+        apu.nr51 = nr51;
+        apu.nr52 = nr52;
+        state.bytes(&mut apu.wave_ram)?;
+        Ok(())
+    }
+}
+
 //
 // - APU registers always have some bits set when read back.
 // - Wave memory can be read back freely.
@@ -193,9 +556,8 @@ impl<D: Audio> Device for Apu<D> {
             0xff24 => apu.nr50,
             0xff25 => apu.nr51,
 
-            // TODO
-            0xff26 => apu.nr52 & 0x80,
-            0xff27..=0xff2f => panic!(), // unused
+            0xff26 => apu.nr52 & 0x80 | apu.status(),
+            0xff27..=0xff2f => 0xff, // unused
             _ => panic!(),
         }
     }
@@ -210,45 +572,93 @@ impl<D: Audio> Device for Apu<D> {
             match addr {
                 // Channel 1 sweep
                 0xff10 => apu.nr10 = data,
-                0xff11 => apu.nr11 = data,
-                0xff12 => apu.nr12 = data,
+                0xff11 => {
+                    apu.nr11 = data;
+                    apu.square1.load_length(data);
+                }
+                0xff12 => {
+                    apu.nr12 = data;
+                    if !channel::dac(data) {
+                        apu.square1.enabled = false;
+                    }
+                }
                 0xff13 => apu.nr13 = data,
                 0xff14 => {
                     apu.nr14 = data & 0xc7;
 
-                    if apu.nr14 & 0x80 != 0 {}
+                    if apu.nr14 & 0x80 != 0 {
+                        let freq = channel::frequency(apu.nr13, apu.nr14);
+                        let (nr10, nr12) = (apu.nr10, apu.nr12);
+                        apu.square1.trigger(nr12, freq);
+                        apu.square1.trigger_sweep(nr10);
+                    }
                 }
 
                 // Channel 2 - Tone
-                0xff16 => apu.nr21 = data,
-                0xff17 => apu.nr22 = data,
+                0xff16 => {
+                    apu.nr21 = data;
+                    apu.square2.load_length(data);
+                }
+                0xff17 => {
+                    apu.nr22 = data;
+                    if !channel::dac(data) {
+                        apu.square2.enabled = false;
+                    }
+                }
                 0xff18 => apu.nr23 = data,
                 0xff19 => {
                     apu.nr24 = data & 0xc7;
 
-                    if apu.nr24 & 0x80 != 0 {}
+                    if apu.nr24 & 0x80 != 0 {
+                        let freq = channel::frequency(apu.nr23, apu.nr24);
+                        let nr22 = apu.nr22;
+                        apu.square2.trigger(nr22, freq);
+                    }
                 }
 
                 // Channel 3 - Wave RAM
-                0xff1a => apu.nr30 = data,
-                0xff1b => apu.nr31 = data,
+                0xff1a => {
+                    apu.nr30 = data;
+                    if data & 0x80 == 0 {
+                        apu.wave.enabled = false;
+                    }
+                }
+                0xff1b => {
+                    apu.nr31 = data;
+                    apu.wave.load_length(data);
+                }
                 0xff1c => apu.nr32 = data,
                 0xff1d => apu.nr33 = data,
                 0xff1e => {
                     apu.nr34 = data;
 
-                    if apu.nr34 & 0x80 != 0 {}
+                    if apu.nr34 & 0x80 != 0 {
+                        let freq = channel::frequency(apu.nr33, apu.nr34);
+                        let nr30 = apu.nr30;
+                        apu.wave.trigger(nr30, freq);
+                    }
                 }
                 0xff30..=0xff3f => { /* Handled below */ }
 
                 // Channel 4 - Noise
-                0xff20 => apu.nr41 = data,
-                0xff21 => apu.nr42 = data,
+                0xff20 => {
+                    apu.nr41 = data;
+                    apu.noise.load_length(data);
+                }
+                0xff21 => {
+                    apu.nr42 = data;
+                    if !channel::dac(data) {
+                        apu.noise.enabled = false;
+                    }
+                }
                 0xff22 => apu.nr43 = data,
                 0xff23 => {
                     apu.nr44 = data;
 
-                    if apu.nr44 & 0x80 != 0 {}
+                    if apu.nr44 & 0x80 != 0 {
+                        let (nr42, nr43) = (apu.nr42, apu.nr43);
+                        apu.noise.trigger(nr42, nr43);
+                    }
                 }
 
                 0xff24 => apu.nr50 = data,
@@ -292,7 +702,10 @@ impl<D: Audio> Device for Apu<D> {
 
 #[cfg(test)]
 mod test {
-    use crate::{apu::Apu, device::Device};
+    use crate::{
+        apu::{device::Stereo44100, Apu, Channel},
+        device::Device,
+    };
 
     #[test]
     fn wave_ram() {
@@ -309,4 +722,104 @@ mod test {
             assert_eq!(w, apu.read(0xff30 + i as u16));
         }
     }
+
+    #[test]
+    fn mute_solo() {
+        let apu = Apu::<Stereo44100<f32>>::default();
+        let mut apu = apu.lock();
+
+        apu.nr51 = 0xff;
+        apu.ch0 = Some(1.0);
+        apu.ch1 = Some(-1.0);
+        apu.ch2 = Some(0.0);
+        apu.ch3 = Some(0.0);
+        assert_eq!([0.0, 0.0], apu.mix());
+
+        apu.set_muted(Channel::Square2, true);
+        assert!(!apu.is_audible(Channel::Square2));
+        assert_eq!([1.0 / 3.0, 1.0 / 3.0], apu.mix());
+
+        // NR51 can't bring back a muted channel
+        apu.nr51 = 0x22;
+        assert_eq!([0.0, 0.0], apu.mix());
+
+        apu.nr51 = 0xff;
+        apu.set_solo(Channel::Square1, true);
+        assert!(apu.is_audible(Channel::Square1));
+        assert!(!apu.is_audible(Channel::Wave));
+        assert_eq!([1.0, 1.0], apu.mix());
+
+        // soloing overrides the mute mask
+        apu.set_solo(Channel::Square2, true);
+        assert_eq!([0.0, 0.0], apu.mix());
+
+        // taps are unaffected by mixing
+        assert_eq!(Some(-1.0), apu.tap(Channel::Square2));
+    }
+
+    #[test]
+    fn samples_and_taps() {
+        let apu = Apu::<Stereo44100<i16>>::default();
+        let samples = apu.samples();
+        apu.lock().step(crate::CLOCK);
+
+        // recording doesn't consume the samples being played, and vice versa
+        assert_eq!(44100, samples.lock_taps().count());
+        assert_eq!(44100, apu.lock().frames.len());
+        samples.lock().take(2 * 44100).for_each(drop);
+        assert!(apu.lock().frames.is_empty());
+        assert!(apu.lock().taps.is_empty());
+
+        apu.lock().step(crate::CLOCK);
+        samples.lock().take(2 * 44100).for_each(drop);
+        assert_eq!(44100, samples.lock_taps().count());
+    }
+
+    #[test]
+    fn channel_state() {
+        let mut apu = Apu::<()>::default();
+
+        apu.write(0xff26, 0x80);
+        apu.write(0xff11, 0b1000_0000 | 62); // 50% duty, length = 2
+        apu.write(0xff12, 0xa3); // volume = 10, decrease, period = 3
+        apu.write(0xff13, 0x00);
+        apu.write(0xff14, 0xc0 | 0x6); // trigger, length enabled, freq = 0x600
+
+        let state = apu.lock().channel_state(Channel::Square1);
+        assert!(state.enabled);
+        assert!(state.dac);
+        assert_eq!(Some(2), state.duty);
+        assert_eq!(10, state.volume);
+        assert_eq!(2, state.length);
+        assert!(state.length_enabled);
+        assert_eq!(256.0, state.frequency);
+        assert_eq!(0x81, apu.read(0xff26));
+
+        // two length clocks (256Hz) disable the channel
+        apu.lock().step(crate::CLOCK / 128);
+        let state = apu.lock().channel_state(Channel::Square1);
+        assert!(!state.enabled);
+        assert_eq!(0, state.length);
+        assert_eq!(0x80, apu.read(0xff26));
+        assert_eq!(None, apu.lock().tap(Channel::Square1));
+    }
+
+    #[test]
+    fn noise_lfsr_width() {
+        let mut apu = Apu::<()>::default();
+
+        apu.write(0xff26, 0x80);
+        apu.write(0xff21, 0xf0);
+        apu.write(0xff22, 0x08);
+        apu.write(0xff23, 0x80);
+
+        let state = apu.lock().channel_state(Channel::Noise);
+        assert!(state.enabled);
+        assert_eq!(Some(7), state.lfsr_width);
+        assert_eq!(524_288.0, state.frequency);
+
+        // powering the DAC off disables the channel
+        apu.write(0xff21, 0x00);
+        assert!(!apu.lock().channel_state(Channel::Noise).enabled);
+    }
 }
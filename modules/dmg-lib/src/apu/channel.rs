@@ -0,0 +1,519 @@
+//! Sound channel state.
+//!
+//! The APU registers are stored in [`ApuInner`], while the types in this module
+//! hold the internal state of the channels (timers, counters, envelopes...),
+//! which is not observable through the registers.
+//!
+//! [`ApuInner`]: #
+use crate::{
+    apu::Channel,
+    state::{Error, Reader, State, Writer},
+};
+
+// Waveforms of the 4 duty cycles (12.5%, 25%, 50% and 75%).
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+/// Volume envelope (NR12, NR22 and NR42).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Envelope {
+    /// Volume is increased (true) or decreased (false).
+    pub increase: bool,
+    /// Number of envelope steps (64Hz) between volume changes. 0 stops the
+    /// envelope.
+    pub period: u8,
+}
+
+impl Envelope {
+    fn from_reg(nrx2: u8) -> Self {
+        Self { increase: nrx2 & 0x8 != 0,
+               period: nrx2 & 0x7 }
+    }
+}
+
+/// Frequency sweep (NR10). Only available in channel 1.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Sweep {
+    /// True if the sweep unit is running.
+    pub enabled: bool,
+    /// Number of sweep steps (128Hz) between frequency changes.
+    pub period: u8,
+    /// Frequency decreases (true) or increases (false).
+    pub negate: bool,
+    /// Number of sweep shifts.
+    pub shift: u8,
+    /// Frequency value (11bit) the sweep unit is currently working with.
+    pub shadow: u16,
+}
+
+/// Snapshot of the state of a sound channel.
+///
+/// Fields that don't apply to a channel are set to `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelState {
+    /// The channel.
+    pub channel: Channel,
+    /// Channel ON flag (same as the NR52 status bit).
+    pub enabled: bool,
+    /// True if the channel DAC is powered.
+    pub dac: bool,
+    /// Frequency of the channel in Hz. On the noise channel, this is the rate
+    /// at which the LFSR is clocked.
+    pub frequency: f64,
+    /// Duty cycle (0-3) of the square channels.
+    pub duty: Option<u8>,
+    /// Current volume (0-15). On the wave channel, the output level is mapped
+    /// to 0 (mute), 15 (100%), 7 (50%) or 3 (25%).
+    pub volume: u8,
+    /// Volume envelope.
+    pub envelope: Option<Envelope>,
+    /// Remaining length counter steps (256Hz).
+    pub length: u16,
+    /// Returns true if the length counter is enabled.
+    pub length_enabled: bool,
+    /// Frequency sweep state.
+    pub sweep: Option<Sweep>,
+    /// Width of the noise LFSR (7 or 15 bits).
+    pub lfsr_width: Option<u8>,
+    /// Contents of the wave RAM.
+    pub wave_ram: Option<[u8; 0x10]>,
+}
+
+// Length counter, shared by the four channels.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Length {
+    pub(crate) counter: u16,
+}
+
+impl Length {
+    fn load(&mut self, max: u16, data: u16) {
+        self.counter = max - data;
+    }
+
+    // Clock the counter (256Hz). Returns true when the counter reaches zero,
+    // which disables the channel.
+    fn clock(&mut self, enabled: bool) -> bool {
+        if enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+
+    fn trigger(&mut self, max: u16) {
+        if self.counter == 0 {
+            self.counter = max;
+        }
+    }
+}
+
+// Volume envelope unit of channels 1, 2 and 4.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Volume {
+    pub(crate) volume: u8,
+    timer: u8,
+}
+
+impl Volume {
+    fn trigger(&mut self, nrx2: u8) {
+        self.volume = nrx2 >> 4;
+        self.timer = nrx2 & 0x7;
+    }
+
+    // Clock the envelope (64Hz).
+    fn clock(&mut self, nrx2: u8) {
+        let Envelope { increase, period } = Envelope::from_reg(nrx2);
+        if period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = period;
+            if increase && self.volume < 0xf {
+                self.volume += 1;
+            } else if !increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    // Output of the channel given the state of the waveform.
+    fn output(self, high: bool) -> f64 {
+        let amp = f64::from(self.volume) / 15.0;
+        if high {
+            amp
+        } else {
+            -amp
+        }
+    }
+}
+
+/// Sound Channel 1 & 2 - Tone (& Sweep)
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Square {
+    pub(crate) enabled: bool,
+    pub(crate) length: Length,
+    pub(crate) volume: Volume,
+    timer: u64,
+    duty_pos: u8,
+    // Frequency sweep (channel 1 only)
+    sweep_enabled: bool,
+    sweep_timer: u8,
+    shadow: u16,
+}
+
+impl Square {
+    pub(crate) fn load_length(&mut self, nrx1: u8) {
+        self.length.load(64, u16::from(nrx1 & 0x3f));
+    }
+
+    pub(crate) fn trigger(&mut self, nrx2: u8, freq: u16) {
+        self.enabled = dac(nrx2);
+        self.length.trigger(64);
+        self.volume.trigger(nrx2);
+        self.timer = period(freq, 4);
+        self.shadow = freq;
+    }
+
+    // Trigger the sweep unit. The overflow check may disable the channel.
+    pub(crate) fn trigger_sweep(&mut self, nr10: u8) {
+        let (period, _, shift) = sweep_reg(nr10);
+        self.sweep_timer = if period == 0 { 8 } else { period };
+        self.sweep_enabled = period != 0 || shift != 0;
+        if shift != 0 && self.sweep_freq(nr10) > 0x7ff {
+            self.enabled = false;
+        }
+    }
+
+    pub(crate) fn step(&mut self, cycles: u64, freq: u16) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = period(freq, 4);
+            self.duty_pos = (self.duty_pos + 1) & 0x7;
+        }
+        self.timer -= cycles;
+    }
+
+    pub(crate) fn clock_length(&mut self, nrx4: u8) {
+        if self.length.clock(nrx4 & 0x40 != 0) {
+            self.enabled = false;
+        }
+    }
+
+    pub(crate) fn clock_envelope(&mut self, nrx2: u8) {
+        self.volume.clock(nrx2);
+    }
+
+    // Clock the sweep unit (128Hz). Returns the new frequency, if it changed.
+    pub(crate) fn clock_sweep(&mut self, nr10: u8) -> Option<u16> {
+        let (period, _, shift) = sweep_reg(nr10);
+        if self.sweep_timer > 0 {
+            self.sweep_timer -= 1;
+        }
+        if self.sweep_timer != 0 {
+            return None;
+        }
+        self.sweep_timer = if period == 0 { 8 } else { period };
+        if !self.sweep_enabled || period == 0 {
+            return None;
+        }
+        let freq = self.sweep_freq(nr10);
+        if freq > 0x7ff {
+            self.enabled = false;
+            return None;
+        }
+        if shift == 0 {
+            return None;
+        }
+        self.shadow = freq;
+        // overflow check is performed again with the new frequency
+        if self.sweep_freq(nr10) > 0x7ff {
+            self.enabled = false;
+        }
+        Some(freq)
+    }
+
+    fn sweep_freq(&self, nr10: u8) -> u16 {
+        let (_, negate, shift) = sweep_reg(nr10);
+        let delta = self.shadow >> shift;
+        if negate {
+            self.shadow.wrapping_sub(delta)
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    pub(crate) fn output(&self, nrx1: u8) -> f64 {
+        let duty = DUTY[(nrx1 >> 6) as usize];
+        self.volume.output(duty >> self.duty_pos & 0x1 != 0)
+    }
+
+    pub(crate) fn state(&self, ch: Channel, regs: [u8; 5]) -> ChannelState {
+        let [nrx0, nrx1, nrx2, nrx3, nrx4] = regs;
+        let freq = frequency(nrx3, nrx4);
+        let sweep = if let Channel::Square1 = ch {
+            let (period, negate, shift) = sweep_reg(nrx0);
+            Some(Sweep { enabled: self.sweep_enabled,
+                         period,
+                         negate,
+                         shift,
+                         shadow: self.shadow })
+        } else {
+            None
+        };
+        ChannelState { channel: ch,
+                       enabled: self.enabled,
+                       dac: dac(nrx2),
+                       frequency: 131_072.0 / f64::from(2048 - freq),
+                       duty: Some(nrx1 >> 6),
+                       volume: self.volume.volume,
+                       envelope: Some(Envelope::from_reg(nrx2)),
+                       length: self.length.counter,
+                       length_enabled: nrx4 & 0x40 != 0,
+                       sweep,
+                       lfsr_width: None,
+                       wave_ram: None }
+    }
+}
+
+/// Sound Channel 3 - Wave Output
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Wave {
+    pub(crate) enabled: bool,
+    pub(crate) length: Length,
+    timer: u64,
+    pos: u8,
+}
+
+impl Wave {
+    pub(crate) fn load_length(&mut self, nr31: u8) {
+        self.length.load(256, u16::from(nr31));
+    }
+
+    pub(crate) fn trigger(&mut self, nr30: u8, freq: u16) {
+        self.enabled = nr30 & 0x80 != 0;
+        self.length.trigger(256);
+        self.timer = period(freq, 2);
+        self.pos = 0;
+    }
+
+    pub(crate) fn step(&mut self, cycles: u64, freq: u16) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = period(freq, 2);
+            self.pos = (self.pos + 1) & 0x1f;
+        }
+        self.timer -= cycles;
+    }
+
+    pub(crate) fn clock_length(&mut self, nr34: u8) {
+        if self.length.clock(nr34 & 0x40 != 0) {
+            self.enabled = false;
+        }
+    }
+
+    pub(crate) fn output(&self, nr32: u8, wave_ram: &[u8; 0x10]) -> f64 {
+        let byte = wave_ram[(self.pos / 2) as usize];
+        let sample = if self.pos & 0x1 == 0 {
+            byte >> 4
+        } else {
+            byte & 0xf
+        };
+        let sample = f64::from(sample) / 7.5 - 1.0;
+        match (nr32 >> 5) & 0x3 {
+            0 => 0.0,
+            1 => sample,
+            2 => sample * 0.5,
+            _ => sample * 0.25,
+        }
+    }
+
+    pub(crate) fn state(&self, regs: [u8; 5], wave_ram: &[u8; 0x10]) -> ChannelState {
+        let [nr30, _, nr32, nr33, nr34] = regs;
+        let freq = frequency(nr33, nr34);
+        ChannelState { channel: Channel::Wave,
+                       enabled: self.enabled,
+                       dac: nr30 & 0x80 != 0,
+                       frequency: 65_536.0 / f64::from(2048 - freq),
+                       duty: None,
+                       volume: [0, 15, 7, 3][((nr32 >> 5) & 0x3) as usize],
+                       envelope: None,
+                       length: self.length.counter,
+                       length_enabled: nr34 & 0x40 != 0,
+                       sweep: None,
+                       lfsr_width: None,
+                       wave_ram: Some(*wave_ram) }
+    }
+}
+
+/// Sound Channel 4 - Noise
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Noise {
+    pub(crate) enabled: bool,
+    pub(crate) length: Length,
+    pub(crate) volume: Volume,
+    timer: u64,
+    lfsr: u16,
+}
+
+impl Noise {
+    pub(crate) fn load_length(&mut self, nr41: u8) {
+        self.length.load(64, u16::from(nr41 & 0x3f));
+    }
+
+    pub(crate) fn trigger(&mut self, nr42: u8, nr43: u8) {
+        self.enabled = dac(nr42);
+        self.length.trigger(64);
+        self.volume.trigger(nr42);
+        self.timer = noise_period(nr43);
+        self.lfsr = 0x7fff;
+    }
+
+    pub(crate) fn step(&mut self, cycles: u64, nr43: u8) {
+        let mut cycles = cycles;
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = noise_period(nr43);
+
+            let xor = (self.lfsr & 0x1) ^ ((self.lfsr >> 1) & 0x1);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+            if nr43 & 0x8 != 0 {
+                self.lfsr &= !(1 << 6);
+                self.lfsr |= xor << 6;
+            }
+        }
+        self.timer -= cycles;
+    }
+
+    pub(crate) fn clock_length(&mut self, nr44: u8) {
+        if self.length.clock(nr44 & 0x40 != 0) {
+            self.enabled = false;
+        }
+    }
+
+    pub(crate) fn clock_envelope(&mut self, nr42: u8) {
+        self.volume.clock(nr42);
+    }
+
+    pub(crate) fn output(&self) -> f64 {
+        self.volume.output(self.lfsr & 0x1 == 0)
+    }
+
+    pub(crate) fn state(&self, regs: [u8; 5]) -> ChannelState {
+        let [_, _, nr42, nr43, nr44] = regs;
+        ChannelState { channel: Channel::Noise,
+                       enabled: self.enabled,
+                       dac: dac(nr42),
+                       frequency: 4_194_304.0 / noise_period(nr43) as f64,
+                       duty: None,
+                       volume: self.volume.volume,
+                       envelope: Some(Envelope::from_reg(nr42)),
+                       length: self.length.counter,
+                       length_enabled: nr44 & 0x40 != 0,
+                       sweep: None,
+                       lfsr_width: Some(if nr43 & 0x8 != 0 { 7 } else { 15 }),
+                       wave_ram: None }
+    }
+}
+
+// The DAC of channels 1, 2 and 4 is powered off when the upper 5 bits of NRx2
+// are cleared.
+pub(crate) fn dac(nrx2: u8) -> bool {
+    nrx2 & 0xf8 != 0
+}
+
+impl State for Square {
+    fn save_state(&self, state: &mut Writer) {
+        state.bool(self.enabled);
+        state.u16(self.length.counter);
+        state.u8(self.volume.volume);
+        state.u8(self.volume.timer);
+        state.u64(self.timer);
+        state.u8(self.duty_pos);
+        state.bool(self.sweep_enabled);
+        state.u8(self.sweep_timer);
+        state.u16(self.shadow);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.enabled = state.bool()?;
+        self.length.counter = state.u16()?;
+        self.volume.volume = state.u8()?;
+        self.volume.timer = state.u8()?;
+        self.timer = state.u64()?;
+        self.duty_pos = state.u8()?;
+        self.sweep_enabled = state.bool()?;
+        self.sweep_timer = state.u8()?;
+        self.shadow = state.u16()?;
+        Ok(())
+    }
+}
+
+impl State for Wave {
+    fn save_state(&self, state: &mut Writer) {
+        state.bool(self.enabled);
+        state.u16(self.length.counter);
+        state.u64(self.timer);
+        state.u8(self.pos);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.enabled = state.bool()?;
+        self.length.counter = state.u16()?;
+        self.timer = state.u64()?;
+        self.pos = state.u8()?;
+        Ok(())
+    }
+}
+
+impl State for Noise {
+    fn save_state(&self, state: &mut Writer) {
+        state.bool(self.enabled);
+        state.u16(self.length.counter);
+        state.u8(self.volume.volume);
+        state.u8(self.volume.timer);
+        state.u64(self.timer);
+        state.u16(self.lfsr);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.enabled = state.bool()?;
+        self.length.counter = state.u16()?;
+        self.volume.volume = state.u8()?;
+        self.volume.timer = state.u8()?;
+        self.timer = state.u64()?;
+        self.lfsr = state.u16()?;
+        Ok(())
+    }
+}
+
+// 11bit frequency value from the NRx3 and NRx4 registers.
+pub(crate) fn frequency(nrx3: u8, nrx4: u8) -> u16 {
+    u16::from(nrx4 & 0x7) << 8 | u16::from(nrx3)
+}
+
+// Period of the channel timer in cycles.
+fn period(freq: u16, mul: u64) -> u64 {
+    (2048 - u64::from(freq)) * mul
+}
+
+// Bit 7-4 - Clock shift (s)
+// Bit 3   - Counter step/width (0=15 bits, 1=7 bits)
+// Bit 2-0 - Dividing ratio of frequencies (r)
+fn noise_period(nr43: u8) -> u64 {
+    let r = u64::from(nr43 & 0x7);
+    let s = u64::from(nr43 >> 4);
+    let divisor = if r == 0 { 8 } else { r * 16 };
+    divisor << s
+}
+
+// Bit 6-4 - Sweep period
+// Bit 3   - Sweep direction (0: Addition, 1: Subtraction)
+// Bit 2-0 - Number of sweep shift
+fn sweep_reg(nr10: u8) -> (u8, bool, u8) {
+    ((nr10 >> 4) & 0x7, nr10 & 0x8 != 0, nr10 & 0x7)
+}
@@ -47,7 +47,8 @@ sample! {
 pub trait Audio {
     type Sample: Sample;
 
-    /// Return the samples per second of the device.
+    /// Return the samples per second of the device. The APU doesn't generate
+    /// any samples if the rate is 0.
     fn sample_rate() -> u64;
 
     /// Returns true if the channel is single-channel.
@@ -84,7 +85,7 @@ impl<T: Sample> Audio for Mono44100<T> {
 
     #[inline]
     fn mono() -> bool {
-        false
+        true
     }
 }
 
@@ -92,12 +93,12 @@ impl<T: Sample> Audio for Mono44100<T> {
 ///
 /// # Panic
 /// Since this device is meant for emulators without sound, calling any method
-/// will panic.
+/// other than `sample_rate` (which returns 0) will panic.
 impl Audio for () {
     type Sample = ();
 
     fn sample_rate() -> u64 {
-        panic!()
+        0
     }
 
     fn mono() -> bool {
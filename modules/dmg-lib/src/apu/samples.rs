@@ -23,10 +23,74 @@ impl<D: Audio> SamplesMutex<D> {
                        buf: Arc::new(Cell::new(None)) }
     }
 
+    /// Lock the APU and return an iterator of interleaved samples.
+    ///
+    /// Buffered samples are consumed first. When the buffer runs out, the
+    /// iterator keeps producing samples from the current output of the APU,
+    /// so it never ends.
     pub fn lock<'a>(&'a self) -> impl Iterator<Item = D::Sample> + 'a {
         Samples { inner: self.inner.lock().expect("Error locking APU"),
                   buf: Arc::clone(&self.buf) }
     }
+
+    /// Same as [`SamplesMutex::lock`], but each item contains a whole output
+    /// frame alongside the individual output of each channel.
+    ///
+    /// Unlike [`SamplesMutex::lock`], the iterator ends once the buffered
+    /// frames have been consumed, so it can be used to record the exact
+    /// output of the APU. Taps are buffered apart from the samples, so
+    /// recording doesn't take any samples away from the audio device.
+    ///
+    /// [`SamplesMutex::lock`]: #
+    pub fn lock_taps<'a>(&'a self) -> impl Iterator<Item = Tap<D::Sample>> + 'a {
+        Taps { inner: self.inner.lock().expect("Error locking APU") }
+    }
+}
+
+/// A mixed output frame, along with the output of each of the channels before
+/// mixing.
+#[derive(Debug, Clone, Copy)]
+pub struct Tap<S: Sample> {
+    /// Left and right mixed samples. Both are equal on mono devices.
+    pub mix: [S; 2],
+    /// Channel samples, in [`Channel::ALL`] order. Channels are tapped before
+    /// NR51 panning and the mute/solo masks are applied.
+    ///
+    /// [`Channel::ALL`]: #
+    pub channels: [S; 4],
+}
+
+/// Output of the APU at a given sample.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Frame {
+    // Mixed output (SO1, SO2)
+    pub(crate) mix: [f64; 2],
+    // Output of each channel, in Channel::ALL order
+    pub(crate) channels: [Option<f64>; 4],
+}
+
+/// Iterator of tapped frames produced by the APU.
+struct Taps<'a, D: Audio> {
+    inner: MutexGuard<'a, ApuInner<D>>,
+}
+
+impl<D: Audio> Iterator for Taps<'_, D> {
+    type Item = Tap<D::Sample>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Frame { mix: [l, r],
+                    channels, } = self.inner.taps.pop_front()?;
+        let mix = if D::mono() {
+            let mix = to_sample::<D>((l + r) / 2.0);
+            [mix, mix]
+        } else {
+            [to_sample::<D>(l), to_sample::<D>(r)]
+        };
+        let [ch0, ch1, ch2, ch3] = channels;
+        let tap = |ch: Option<f64>| to_sample::<D>(ch.unwrap_or(0.0));
+        let channels = [tap(ch0), tap(ch1), tap(ch2), tap(ch3)];
+        Some(Tap { mix, channels })
+    }
 }
 
 enum SampleBuffer<D: Audio> {
@@ -43,58 +107,15 @@ struct Samples<'a, D: Audio> {
 impl<D: Audio> Samples<'_, D> {
     // Loads the next sample into the buffer
     fn load(&mut self) {
-        let apu = &mut self.inner;
-
-        // sample new voices
-        let ch0 = apu.ch0;
-        let ch1 = apu.ch1;
-        let ch2 = apu.ch2;
-        let ch3 = apu.ch3;
-
-        // audio mixing
-        let mut so: [f64; 2] = [0.0; 2];
-        let mut count: [u32; 2] = [0; 2];
-
-        let nr51 = apu.nr51;
-        for (ch, sample) in [ch0, ch1, ch2, ch3].iter().copied().enumerate() {
-            let so1_bit = 1 << (ch as u8);
-            let so2_bit = 1 << (4 + ch as u8);
-            let sample = if let Some(sample) = sample {
-                sample
-            } else {
-                0.0
-            };
-            if nr51 & so1_bit != 0 {
-                so[0] += sample;
-                count[0] += 1;
-            }
-            if nr51 & so2_bit != 0 {
-                so[1] += sample;
-                count[1] += 1;
-            }
-        }
-
-        if count[0] > 0 {
-            so[0] /= count[0] as f64;
-        }
-        if count[1] > 0 {
-            so[1] /= count[1] as f64;
-        }
-
-        let max: f64 = D::Sample::max().as_f64();
-        let min: f64 = D::Sample::min().as_f64();
-        let l = clamp(so[0] * 0.5 + 0.5, 0.0, 1.0);
-        let l = min * (1.0 - l) + max * l;
-        let r = clamp(so[1] * 0.5 + 0.5, 0.0, 1.0);
-        let r = min * (1.0 - r) + max * r;
-
+        let [l, r] = match self.inner.frames.pop_front() {
+            Some(frame) => frame.mix,
+            None => self.inner.mix(),
+        };
         self.buf.set(Some(if D::mono() {
-                              let mix = D::Sample::from_f64((l + r) / 2.0);
+                              let mix = to_sample::<D>((l + r) / 2.0);
                               SampleBuffer::One([mix])
                           } else {
-                              let l = D::Sample::from_f64(l);
-                              let r = D::Sample::from_f64(r);
-                              SampleBuffer::Two([l, r])
+                              SampleBuffer::Two([to_sample::<D>(l), to_sample::<D>(r)])
                           }));
     }
 }
@@ -119,6 +140,14 @@ impl<D: Audio> Iterator for Samples<'_, D> {
     }
 }
 
+// Map a value in the [-1.0, 1.0] range to the sample range of the device.
+fn to_sample<D: Audio>(n: f64) -> D::Sample {
+    let max: f64 = D::Sample::max().as_f64();
+    let min: f64 = D::Sample::min().as_f64();
+    let n = clamp(n * 0.5 + 0.5, 0.0, 1.0);
+    D::Sample::from_f64(min * (1.0 - n) + max * n)
+}
+
 fn clamp(n: f64, min: f64, max: f64) -> f64 {
     if n > max {
         max
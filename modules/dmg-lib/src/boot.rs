@@ -0,0 +1,392 @@
+//! Boot ROM images.
+//!
+//! Boot ROMs are not distributed with the crate. An image can be supplied at
+//! runtime with [`Builder::boot_rom`]. Otherwise, the boot sequence is skipped
+//! and the CPU registers, I/O registers and VRAM are initialized to the state
+//! left by the boot ROM of the emulated [`Model`].
+//!
+//! [`Builder::boot_rom`]: #
+//! [`Model`]: #
+use crate::{
+    apu::device::Audio,
+    cartridge::Cartridge,
+    cpu::Cpu,
+    device::Device,
+    joypad::{Btn, Dir},
+    mmu::Mmu,
+    ppu::{compat::CompatPalette, Video},
+    Error, Mode, Model,
+};
+
+/// Size of the DMG, MGB and SGB boot ROMs.
+pub const GB_SIZE: usize = 0x100;
+/// Size of the CGB boot ROM.
+pub const CGB_SIZE: usize = 0x900;
+
+/// A boot ROM image.
+#[derive(Clone)]
+pub struct BootRom {
+    rom: Box<[u8]>,
+}
+
+impl BootRom {
+    /// Create a boot ROM from its image. The size of the image must match that
+    /// of the DMG, MGB & SGB boot ROMs (256 bytes) or the CGB one (2304 bytes).
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        match bytes.len() {
+            GB_SIZE | CGB_SIZE => Ok(Self { rom: bytes.to_vec().into_boxed_slice() }),
+            len => Err(Error::BootRomSize(len)),
+        }
+    }
+
+    /// Returns true if this is a CGB boot ROM.
+    pub fn is_cgb(&self) -> bool {
+        self.rom.len() == CGB_SIZE
+    }
+
+    // While the boot ROM is mapped, it overlays the cartridge in 0000-00FF (and
+    // 0200-08FF in the case of the CGB, leaving the cartridge header visible).
+    pub(crate) fn read(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x00ff => Some(self.rom[addr as usize]),
+            0x0200..=0x08ff if self.is_cgb() => Some(self.rom[addr as usize]),
+            _ => None,
+        }
+    }
+}
+
+// Registered trademark symbol drawn next to the logo (read from the boot ROM).
+const REGISTERED: [u8; 8] = [0x3c, 0x42, 0xb9, 0xa5, 0xb9, 0xa5, 0x42, 0x3c];
+
+// I/O registers left by the boot ROM (common to all models). The APU is powered
+// on first, otherwise writes to the rest of sound registers are ignored.
+#[rustfmt::skip]
+const IO: [(u16, u8); 30] = [
+    (0xff26, 0x80), // NR52
+    (0xff05, 0x00), // TIMA
+    (0xff06, 0x00), // TMA
+    (0xff07, 0xf8), // TAC
+    (0xff0f, 0xe1), // IF
+    (0xff10, 0x80), // NR10
+    (0xff11, 0xbf), // NR11
+    (0xff12, 0xf3), // NR12
+    (0xff13, 0xff), // NR13
+    (0xff16, 0x3f), // NR21
+    (0xff17, 0x00), // NR22
+    (0xff18, 0xff), // NR23
+    (0xff19, 0xbf), // NR24
+    (0xff1a, 0x7f), // NR30
+    (0xff1b, 0xff), // NR31
+    (0xff1c, 0x9f), // NR32
+    (0xff1d, 0xff), // NR33
+    (0xff1e, 0xbf), // NR34
+    (0xff20, 0xff), // NR41
+    (0xff21, 0x00), // NR42
+    (0xff22, 0x00), // NR43
+    (0xff23, 0xbf), // NR44
+    (0xff24, 0x77), // NR50
+    (0xff25, 0xf3), // NR51
+    (0xff40, 0x91), // LCDC
+    (0xff42, 0x00), // SCY
+    (0xff43, 0x00), // SCX
+    (0xff45, 0x00), // LYC
+    (0xff47, 0xfc), // BGP
+    (0xffff, 0x00), // IE
+];
+
+/// Initialize the CPU & memory to the state left by the boot ROM of the given
+/// model, as if it had just been unmapped. On the CGB, games without CGB
+/// support are colorized with the palette picked from the title (or from the
+/// given keys).
+pub(crate) fn skip<C: Cartridge, V: Video, D: Audio>(model: Model,
+                                                     keys: Option<(Dir, Option<Btn>)>,
+                                                     cpu: &mut Cpu,
+                                                     mmu: &mut Mmu<C, V, D>) {
+    if model.mode() == Mode::CGB && mmu.cartridge().read(0x0143) & 0x80 == 0 {
+        let palette = match keys {
+            Some((dir, btn)) => CompatPalette::from_keys(dir, btn),
+            None => {
+                let cartridge = mmu.cartridge();
+                CompatPalette::from_title(title_checksum(cartridge), cartridge.read(0x0137))
+            }
+        };
+        mmu.set_dmg_compat();
+        mmu.ppu_mut().set_compat_palette(&palette);
+    }
+
+    let [af, bc, de, hl] = registers(model, mmu.cartridge());
+    let reg = cpu.reg_mut();
+    reg.set_af(af);
+    reg.set_bc(bc);
+    reg.set_de(de);
+    reg.set_hl(hl);
+    reg.sp = 0xfffe;
+    reg.pc = 0x0100;
+
+    for (addr, data) in IO.iter().copied() {
+        mmu.write(addr, data);
+    }
+    // The boot ROM leaves square channel 1 playing the startup sound (NR52 =
+    // F1), except on the SGB, where it doesn't play it (NR52 = F0).
+    mmu.write(0xff14, if model.is_sgb() { 0x3f } else { 0xbf });
+
+    mmu.write(0xff48, 0xff); // OBP0
+    mmu.write(0xff49, 0xff); // OBP1
+    mmu.write(0xff4a, 0x00); // WY
+    mmu.write(0xff4b, 0x00); // WX
+
+    // DIV depends on the time it takes the boot ROM to run. It is only
+    // deterministic on the DMG & MGB (the rest of boot ROMs wait for the SNES or
+    // vary with the cartridge header).
+    let div = match model {
+        Model::Dmg0 => 0x18,
+        Model::Dmg | Model::Mgb => 0xab,
+        _ => 0x00,
+    };
+    mmu.timer_mut().set_div(div);
+
+    // The CGB boot ROM clears VRAM before starting the game, so only the
+    // monochrome models have the logo left in it.
+    if let Model::Dmg0 | Model::Dmg | Model::Mgb | Model::Sgb | Model::Sgb2 = model {
+        logo(mmu);
+    }
+}
+
+// Values of the AF, BC, DE & HL registers. Some of them depend on the header of
+// the cartridge:
+//  - DMG & MGB: if the header checksum is 0, H & C flags are clear.
+//  - CGB & AGB running a non-CGB game: B is the checksum of the title (but only
+//    for games licensed by Nintendo), which is also used to set HL.
+//  - AGB: the boot ROM is the same as the CGB one, but it runs an extra INC B
+//    before starting the game.
+fn registers<C: Cartridge>(model: Model, cartridge: &C) -> [u16; 4] {
+    let header_checksum = cartridge.read(0x014d);
+    let flags = if header_checksum == 0 { 0x80 } else { 0xb0 };
+
+    match model {
+        Model::Dmg0 => [0x0100, 0xff13, 0x00c1, 0x8403],
+        Model::Dmg => [0x0100 | flags, 0x0013, 0x00d8, 0x014d],
+        Model::Mgb => [0xff00 | flags, 0x0013, 0x00d8, 0x014d],
+        Model::Sgb => [0x0100, 0x0014, 0x0000, 0xc060],
+        Model::Sgb2 => [0xff00, 0x0014, 0x0000, 0xc060],
+        Model::Cgb | Model::Agb => {
+            let [af, bc, de, hl] = if cartridge.read(0x0143) & 0x80 != 0 {
+                [0x1180, 0x0000, 0xff56, 0x000d]
+            } else {
+                let b = title_checksum(cartridge);
+                let hl = if b == 0x43 || b == 0x58 {
+                    0x991a
+                } else {
+                    0x007c
+                };
+                [0x1180, u16::from(b) << 8, 0x0008, hl]
+            };
+            if let Model::Agb = model {
+                // INC B (Z & H flags are set from the result, C is preserved)
+                let b = (bc >> 8) as u8;
+                let b = b.wrapping_add(1);
+                let mut f = af as u8 & 0x10;
+                if b == 0 {
+                    f |= 0x80;
+                }
+                if b & 0xf == 0 {
+                    f |= 0x20;
+                }
+                [af & 0xff00 | u16::from(f),
+                 u16::from(b) << 8 | bc & 0xff,
+                 de,
+                 hl]
+            } else {
+                [af, bc, de, hl]
+            }
+        }
+    }
+}
+
+// Sum of the bytes of the title, for games licensed by Nintendo (zero
+// otherwise).
+fn title_checksum<C: Cartridge>(cartridge: &C) -> u8 {
+    let old_licensee = cartridge.read(0x014b);
+    let new_licensee = [cartridge.read(0x0144), cartridge.read(0x0145)];
+    if old_licensee == 0x01 || (old_licensee == 0x33 && new_licensee == *b"01") {
+        (0x0134..=0x0143).map(|addr| cartridge.read(addr))
+                         .fold(0, u8::wrapping_add)
+    } else {
+        0
+    }
+}
+
+// Decompress the logo from the cartridge header into tiles 1-24, the same way
+// the boot ROM does (each bit is doubled horizontally and vertically, and only
+// the low bit plane is written), and lay out the tile map.
+fn logo<C: Cartridge, V: Video, D: Audio>(mmu: &mut Mmu<C, V, D>) {
+    let mut addr = 0x8010;
+    for header_addr in 0x0104..0x0134 {
+        let data = mmu.cartridge().read(header_addr);
+        for nibble in [data >> 4, data & 0xf].iter() {
+            let row = (0..4).fold(0, |row, bit| {
+                                let set = (nibble >> (3 - bit)) & 0x1;
+                                row | (set * 0x3) << (6 - 2 * bit)
+                            });
+            mmu.write(addr, row);
+            mmu.write(addr + 2, row);
+            addr += 4;
+        }
+    }
+    for (i, row) in REGISTERED.iter().enumerate() {
+        mmu.write(0x8190 + 2 * i as u16, *row);
+    }
+
+    for tile in 0..12 {
+        mmu.write(0x9904 + tile, tile as u8 + 1);
+        mmu.write(0x9924 + tile, tile as u8 + 13);
+    }
+    mmu.write(0x9910, 0x19);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        boot::BootRom,
+        cartridge::Rom,
+        device::Device,
+        joypad::{Btn, Dir},
+        Builder, Error, Mode, Model,
+    };
+
+    #[test]
+    fn size() {
+        assert!(BootRom::from_bytes(&[0; 0x100]).is_ok());
+        assert!(BootRom::from_bytes(&[0; 0x900]).unwrap().is_cgb());
+        assert_eq!(Some(Error::BootRomSize(0x200)),
+                   BootRom::from_bytes(&[0; 0x200]).err());
+    }
+
+    #[test]
+    fn model() {
+        let gb = BootRom::from_bytes(&[0; 0x100]).unwrap();
+        let cgb = BootRom::from_bytes(&[0; 0x900]).unwrap();
+        let build = |model, boot_rom: &BootRom| {
+            Builder::default().model(model)
+                              .boot_rom(boot_rom.clone())
+                              .try_build()
+                              .err()
+        };
+        assert_eq!(None, build(Model::Mgb, &gb));
+        assert_eq!(None, build(Model::Agb, &cgb));
+        assert_eq!(Some(Error::BootRomModel(Model::Cgb)),
+                   build(Model::Cgb, &gb));
+        assert_eq!(Some(Error::BootRomModel(Model::Sgb)),
+                   build(Model::Sgb, &cgb));
+
+        // the boot ROM isn't used when skipped
+        assert!(Builder::default().model(Model::Cgb)
+                                  .boot_rom(gb)
+                                  .skip_boot()
+                                  .try_build()
+                                  .is_ok());
+    }
+
+    #[test]
+    fn mapping() {
+        let gb = BootRom::from_bytes(&[1; 0x100]).unwrap();
+        assert_eq!(Some(1), gb.read(0x00ff));
+        assert_eq!(None, gb.read(0x0100));
+        assert_eq!(None, gb.read(0x0200));
+
+        let cgb = BootRom::from_bytes(&[2; 0x900]).unwrap();
+        assert_eq!(Some(2), cgb.read(0x0000));
+        assert_eq!(None, cgb.read(0x0100));
+        assert_eq!(None, cgb.read(0x014f));
+        assert_eq!(Some(2), cgb.read(0x0200));
+        assert_eq!(Some(2), cgb.read(0x08ff));
+        assert_eq!(None, cgb.read(0x0900));
+    }
+
+    #[test]
+    fn registers() {
+        let a = |model| Builder::default().model(model).build().cpu().reg().a;
+        assert_eq!(0x01, a(Model::Dmg));
+        assert_eq!(0xff, a(Model::Mgb));
+        assert_eq!(0x01, a(Model::Sgb));
+        assert_eq!(0xff, a(Model::Sgb2));
+        assert_eq!(0x11, a(Model::Cgb));
+        assert_eq!(0x11, a(Model::Agb));
+
+        // B register tells the AGB apart from the CGB
+        let dmg = Builder::default().model(Model::Agb).build();
+        assert_eq!(0x0100, dmg.cpu().reg().bc());
+        assert_eq!(0x00, dmg.cpu().reg().f);
+
+        let dmg = Builder::default().model(Model::Dmg0).build();
+        assert_eq!(0xff13, dmg.cpu().reg().bc());
+        assert_eq!(0x18, dmg.mmu().read(0xff04));
+    }
+
+    #[test]
+    fn io() {
+        let dmg = Builder::default().model(Model::Dmg).build();
+        assert_eq!(0x91, dmg.mmu().read(0xff40));
+        assert_eq!(0xfc, dmg.mmu().read(0xff47));
+        assert_eq!(0xab, dmg.mmu().read(0xff04));
+        // power & channel status bits of NR52 (channel 1 is still on)
+        assert_eq!(0x81, dmg.mmu().read(0xff26) & 0x8f);
+
+        let sgb = Builder::default().model(Model::Sgb).build();
+        assert_eq!(0x80, sgb.mmu().read(0xff26) & 0x8f);
+    }
+
+    #[test]
+    fn logo() {
+        // the (empty) cartridge reads FF, so every row of the logo is filled
+        let dmg = Builder::default().model(Model::Dmg).build();
+        assert_eq!(0xff, dmg.mmu().read(0x8010));
+        assert_eq!(0x00, dmg.mmu().read(0x8011));
+        assert_eq!(0xff, dmg.mmu().read(0x818e));
+        assert_eq!(0x3c, dmg.mmu().read(0x8190));
+        assert_eq!(0x00, dmg.mmu().read(0x81a0));
+        assert_eq!(0x01, dmg.mmu().read(0x9904));
+        assert_eq!(0x0c, dmg.mmu().read(0x990f));
+        assert_eq!(0x19, dmg.mmu().read(0x9910));
+        assert_eq!(0x0d, dmg.mmu().read(0x9924));
+        assert_eq!(0x18, dmg.mmu().read(0x992f));
+
+        let cgb = Builder::default().model(Model::Cgb).build();
+        assert_eq!(0x00, cgb.mmu().read(0x8010));
+        assert_eq!(0x00, cgb.mmu().read(0x9904));
+    }
+
+    fn dmg_rom(title: &[u8]) -> Rom {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0134..0x0134 + title.len()].copy_from_slice(title);
+        rom[0x014b] = 0x01; // Nintendo
+        Rom::new(rom.into_boxed_slice())
+    }
+
+    #[test]
+    fn compat_palette() {
+        let dmg = Builder::default().cartridge(dmg_rom(b"TETRIS"))
+                                    .model(Model::Cgb)
+                                    .build();
+        assert_eq!(Mode::GB, dmg.mmu().mode());
+        assert!(dmg.mmu().ppu().is_dmg_compat());
+        // title checksum in B
+        assert_eq!(0xdb, dmg.cpu().reg().b);
+        assert_eq!([0xff, 0xff, 0x00],
+                   dmg.mmu().ppu().color_pal().bg_pal_color(0, 1));
+        assert_eq!([0xff, 0x00, 0x00],
+                   dmg.mmu().ppu().color_pal().ob_pal_color(0, 2));
+
+        let dmg = Builder::default().cartridge(dmg_rom(b"TETRIS"))
+                                    .model(Model::Cgb)
+                                    .compat_palette(Dir::Left, Some(Btn::B))
+                                    .build();
+        assert_eq!([0xa4, 0xa4, 0xa4],
+                   dmg.mmu().ppu().color_pal().bg_pal_color(0, 1));
+
+        // not colorized on monochrome models
+        let dmg = Builder::default().cartridge(dmg_rom(b"TETRIS")).build();
+        assert_eq!(Model::Dmg, dmg.mmu().model());
+        assert!(!dmg.mmu().ppu().is_dmg_compat());
+    }
+}
@@ -2,27 +2,79 @@
 //!
 //! Only the most common cartridge types are implemented. Less common cartridges
 //! (such as the camera) are implemented in external crates.
-use crate::device::Device;
+use crate::{
+    device::Device,
+    state::{Error, Reader, Writer},
+};
 
+pub(crate) mod gbs;
 mod mbc1;
 mod mbc3;
 mod mbc5;
 mod rom;
 
+pub use gbs::Gbs;
 pub use mbc1::Mbc1;
 pub use mbc3::Mbc3;
 pub use mbc5::Mbc5;
 pub use rom::Rom;
 
 /// Bank controller trait.
-pub trait Cartridge: Device {}
+pub trait Cartridge: Device {
+    /// ROM bank mapped to the switchable area (4000-7FFF). Cartridges without
+    /// a bank controller always map bank 1.
+    fn rom_bank(&self) -> usize {
+        1
+    }
+
+    /// Cartridge RAM (all the banks, in order), to be stored in a save file.
+    /// Empty for cartridges without RAM.
+    fn ram(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Mutable cartridge RAM (see [`Cartridge::ram`]).
+    ///
+    /// [`Cartridge::ram`]: #
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut []
+    }
+
+    /// Save the state of the cartridge (bank controller registers and RAM)
+    /// into a savestate. Stateless by default.
+    fn save_state(&self, _state: &mut Writer) {}
+
+    /// Restore the state written by [`Cartridge::save_state`].
+    ///
+    /// [`Cartridge::save_state`]: #
+    fn load_state(&mut self, _state: &mut Reader) -> Result<(), Error> {
+        Ok(())
+    }
+}
 
 impl Cartridge for () {}
-impl Cartridge for Rom {}
-impl Cartridge for Mbc1 {}
-impl Cartridge for Mbc3 {}
-impl Cartridge for Mbc5 {}
-impl Cartridge for Box<dyn Cartridge> {}
+
+impl Cartridge for Box<dyn Cartridge> {
+    fn rom_bank(&self) -> usize {
+        self.as_ref().rom_bank()
+    }
+
+    fn ram(&self) -> &[u8] {
+        self.as_ref().ram()
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        self.as_mut().ram_mut()
+    }
+
+    fn save_state(&self, state: &mut Writer) {
+        self.as_ref().save_state(state)
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.as_mut().load_state(state)
+    }
+}
 
 impl Device for Box<dyn Cartridge> {
     fn read(&self, addr: u16) -> u8 {
@@ -45,6 +97,7 @@ pub fn from_bytes(bytes: &[u8]) -> Result<Box<dyn Cartridge>, ()> {
     }
 }
 
+
 fn ram_banks(banks: u8) -> usize {
     match banks {
         0x00 => 0,
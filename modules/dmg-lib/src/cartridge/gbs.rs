@@ -0,0 +1,112 @@
+use crate::{
+    cartridge::Cartridge,
+    device::Device,
+    state::{Error, Reader, Writer},
+};
+
+// Address the play and init routines return to. It is located in the (unused)
+// area before the data, and it's never executed.
+pub(crate) const RETURN_ADDR: u16 = 0x00f0;
+
+/// Minimal ROM cartridge for the music data of GBS files.
+///
+/// The data is mapped at the load address given in the GBS header. Banks are
+/// switched by writing to 2000-3FFF, same as MBC1 and MBC5.
+pub struct Gbs {
+    rom: Box<[u8]>,
+    ram: Box<[u8; 0x2000]>,
+    bank: usize,
+}
+
+impl Gbs {
+    /// Map the data of a GBS file (everything following the header) at the
+    /// given load address.
+    pub fn new(load_addr: u16, data: &[u8]) -> Self {
+        let load_addr = load_addr as usize;
+        let len = load_addr + data.len();
+        let mut rom = vec![0; (len + 0x3fff) & !0x3fff];
+        rom[load_addr..len].copy_from_slice(data);
+
+        // RST vectors are relocated to the load address
+        for rst in (0x00..0x40).step_by(8) {
+            let addr = (load_addr + rst) as u16;
+            rom[rst] = 0xc3; // JP nn
+            rom[rst + 1] = addr as u8;
+            rom[rst + 2] = (addr >> 8) as u8;
+        }
+        // Interrupt vectors
+        for int in (0x40..=0x60).step_by(8) {
+            rom[int] = 0xd9; // RETI
+        }
+
+        Self { rom: rom.into_boxed_slice(),
+               ram: Box::new([0; 0x2000]),
+               bank: 1 }
+    }
+}
+
+impl Device for Gbs {
+    fn read(&self, addr: u16) -> u8 {
+        match addr as usize {
+            addr @ 0x0000..=0x3fff => self.rom[addr],
+            addr @ 0x4000..=0x7fff => {
+                let addr = self.bank * 0x4000 + addr - 0x4000;
+                *self.rom.get(addr).unwrap_or(&0xff)
+            }
+            addr @ 0xa000..=0xbfff => self.ram[addr - 0xa000],
+            _ => panic!(),
+        }
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        match addr as usize {
+            0x2000..=0x3fff => self.bank = (data as usize).max(1),
+            0x0000..=0x7fff => {}
+            addr @ 0xa000..=0xbfff => self.ram[addr - 0xa000] = data,
+            _ => panic!(),
+        }
+    }
+}
+
+impl Cartridge for Gbs {
+    fn rom_bank(&self) -> usize {
+        self.bank
+    }
+
+    fn save_state(&self, state: &mut Writer) {
+        state.bytes(&self.ram[..]);
+        state.u16(self.bank as u16);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        state.bytes(&mut self.ram[..])?;
+        self.bank = state.u16()? as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{cartridge::Gbs, device::Device};
+
+    #[test]
+    fn banking() {
+        let mut data = vec![0; 0x8000];
+        data[0x3c00] = 1;
+        data[0x7c00] = 2;
+
+        let mut gbs = Gbs::new(0x400, &data);
+
+        // RST 08 relocated to load address + 8
+        assert_eq!(0xc3, gbs.read(0x08));
+        assert_eq!(0x0408, gbs.read_word(0x09));
+        assert_eq!(1, gbs.read(0x4000));
+
+        gbs.write(0x2000, 2);
+        assert_eq!(2, gbs.read(0x4000));
+
+        // bank 0 maps bank 1
+        gbs.write(0x2000, 0);
+        assert_eq!(1, gbs.read(0x4000));
+    }
+}
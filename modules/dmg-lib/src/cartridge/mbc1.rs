@@ -1,4 +1,8 @@
-use crate::{cartridge::ram_banks, device::Device};
+use crate::{
+    cartridge::{ram_banks, Cartridge},
+    device::Device,
+    state::{Error, Reader, Writer},
+};
 
 enum Mode {
     Rom,
@@ -9,7 +13,7 @@ enum Mode {
 #[rustfmt::skip]
 pub struct Mbc1 {
     rom: Box<[u8]>,
-    ram: Vec<[u8; 0x2000]>,
+    ram: Vec<u8>,
     rom_bank: usize,
     ram_bank: usize,
     ram_enable: bool,
@@ -20,7 +24,7 @@ impl Mbc1 {
     pub fn new(rom: Box<[u8]>) -> Self {
         let ram_banks = ram_banks(rom[0x149]);
         Self { rom,
-               ram: vec![[0; 0x2000]; ram_banks],
+               ram: vec![0; 0x2000 * ram_banks],
                rom_bank: 0,
                ram_bank: 0,
                ram_enable: false,
@@ -30,6 +34,10 @@ impl Mbc1 {
     fn rom_addr(&self, addr: usize) -> usize {
         0x4000 * self.rom_bank.max(1) + addr - 0x4000
     }
+
+    fn ram_addr(&self, addr: usize) -> usize {
+        0x2000 * self.ram_bank + addr - 0xa000
+    }
 }
 
 impl Device for Mbc1 {
@@ -42,10 +50,7 @@ impl Device for Mbc1 {
             }
             addr @ 0xa000..=0xbfff => {
                 if self.ram_enable {
-                    self.ram
-                        .get(self.ram_bank)
-                        .map(|bank| bank[addr - 0xa000])
-                        .unwrap_or(0)
+                    self.ram.get(self.ram_addr(addr)).copied().unwrap_or(0)
                 } else {
                     0
                 }
@@ -87,11 +92,43 @@ impl Device for Mbc1 {
                 }
             }
             addr @ 0xa000..=0xbfff => {
-                if let Some(bank) = self.ram.get_mut(self.ram_bank) {
-                    bank[addr as usize - 0xa000] = data
+                let addr = self.ram_addr(addr);
+                if let Some(byte) = self.ram.get_mut(addr) {
+                    *byte = data
                 }
             }
             _ => panic!(),
         }
     }
 }
+
+impl Cartridge for Mbc1 {
+    fn rom_bank(&self) -> usize {
+        self.rom_bank.max(1)
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state: &mut Writer) {
+        state.bytes(&self.ram);
+        state.u16(self.rom_bank as u16);
+        state.u8(self.ram_bank as u8);
+        state.bool(self.ram_enable);
+        state.bool(matches!(self.mode, Mode::Ram));
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        state.bytes(&mut self.ram)?;
+        self.rom_bank = state.u16()? as usize;
+        self.ram_bank = state.u8()? as usize;
+        self.ram_enable = state.bool()?;
+        self.mode = if state.bool()? { Mode::Ram } else { Mode::Rom };
+        Ok(())
+    }
+}
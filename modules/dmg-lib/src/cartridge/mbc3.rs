@@ -1,4 +1,8 @@
-use crate::{cartridge::ram_banks, device::Device};
+use crate::{
+    cartridge::{ram_banks, Cartridge},
+    device::Device,
+    state::{Error, Reader, Writer},
+};
 
 enum Mode {
     Ram,
@@ -8,7 +12,7 @@ enum Mode {
 /// MBC3 controller.
 pub struct Mbc3 {
     rom: Box<[u8]>,
-    ram: Vec<[u8; 0x2000]>,
+    ram: Vec<u8>,
     // The Clock Counter Registers
     // 08h  RTC S   Seconds   0-59 (0-3Bh)
     // 09h  RTC M   Minutes   0-59 (0-3Bh)
@@ -30,7 +34,7 @@ impl Mbc3 {
     pub fn new(rom: Box<[u8]>) -> Self {
         let ram_banks = ram_banks(rom[0x149]);
         Self { rom,
-               ram: vec![[0; 0x2000]; ram_banks],
+               ram: vec![0; 0x2000 * ram_banks],
                rtc: [0; 5],
                rtc_select: 0,
                rom_bank: 0,
@@ -42,6 +46,10 @@ impl Mbc3 {
     fn rom_addr(&self, addr: usize) -> usize {
         0x4000 * self.rom_bank.max(1) + addr - 0x4000
     }
+
+    fn ram_addr(&self, addr: usize) -> usize {
+        0x2000 * self.ram_bank + addr - 0xa000
+    }
 }
 
 impl Device for Mbc3 {
@@ -55,10 +63,7 @@ impl Device for Mbc3 {
             addr @ 0xa000..=0xbfff => {
                 if self.ram_timer_enabled {
                     match self.mode {
-                        Mode::Ram => self.ram
-                                         .get(self.ram_bank)
-                                         .map(|bank| bank[addr - 0xa000])
-                                         .unwrap_or(0),
+                        Mode::Ram => self.ram.get(self.ram_addr(addr)).copied().unwrap_or(0),
                         Mode::Rtc => self.rtc[self.rtc_select],
                     }
                 } else {
@@ -104,8 +109,9 @@ impl Device for Mbc3 {
                 if self.ram_timer_enabled {
                     match self.mode {
                         Mode::Ram => {
-                            if let Some(bank) = self.ram.get_mut(self.ram_bank) {
-                                bank[addr - 0xa000] = data
+                            let addr = self.ram_addr(addr);
+                            if let Some(byte) = self.ram.get_mut(addr) {
+                                *byte = data
                             }
                         }
                         Mode::Rtc => self.rtc[self.rtc_select] = data,
@@ -116,3 +122,41 @@ impl Device for Mbc3 {
         }
     }
 }
+
+impl Cartridge for Mbc3 {
+    fn rom_bank(&self) -> usize {
+        self.rom_bank.max(1)
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state: &mut Writer) {
+        state.bytes(&self.ram);
+        state.bytes(&self.rtc);
+        state.u8(self.rtc_select as u8);
+        state.u16(self.rom_bank as u16);
+        state.u8(self.ram_bank as u8);
+        state.bool(self.ram_timer_enabled);
+        state.bool(matches!(self.mode, Mode::Rtc));
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        state.bytes(&mut self.ram)?;
+        state.bytes(&mut self.rtc)?;
+        self.rtc_select = state.u8()? as usize;
+        if self.rtc_select >= self.rtc.len() {
+            return Err(Error::InvalidData);
+        }
+        self.rom_bank = state.u16()? as usize;
+        self.ram_bank = state.u8()? as usize;
+        self.ram_timer_enabled = state.bool()?;
+        self.mode = if state.bool()? { Mode::Rtc } else { Mode::Ram };
+        Ok(())
+    }
+}
@@ -1,9 +1,13 @@
-use crate::{cartridge::ram_banks, device::Device};
+use crate::{
+    cartridge::{ram_banks, Cartridge},
+    device::Device,
+    state::{Error, Reader, Writer},
+};
 
 /// MBC5 controller.
 pub struct Mbc5 {
     rom: Box<[u8]>,
-    ram: Vec<[u8; 0x2000]>,
+    ram: Vec<u8>,
     rom_bank: usize,
     ram_bank: usize,
     ram_enabled: bool,
@@ -13,7 +17,7 @@ impl Mbc5 {
     pub fn new(rom: Box<[u8]>) -> Self {
         let ram_banks = ram_banks(rom[0x149]);
         Self { rom,
-               ram: vec![[0; 0x2000]; ram_banks],
+               ram: vec![0; 0x2000 * ram_banks],
                rom_bank: 0,
                ram_bank: 0,
                ram_enabled: true }
@@ -22,6 +26,10 @@ impl Mbc5 {
     fn rom_addr(&self, addr: usize) -> usize {
         0x4000 * self.rom_bank + addr - 0x4000
     }
+
+    fn ram_addr(&self, addr: usize) -> usize {
+        0x2000 * self.ram_bank + addr - 0xa000
+    }
 }
 
 impl Device for Mbc5 {
@@ -34,11 +42,7 @@ impl Device for Mbc5 {
             }
             addr @ 0xa000..=0xbfff => {
                 if self.ram_enabled {
-                    if let Some(bank) = self.ram.get(self.ram_bank) {
-                        bank[addr - 0xa000]
-                    } else {
-                        0
-                    }
+                    self.ram.get(self.ram_addr(addr)).copied().unwrap_or(0)
                 } else {
                     0
                 }
@@ -69,8 +73,9 @@ impl Device for Mbc5 {
             0x6000..=0x7fff => { /* read-only */ }
             addr @ 0xa000..=0xbfff => {
                 if self.ram_enabled {
-                    if let Some(bank) = self.ram.get_mut(self.ram_bank) {
-                        bank[addr - 0xa000] = data;
+                    let addr = self.ram_addr(addr);
+                    if let Some(byte) = self.ram.get_mut(addr) {
+                        *byte = data;
                     }
                 }
             }
@@ -78,3 +83,32 @@ impl Device for Mbc5 {
         }
     }
 }
+
+impl Cartridge for Mbc5 {
+    fn rom_bank(&self) -> usize {
+        self.rom_bank
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    fn save_state(&self, state: &mut Writer) {
+        state.bytes(&self.ram);
+        state.u16(self.rom_bank as u16);
+        state.u8(self.ram_bank as u8);
+        state.bool(self.ram_enabled);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        state.bytes(&mut self.ram)?;
+        self.rom_bank = state.u16()? as usize;
+        self.ram_bank = state.u8()? as usize;
+        self.ram_enabled = state.bool()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,8 @@
-use crate::device::Device;
+use crate::{
+    cartridge::Cartridge,
+    device::Device,
+    state::{Error, Reader, Writer},
+};
 
 pub struct Rom {
     rom: Box<[u8]>,
@@ -29,3 +33,13 @@ impl Device for Rom {
         }
     }
 }
+
+impl Cartridge for Rom {
+    fn save_state(&self, state: &mut Writer) {
+        state.bytes(&self.ram[..]);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        state.bytes(&mut self.ram[..])
+    }
+}
@@ -0,0 +1,273 @@
+//! Code/data logging.
+//!
+//! Records how each byte of the cartridge ROM is accessed: executed as an
+//! opcode or as an operand, read as data, or used as the source of a DMA
+//! transfer. The log is stored (and exported) as one byte of flags per ROM
+//! byte, so logs of different runs can be merged to measure coverage.
+//!
+//! The log is used to disassemble the ROM, telling the code and the data apart
+//! (see [`disassemble`]).
+//!
+//! [`disassemble`]: #
+use crate::{device::Device, disasm, symbols::Symbols};
+use std::{
+    cell::Cell,
+    io::{self, Write},
+};
+
+/// Executed as the opcode of an instruction.
+pub const CODE: u8 = 0x01;
+/// Executed as an operand of an instruction.
+pub const OPERAND: u8 = 0x02;
+/// Read as data.
+pub const DATA: u8 = 0x04;
+/// Read by an OAM or VRAM DMA transfer.
+pub const DMA: u8 = 0x08;
+
+// Size of a ROM bank.
+const BANK_SIZE: usize = 0x4000;
+
+// Shortest run of unlogged identical bytes disassembled as a `ds` directive.
+const PADDING: usize = 16;
+
+/// Log of the accesses to the cartridge ROM (see [`Mmu::start_cdl`]).
+///
+/// [`Mmu::start_cdl`]: #
+#[derive(Debug, Clone)]
+pub struct CodeDataLog {
+    flags: Box<[Cell<u8>]>,
+    // Set while the CPU executes an instruction.
+    active: bool,
+}
+
+impl CodeDataLog {
+    /// Empty log for a ROM of the given size.
+    pub fn new(len: usize) -> Self {
+        Self { flags: vec![Cell::new(0); len].into_boxed_slice(),
+               active: false }
+    }
+
+    /// Log from the bytes of a CDL file (one byte of flags per ROM byte).
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self { flags: bytes.iter().copied().map(Cell::new).collect(),
+               active: false }
+    }
+
+    /// Return the bytes of the CDL file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.flags.iter().map(Cell::get).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    /// Flags of the byte at the given ROM offset.
+    pub fn get(&self, offset: usize) -> u8 {
+        self.flags.get(offset).map(Cell::get).unwrap_or(0)
+    }
+
+    /// Number of ROM bytes with any of the given flags.
+    pub fn count(&self, flags: u8) -> usize {
+        self.flags
+            .iter()
+            .filter(|flag| flag.get() & flags != 0)
+            .count()
+    }
+
+    /// Add the flags of another log (of a ROM of the same size).
+    pub fn merge(&mut self, other: &CodeDataLog) {
+        for (flag, other) in self.flags.iter().zip(other.flags.iter()) {
+            flag.set(flag.get() | other.get());
+        }
+    }
+
+    // Called before the CPU executes an instruction.
+    pub(crate) fn begin(&mut self) {
+        self.active = true;
+    }
+
+    // Called after the CPU has executed an instruction.
+    pub(crate) fn end(&mut self) {
+        self.active = false;
+    }
+
+    // Called by the MMU on every access to the ROM. Data reads are only logged
+    // while the CPU executes an instruction, and not when other components (or
+    // the tools) read the memory.
+    pub(crate) fn mark(&self, offset: usize, flags: u8) {
+        let flags = if self.active { flags } else { flags & !DATA };
+        if let Some(flag) = self.flags.get(offset) {
+            flag.set(flag.get() | flags);
+        }
+    }
+}
+
+// A ROM bank, mapped to 0000-3FFF (bank 0) or 4000-7FFF.
+struct Bank<'a> {
+    rom: &'a [u8],
+    bank: usize,
+}
+
+impl Device for Bank<'_> {
+    fn read(&self, addr: u16) -> u8 {
+        let offset = match addr {
+            0x0000..=0x3fff => usize::from(addr),
+            0x4000..=0x7fff => self.bank * BANK_SIZE + usize::from(addr) - 0x4000,
+            _ => return 0xff,
+        };
+        self.rom.get(offset).copied().unwrap_or(0xff)
+    }
+
+    fn write(&mut self, _: u16, _: u8) {}
+}
+
+/// Disassemble a ROM in the RGBDS syntax. The bytes logged as opcodes are
+/// disassembled as instructions, the rest are written as `db` (or `ds`)
+/// directives, commented with how they were accessed.
+pub fn disassemble<W: Write>(rom: &[u8],
+                             cdl: &CodeDataLog,
+                             symbols: Option<&Symbols>,
+                             mut out: W)
+                             -> io::Result<()> {
+    for (bank, chunk) in rom.chunks(BANK_SIZE).enumerate() {
+        let mem = Bank { rom, bank };
+        let base = bank * BANK_SIZE;
+        let start = if bank == 0 { 0x0000 } else { 0x4000 };
+        let addr = |i: usize| (start + i) as u16;
+
+        if bank == 0 {
+            writeln!(out, "SECTION \"ROM Bank $000\", ROM0[$0000]")?;
+        } else {
+            writeln!(out)?;
+            writeln!(out,
+                     "SECTION \"ROM Bank ${:03x}\", ROMX[$4000], BANK[${:x}]",
+                     bank, bank)?;
+        }
+
+        let mut i = 0;
+        while i < chunk.len() {
+            if let Some(label) = symbols.and_then(|symbols| symbols.label(bank, addr(i))) {
+                writeln!(out, "{}:", label)?;
+            }
+            let flags = cdl.get(base + i);
+            if flags & CODE != 0 {
+                let instr = disasm::decode(&mem, addr(i));
+                // the WRAM bank isn't known ahead of time, so bank 1 is assumed
+                let text = match symbols {
+                    Some(symbols) => instr.with_symbols(symbols, bank, 1).to_string(),
+                    None => instr.to_string(),
+                };
+                writeln!(out, "    {:<28}; {:02x}:{:04x}", text, bank, addr(i))?;
+                i += usize::from(instr.size());
+                continue;
+            }
+
+            // runs of data bytes with the same flags, not broken by a label
+            let same = |j: usize| {
+                cdl.get(base + j) == flags
+                && symbols.and_then(|symbols| symbols.label(bank, addr(j)))
+                          .is_none()
+            };
+            let fill = (i + 1..chunk.len()).take_while(|&j| same(j) && chunk[j] == chunk[i])
+                                           .count()
+                       + 1;
+            if flags == 0 && fill >= PADDING {
+                writeln!(out,
+                         "    {:<28}; {:02x}:{:04x}",
+                         format!("ds {}, ${:02x}", fill, chunk[i]),
+                         bank,
+                         addr(i))?;
+                i += fill;
+                continue;
+            }
+            let len = (i + 1..chunk.len()).take(7)
+                                          .take_while(|&j| same(j))
+                                          .count()
+                      + 1;
+            let bytes: Vec<_> = chunk[i..i + len].iter()
+                                                 .map(|b| format!("${:02x}", b))
+                                                 .collect();
+            let mut comment = format!("{:02x}:{:04x}", bank, addr(i));
+            for (flag, kind) in &[(OPERAND, "operand"), (DATA, "data"), (DMA, "dma")] {
+                if flags & flag != 0 {
+                    comment.push(' ');
+                    comment.push_str(kind);
+                }
+            }
+            writeln!(out,
+                     "    {:<28}; {}",
+                     format!("db {}", bytes.join(", ")),
+                     comment)?;
+            i += len;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        cartridge::Rom,
+        cdl::{self, CodeDataLog},
+        device::Device,
+        symbols::Symbols,
+        Builder,
+    };
+
+    #[test]
+    fn log() {
+        let mut rom = vec![0; 0x8000];
+        // 0100: LD A,($0150)
+        // 0103: JR $0100
+        rom[0x0100..0x0105].copy_from_slice(&[0xfa, 0x50, 0x01, 0x18, 0xfb]);
+        rom[0x0150] = 0x42;
+        let mut dmg = Builder::default().cartridge(Rom::new(rom.clone().into_boxed_slice()))
+                                        .build();
+
+        dmg.mmu_mut().start_cdl(CodeDataLog::new(rom.len()));
+        for _ in 0..4 {
+            dmg.step_instruction();
+        }
+        // OAM DMA from 0200-029F
+        dmg.mmu_mut().write(0xff46, 0x02);
+        // not read by the CPU
+        dmg.mmu().read(0x0160);
+        let log = dmg.mmu_mut().stop_cdl().unwrap();
+
+        assert_eq!(cdl::CODE, log.get(0x0100));
+        assert_eq!(cdl::OPERAND, log.get(0x0101));
+        assert_eq!(cdl::OPERAND, log.get(0x0102));
+        assert_eq!(cdl::CODE, log.get(0x0103));
+        assert_eq!(cdl::OPERAND, log.get(0x0104));
+        assert_eq!(cdl::DATA, log.get(0x0150));
+        assert_eq!(0, log.get(0x0105));
+        assert_eq!(0, log.get(0x0160));
+        assert_eq!(0xa0, log.count(cdl::DMA));
+        assert_eq!(log.to_bytes(),
+                   CodeDataLog::from_bytes(&log.to_bytes()).to_bytes());
+
+        let mut symbols = Symbols::new();
+        symbols.parse_sym("00:0100 Main\n00:0150 Value\n").unwrap();
+        let mut text = Vec::new();
+        cdl::disassemble(&rom, &log, Some(&symbols), &mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+
+        assert_eq!("SECTION \"ROM Bank $000\", ROM0[$0000]", lines[0]);
+        assert_eq!("    ds 256, $00                 ; 00:0000", lines[1]);
+        assert_eq!(&["Main:",
+                     "    ld a, [Value]               ; 00:0100",
+                     "    jr Main                     ; 00:0103",
+                     "    ds 75, $00                  ; 00:0105",
+                     "Value:",
+                     "    db $42                      ; 00:0150 data"],
+                   &lines[2..8]);
+        assert_eq!("    db $00, $00, $00, $00, $00, $00, $00, $00; 00:0200 dma",
+                   lines[9]);
+    }
+}
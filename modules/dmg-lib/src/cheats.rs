@@ -0,0 +1,287 @@
+//! GameShark & Game Genie cheats.
+//!
+//! - GameShark codes (`01VVAAAA`) write a value to RAM at the start of every
+//!   frame. Codes of type `9x` write to the WRAM bank `x` (D000-DFFF).
+//! - Game Genie codes (`VVA-AAA-CCC`) replace a byte of the ROM when it is
+//!   read, if it matches the compare byte (if any).
+//!
+//! ```
+//! use dmg_lib::cheats::{Cheats, Code};
+//!
+//! let mut cheats = Cheats::new();
+//! let id = cheats.add("010F31C1").unwrap();
+//!
+//! assert_eq!(Code::GameShark { bank: None,
+//!                              addr: 0xc131,
+//!                              value: 0x0f },
+//!            cheats.get(id).unwrap().code);
+//! ```
+use std::{fmt, str::FromStr};
+
+/// Identifies a cheat.
+pub type Id = usize;
+
+/// Error parsing a cheat code.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// Not a GameShark or Game Genie code.
+    Format,
+    /// Unsupported GameShark code type.
+    Type(u8),
+    /// The address can't be patched by the code (RAM for GameShark codes, ROM
+    /// for Game Genie codes).
+    Address(u16),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Format => write!(f, "Invalid cheat code"),
+            Error::Type(ty) => write!(f, "Unsupported GameShark code type ({:02X})", ty),
+            Error::Address(addr) => write!(f, "Invalid cheat address ({:04X})", addr),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A decoded cheat code.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Code {
+    /// Write `value` to `addr` every frame. The WRAM `bank` is only set for
+    /// addresses in D000-DFFF.
+    GameShark {
+        bank: Option<usize>,
+        addr: u16,
+        value: u8,
+    },
+    /// Read `value` in place of the ROM byte at `addr`, if it is `compare`.
+    GameGenie {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+}
+
+impl FromStr for Code {
+    type Err = Error;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        let code = code.trim();
+        let digits: Vec<u8> = code.chars()
+                                  .filter(|c| *c != '-')
+                                  .map(|c| c.to_digit(16).map(|d| d as u8))
+                                  .collect::<Option<_>>()
+                                  .ok_or(Error::Format)?;
+        let byte = |i: usize| (digits[i] << 4) | digits[i + 1];
+        match digits.len() {
+            // ABCDEFGH: type (AB), value (CD) & address (GHEF)
+            8 if !code.contains('-') => {
+                let (ty, value) = (byte(0), byte(2));
+                let addr = u16::from_le_bytes([byte(4), byte(6)]);
+                let bank = match ty {
+                    0x00 | 0x01 => None,
+                    0x90..=0x97 if (0xd000..=0xdfff).contains(&addr) => {
+                        Some(usize::from(ty & 0x7).max(1))
+                    }
+                    0x90..=0x97 => return Err(Error::Address(addr)),
+                    _ => return Err(Error::Type(ty)),
+                };
+                if !(0xa000..=0xdfff).contains(&addr) {
+                    return Err(Error::Address(addr));
+                }
+                Ok(Code::GameShark { bank, addr, value })
+            }
+            // ABC-DEF(-GHI): value (AB), address (FCDE xor F000) & compare (GI
+            // rotated right by 2, xor BA). H is ignored.
+            6 | 9 => {
+                let addr = u16::from(digits[2]) << 8
+                           | u16::from(digits[3]) << 4
+                           | u16::from(digits[4])
+                           | u16::from(digits[5] ^ 0xf) << 12;
+                if addr > 0x7fff {
+                    return Err(Error::Address(addr));
+                }
+                let compare = match digits.len() {
+                    9 => Some(((digits[6] << 4) | digits[8]).rotate_right(2) ^ 0xba),
+                    _ => None,
+                };
+                Ok(Code::GameGenie { addr,
+                                     value: byte(0),
+                                     compare })
+            }
+            _ => Err(Error::Format),
+        }
+    }
+}
+
+/// A cheat.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Cheat {
+    pub code: Code,
+    /// The code, as entered.
+    pub text: String,
+    pub enabled: bool,
+}
+
+/// Table of cheats (see [`Mmu::cheats_mut`]).
+///
+/// [`Mmu::cheats_mut`]: #
+#[derive(Debug, Default, Clone)]
+pub struct Cheats {
+    next_id: Id,
+    cheats: Vec<(Id, Cheat)>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cheats.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    /// Decode and add a cheat (enabled).
+    pub fn add(&mut self, text: &str) -> Result<Id, Error> {
+        let code = text.parse()?;
+        let id = self.next_id;
+        self.next_id += 1;
+        self.cheats.push((id,
+                          Cheat { code,
+                                  text: text.trim().to_uppercase(),
+                                  enabled: true }));
+        Ok(id)
+    }
+
+    /// Remove a cheat. Returns `false` if it doesn't exist.
+    pub fn remove(&mut self, id: Id) -> bool {
+        let len = self.cheats.len();
+        self.cheats.retain(|(i, _)| *i != id);
+        self.cheats.len() != len
+    }
+
+    /// Remove all the cheats.
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    /// Enable or disable a cheat. Returns `false` if it doesn't exist.
+    pub fn set_enabled(&mut self, id: Id, enabled: bool) -> bool {
+        match self.cheats.iter_mut().find(|(i, _)| *i == id) {
+            Some((_, cheat)) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, id: Id) -> Option<&Cheat> {
+        self.cheats
+            .iter()
+            .find(|(i, _)| *i == id)
+            .map(|(_, cheat)| cheat)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Id, &Cheat)> {
+        self.cheats.iter().map(|(id, cheat)| (*id, cheat))
+    }
+
+    // Apply the Game Genie codes to a byte read from the ROM.
+    pub(crate) fn patch(&self, addr: u16, data: u8) -> u8 {
+        for (_, cheat) in &self.cheats {
+            match cheat.code {
+                Code::GameGenie { addr: a,
+                                  value,
+                                  compare, }
+                    if cheat.enabled && a == addr && compare.unwrap_or(data) == data =>
+                {
+                    return value
+                }
+                _ => {}
+            }
+        }
+        data
+    }
+
+    // Writes of the enabled GameShark codes: (WRAM bank, address, value).
+    pub(crate) fn writes(&self) -> impl Iterator<Item = (Option<usize>, u16, u8)> + '_ {
+        self.cheats
+            .iter()
+            .filter_map(|(_, cheat)| match cheat.code {
+                Code::GameShark { bank, addr, value } if cheat.enabled => Some((bank, addr, value)),
+                _ => None,
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        cartridge::Rom,
+        cheats::{Code, Error},
+        device::Device,
+        Builder,
+    };
+
+    #[test]
+    fn decode() {
+        assert_eq!(Ok(Code::GameShark { bank: None,
+                                        addr: 0xc0de,
+                                        value: 0x63 }),
+                   "0163DEC0".parse());
+        assert_eq!(Ok(Code::GameShark { bank: Some(3),
+                                        addr: 0xd000,
+                                        value: 0xff }),
+                   "93FF00D0".parse());
+        assert_eq!(Ok(Code::GameGenie { addr: 0x4abc,
+                                        value: 0xc3,
+                                        compare: Some(0x00) }),
+                   "C3A-BCB-E6A".parse());
+        assert_eq!(Ok(Code::GameGenie { addr: 0x0150,
+                                        value: 0x99,
+                                        compare: None }),
+                   "991-50F".parse());
+
+        assert_eq!(Err(Error::Format), "0163DEC".parse::<Code>());
+        assert_eq!(Err(Error::Format), "0163DEXX".parse::<Code>());
+        assert_eq!(Err(Error::Type(0x42)), "4263DEC0".parse::<Code>());
+        assert_eq!(Err(Error::Address(0x8000)), "01630080".parse::<Code>());
+        assert_eq!(Err(Error::Address(0x9abc)), "C3A-BC6-E6A".parse::<Code>());
+    }
+
+    #[test]
+    fn cheats() {
+        let mut rom = vec![0; 0x8000];
+        // 0100: LD A,($0150)
+        // 0103: LD ($C000),A
+        // 0106: JR $0100
+        rom[0x0100..0x0108].copy_from_slice(&[0xfa, 0x50, 0x01, 0xea, 0x00, 0xc0, 0x18, 0xf8]);
+        rom[0x0150] = 0x42;
+        let mut dmg = Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                                        .build();
+
+        let cheats = dmg.mmu_mut().cheats_mut();
+        let genie = cheats.add("991-50F-EA3").unwrap();
+        let shark = cheats.add("015501C0").unwrap();
+        // doesn't match the compare byte
+        cheats.add("771-50F-FA3").unwrap();
+
+        dmg.emulate_frame();
+        assert_eq!(0x99, dmg.mmu().read(0xc000));
+        assert_eq!(0x55, dmg.mmu().read(0xc001));
+
+        let cheats = dmg.mmu_mut().cheats_mut();
+        assert!(cheats.set_enabled(genie, false));
+        assert!(cheats.remove(shark));
+        dmg.mmu_mut().write(0xc001, 0);
+        dmg.emulate_frame();
+        assert_eq!(0x42, dmg.mmu().read(0xc000));
+        assert_eq!(0x00, dmg.mmu().read(0xc001));
+    }
+}
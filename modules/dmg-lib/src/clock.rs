@@ -1,3 +1,5 @@
+use crate::state::{Error, Reader, State, Writer};
+
 /// To generate ticks at specific given rate, given a base clock rate.
 pub struct Clock {
     base: u64,
@@ -24,6 +26,26 @@ impl Clock {
     }
 }
 
+impl State for Clock {
+    fn save_state(&self, state: &mut Writer) {
+        state.u64(self.base);
+        state.u64(self.freq);
+        state.u64(self.tick);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        let base = state.u64()?;
+        let freq = state.u64()?;
+        if freq > base {
+            return Err(Error::InvalidData);
+        }
+        self.base = base;
+        self.freq = freq;
+        self.tick = state.u64()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::clock::Clock;
@@ -5,6 +5,7 @@ use crate::{
     device::Device,
     mmu::Mmu,
     ppu::Video,
+    state::{Error, Reader, State, Writer},
 };
 
 pub mod registers;
@@ -46,6 +47,37 @@ impl Default for Cpu {
     }
 }
 
+impl State for Cpu {
+    fn save_state(&self, state: &mut Writer) {
+        let reg = &self.reg;
+        state.bytes(&[reg.a, reg.f, reg.b, reg.c, reg.d, reg.e, reg.h, reg.l]);
+        state.u16(reg.pc);
+        state.u16(reg.sp);
+        state.bool(self.ime);
+        state.bool(self.halt);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        let mut data = [0; 8];
+        state.bytes(&mut data)?;
+        let reg = &mut self.reg;
+        let [a, f, b, c, d, e, h, l] = data;
+        reg.a = a;
+        reg.f = f;
+        reg.b = b;
+        reg.c = c;
+        reg.d = d;
+        reg.e = e;
+        reg.h = h;
+        reg.l = l;
+        reg.pc = state.u16()?;
+        reg.sp = state.u16()?;
+        self.ime = state.bool()?;
+        self.halt = state.bool()?;
+        Ok(())
+    }
+}
+
 impl Cpu {
     pub fn reg(&self) -> &Registers {
         &self.reg
@@ -63,15 +95,21 @@ impl Cpu {
         self.halt
     }
 
+    fn fetch_opcode<C: Cartridge, V: Video, D: Audio>(&mut self, mmu: &Mmu<C, V, D>) -> u8 {
+        let b = mmu.fetch(self.reg.pc, true);
+        self.reg.pc += 1;
+        b
+    }
+
     fn fetch<C: Cartridge, V: Video, D: Audio>(&mut self, mmu: &Mmu<C, V, D>) -> u8 {
-        let b = mmu.read(self.reg.pc);
+        let b = mmu.fetch(self.reg.pc, false);
         self.reg.pc += 1;
         b
     }
 
     fn fetch_word<C: Cartridge, V: Video, D: Audio>(&mut self, mmu: &Mmu<C, V, D>) -> u16 {
-        let lo = mmu.read(self.reg.pc) as u16;
-        let hi = mmu.read(self.reg.pc + 1) as u16;
+        let lo = mmu.fetch(self.reg.pc, false) as u16;
+        let hi = mmu.fetch(self.reg.pc + 1, false) as u16;
         self.reg.pc += 2;
         (hi << 8) | lo
     }
@@ -442,6 +480,7 @@ impl Cpu {
         let c = if int != 0 {
             int
         } else if !self.halt {
+            mmu.trace_instruction(self);
             self.exec(mmu)
         } else {
             CYCLES[0x0]
@@ -471,7 +510,7 @@ impl Cpu {
     }
 
     fn exec<C: Cartridge, V: Video, D: Audio>(&mut self, mmu: &mut Mmu<C, V, D>) -> u64 {
-        let opcode = self.fetch(mmu);
+        let opcode = self.fetch_opcode(mmu);
         let mut branch = false;
 
         match opcode {
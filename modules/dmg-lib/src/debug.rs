@@ -0,0 +1,451 @@
+//! Breakpoints & watchpoints.
+//!
+//! A [`Debugger`] attached to the emulator (see [`Mmu::attach_debugger`])
+//! stops the emulation after the instruction that triggers one of its
+//! breakpoints or watchpoints. The [`Hit`] is reported in the [`Step`] returned
+//! by the stepping methods (and [`GameBoy::emulate_frame`]).
+//!
+//! Only the memory accesses of the CPU are watched. Accesses made by the DMA
+//! transfers, or through the [`Mmu`] by the user, are not.
+//!
+//! [`Debugger`]: #
+//! [`Mmu::attach_debugger`]: #
+//! [`Hit`]: #
+//! [`Step`]: #
+//! [`GameBoy::emulate_frame`]: #
+//! [`Mmu`]: #
+use crate::{apu::device::Audio, cartridge::Cartridge, cpu::Cpu, mmu::Mmu, ppu::Video};
+use std::{cell::RefCell, ops::RangeInclusive};
+
+/// Identifies a breakpoint or a watchpoint.
+pub type Id = usize;
+
+/// Kind of memory access.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    /// The instruction at the address is about to be executed.
+    Execute,
+}
+
+/// CPU registers, for conditions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Reg {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+}
+
+/// Comparison operators.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Cmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    fn eval(self, lhs: u16, rhs: u16) -> bool {
+        match self {
+            Cmp::Eq => lhs == rhs,
+            Cmp::Ne => lhs != rhs,
+            Cmp::Lt => lhs < rhs,
+            Cmp::Le => lhs <= rhs,
+            Cmp::Gt => lhs > rhs,
+            Cmp::Ge => lhs >= rhs,
+        }
+    }
+}
+
+/// Condition that must hold for a breakpoint or a watchpoint to trigger. It is
+/// evaluated once the instruction has been executed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Condition {
+    /// Compare the value of a register.
+    Reg(Reg, Cmp, u16),
+    /// Compare the byte at the given address.
+    Mem(u16, Cmp, u8),
+    /// Compare the byte read or written by the triggering access.
+    Value(Cmp, u8),
+}
+
+/// Stops the emulation when the CPU is about to execute the instruction at a
+/// given address.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Breakpoint {
+    pub addr: u16,
+    /// For addresses in the ROM area, only trigger when the given ROM bank is
+    /// mapped (bank 0 for addresses 0000-3FFF).
+    pub bank: Option<usize>,
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    pub fn new(addr: u16) -> Self {
+        Self { addr,
+               bank: None,
+               condition: None }
+    }
+
+    pub fn bank(mut self, bank: usize) -> Self {
+        self.bank = Some(bank);
+        self
+    }
+
+    pub fn condition(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+}
+
+/// Stops the emulation when the CPU accesses an address range.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub condition: Option<Condition>,
+}
+
+impl Watchpoint {
+    /// Watchpoint that doesn't trigger on any kind of access.
+    pub fn new(range: RangeInclusive<u16>) -> Self {
+        Self { range,
+               read: false,
+               write: false,
+               execute: false,
+               condition: None }
+    }
+
+    /// Trigger when the range is read.
+    pub fn read(mut self) -> Self {
+        self.read = true;
+        self
+    }
+
+    /// Trigger when the range is written.
+    pub fn write(mut self) -> Self {
+        self.write = true;
+        self
+    }
+
+    /// Trigger when an instruction in the range is about to be executed.
+    pub fn execute(mut self) -> Self {
+        self.execute = true;
+        self
+    }
+
+    pub fn condition(mut self, condition: Condition) -> Self {
+        self.condition = Some(condition);
+        self
+    }
+
+    fn watches(&self, access: Access, addr: u16) -> bool {
+        let kind = match access {
+            Access::Read => self.read,
+            Access::Write => self.write,
+            Access::Execute => self.execute,
+        };
+        kind && self.range.contains(&addr)
+    }
+}
+
+/// A triggered breakpoint or watchpoint.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Hit {
+    /// The breakpoint or watchpoint.
+    pub id: Id,
+    /// Address of the instruction that made the access.
+    pub pc: u16,
+    pub access: Access,
+    /// Accessed address.
+    pub addr: u16,
+    /// Byte read or written (the opcode, on execution).
+    pub value: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MemAccess {
+    access: Access,
+    addr: u16,
+    value: u8,
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    next_id: Id,
+    breakpoints: Vec<(Id, Breakpoint)>,
+    watchpoints: Vec<(Id, Watchpoint)>,
+    // Set while the CPU executes an instruction.
+    active: bool,
+    // Watched accesses of the instruction being executed.
+    accesses: RefCell<Vec<MemAccess>>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> Id {
+        let id = self.next_id();
+        self.breakpoints.push((id, breakpoint));
+        id
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> Id {
+        let id = self.next_id();
+        self.watchpoints.push((id, watchpoint));
+        id
+    }
+
+    /// Remove a breakpoint or a watchpoint. Returns false if it didn't exist.
+    pub fn remove(&mut self, id: Id) -> bool {
+        let len = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|(i, _)| *i != id);
+        self.watchpoints.retain(|(i, _)| *i != id);
+        len != self.breakpoints.len() + self.watchpoints.len()
+    }
+
+    /// Remove all the breakpoints and watchpoints.
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (Id, &Breakpoint)> {
+        self.breakpoints.iter().map(|(id, b)| (*id, b))
+    }
+
+    pub fn watchpoints(&self) -> impl Iterator<Item = (Id, &Watchpoint)> {
+        self.watchpoints.iter().map(|(id, w)| (*id, w))
+    }
+
+    fn next_id(&mut self) -> Id {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    // Called by the MMU on every read & write.
+    pub(crate) fn access(&self, access: Access, addr: u16, value: u8) {
+        if self.active
+           && self.watchpoints
+                  .iter()
+                  .any(|(_, w)| w.watches(access, addr))
+        {
+            self.accesses.borrow_mut().push(MemAccess { access,
+                                                        addr,
+                                                        value });
+        }
+    }
+
+    // Called before the CPU executes an instruction.
+    pub(crate) fn begin(&mut self) {
+        self.active = true;
+        self.accesses.get_mut().clear();
+    }
+
+    // Called after the instruction at `pc` has been executed. Returns the first
+    // breakpoint or watchpoint triggered by it, or by the next instruction.
+    pub(crate) fn check<C: Cartridge, V: Video, D: Audio>(&mut self,
+                                                          pc: u16,
+                                                          cpu: &Cpu,
+                                                          mmu: &Mmu<C, V, D>)
+                                                          -> Option<Hit> {
+        self.active = false;
+        for acc in self.accesses.get_mut().iter() {
+            for (id, w) in &self.watchpoints {
+                if w.watches(acc.access, acc.addr) && eval(w.condition, acc.value, cpu, mmu) {
+                    return Some(Hit { id: *id,
+                                      pc,
+                                      access: acc.access,
+                                      addr: acc.addr,
+                                      value: acc.value });
+                }
+            }
+        }
+
+        // a halted CPU isn't about to execute anything
+        if cpu.halt() {
+            return None;
+        }
+        let next = cpu.reg().pc;
+        let opcode = mmu.peek(next);
+        let bank = match next {
+            0x0000..=0x3fff => Some(0),
+            0x4000..=0x7fff => Some(mmu.cartridge().rom_bank()),
+            _ => None,
+        };
+        let hit = |id| {
+            Some(Hit { id,
+                       pc: next,
+                       access: Access::Execute,
+                       addr: next,
+                       value: opcode })
+        };
+        for (id, b) in &self.breakpoints {
+            if b.addr == next
+               && (b.bank.is_none() || bank.is_none() || b.bank == bank)
+               && eval(b.condition, opcode, cpu, mmu)
+            {
+                return hit(*id);
+            }
+        }
+        for (id, w) in &self.watchpoints {
+            if w.watches(Access::Execute, next) && eval(w.condition, opcode, cpu, mmu) {
+                return hit(*id);
+            }
+        }
+        None
+    }
+}
+
+fn eval<C: Cartridge, V: Video, D: Audio>(condition: Option<Condition>,
+                                          value: u8,
+                                          cpu: &Cpu,
+                                          mmu: &Mmu<C, V, D>)
+                                          -> bool {
+    let reg = cpu.reg();
+    match condition {
+        None => true,
+        Some(Condition::Value(cmp, rhs)) => cmp.eval(value.into(), rhs.into()),
+        Some(Condition::Mem(addr, cmp, rhs)) => cmp.eval(mmu.peek(addr).into(), rhs.into()),
+        Some(Condition::Reg(r, cmp, rhs)) => {
+            let lhs = match r {
+                Reg::A => reg.a.into(),
+                Reg::F => reg.f.into(),
+                Reg::B => reg.b.into(),
+                Reg::C => reg.c.into(),
+                Reg::D => reg.d.into(),
+                Reg::E => reg.e.into(),
+                Reg::H => reg.h.into(),
+                Reg::L => reg.l.into(),
+                Reg::AF => reg.af(),
+                Reg::BC => reg.bc(),
+                Reg::DE => reg.de(),
+                Reg::HL => reg.hl(),
+                Reg::SP => reg.sp,
+                Reg::PC => reg.pc,
+            };
+            cmp.eval(lhs, rhs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        cartridge::Rom,
+        debug::{Access, Breakpoint, Cmp, Condition, Debugger, Hit, Reg, Watchpoint},
+        device::Device,
+        Builder, GameBoy,
+    };
+
+    fn build() -> GameBoy<Rom, (), ()> {
+        let mut rom = vec![0; 0x8000];
+        // 0100: LD HL,$C000
+        // 0103: INC A
+        // 0104: LD (HL),A
+        // 0105: INC L
+        // 0106: JR $0103
+        rom[0x0100..0x0108].copy_from_slice(&[0x21, 0x00, 0xc0, 0x3c, 0x77, 0x2c, 0x18, 0xfb]);
+        Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                          .build()
+    }
+
+    #[test]
+    fn breakpoint() {
+        let mut dmg = build();
+        let mut debugger = Debugger::new();
+        let id = debugger.add_breakpoint(Breakpoint::new(0x0104));
+        dmg.mmu_mut().attach_debugger(debugger);
+
+        let step = dmg.emulate_frame();
+        assert!(!step.frame);
+        assert_eq!(Some(Hit { id,
+                              pc: 0x0104,
+                              access: Access::Execute,
+                              addr: 0x0104,
+                              value: 0x77 }),
+                   step.hit);
+        assert_eq!(0x0104, dmg.cpu().reg().pc);
+
+        // resume
+        let a = dmg.cpu().reg().a;
+        assert!(dmg.emulate_frame().hit.is_some());
+        assert_eq!(a.wrapping_add(1), dmg.cpu().reg().a);
+
+        let debugger = dmg.mmu_mut().debugger_mut().unwrap();
+        assert!(debugger.remove(id));
+        assert!(!debugger.remove(id));
+        let step = dmg.emulate_frame();
+        assert!(step.frame);
+        assert_eq!(None, step.hit);
+    }
+
+    #[test]
+    fn condition() {
+        let mut dmg = build();
+        let mut debugger = Debugger::new();
+        let bp = Breakpoint::new(0x0104).condition(Condition::Reg(Reg::A, Cmp::Eq, 0x10));
+        debugger.add_breakpoint(bp);
+        // never hit (ROM bank 0)
+        debugger.add_breakpoint(Breakpoint::new(0x0103).bank(1));
+        dmg.mmu_mut().attach_debugger(debugger);
+
+        assert!(dmg.emulate_frame().hit.is_some());
+        assert_eq!(0x10, dmg.cpu().reg().a);
+        assert_eq!(0x0104, dmg.cpu().reg().pc);
+    }
+
+    #[test]
+    fn watchpoint() {
+        let mut dmg = build();
+        let mut debugger = Debugger::new();
+        let id = debugger.add_watchpoint(Watchpoint::new(0xc010..=0xc01f).write());
+        debugger.add_watchpoint(Watchpoint::new(0xc000..=0xc0ff).read());
+        dmg.mmu_mut().attach_debugger(debugger);
+
+        let step = dmg.emulate_frame();
+        assert_eq!(Some(Hit { id,
+                              pc: 0x0104,
+                              access: Access::Write,
+                              addr: 0xc010,
+                              value: dmg.cpu().reg().a }),
+                   step.hit);
+        assert_eq!(0x0105, dmg.cpu().reg().pc);
+
+        let debugger = dmg.mmu_mut().debugger_mut().unwrap();
+        debugger.clear();
+        let value = Condition::Value(Cmp::Gt, 0x80);
+        let id = debugger.add_watchpoint(Watchpoint::new(0xc000..=0xc0ff).write().condition(value));
+        let hit = dmg.emulate_frame().hit.unwrap();
+        assert_eq!(id, hit.id);
+        assert_eq!(0x81, hit.value);
+
+        // accesses outside of the CPU are not watched
+        let debugger = dmg.mmu_mut().debugger_mut().unwrap();
+        debugger.clear();
+        debugger.add_watchpoint(Watchpoint::new(0xff80..=0xfffe).read().write());
+        dmg.mmu_mut().write(0xff80, 0x42);
+        assert_eq!(0x42, dmg.mmu().read(0xff80));
+        assert_eq!(None, dmg.step_instruction().hit);
+        assert!(dmg.mmu_mut().detach_debugger().is_some());
+    }
+}
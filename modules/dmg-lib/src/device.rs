@@ -36,6 +36,19 @@ impl Device for () {
     fn write(&mut self, _: u16, _: u8) {}
 }
 
+// Flat memory, starting at address 0000. Out of bounds reads return 0xff.
+impl Device for [u8] {
+    fn read(&self, addr: u16) -> u8 {
+        self.get(addr as usize).copied().unwrap_or(0xff)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let Some(byte) = self.get_mut(addr as usize) {
+            *byte = data;
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::device::Device;
@@ -0,0 +1,692 @@
+//! SM83 disassembler.
+//!
+//! Instructions are decoded from any [`Device`], so the same code works on a
+//! running emulator (its [`Mmu`]) and on raw ROM bytes:
+//!
+//! ```
+//! use dmg_lib::disasm;
+//!
+//! // LD HL,$C000 ; INC A ; JR -3
+//! let rom = [0x21, 0x00, 0xc0, 0x3c, 0x18, 0xfd];
+//! for (addr, instr) in disasm::iter(&rom[..], 0x0000).take(3) {
+//!     println!("{:04x}: {}", addr, instr);
+//! }
+//! ```
+//!
+//! The output uses the syntax of the [RGBDS] assembler.
+//!
+//! [`Device`]: #
+//! [`Mmu`]: #
+//! [RGBDS]: https://rgbds.gbdev.io/docs/gbz80.7
+use crate::{device::Device, symbols::Symbols};
+use std::fmt;
+
+/// 8-bit operands.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum R8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    /// The byte pointed to by HL.
+    HLInd,
+    A,
+}
+
+/// 16-bit register pairs.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum R16 {
+    BC,
+    DE,
+    HL,
+    SP,
+    AF,
+}
+
+/// Memory pointed to by a register pair, in `LD (rr),A` and `LD A,(rr)`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Ind {
+    BC,
+    DE,
+    /// HL, incremented after the access.
+    HLI,
+    /// HL, decremented after the access.
+    HLD,
+}
+
+/// Branch conditions.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Cond {
+    NZ,
+    Z,
+    NC,
+    C,
+}
+
+/// 8-bit arithmetic & logic operations with A.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Alu {
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+}
+
+/// Rotate & shift operations (CB prefix).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Shift {
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+}
+
+/// A decoded instruction.
+///
+/// Relative jumps hold the absolute target address.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Instruction {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    /// `LD r,r`
+    Ld(R8, R8),
+    /// `LD r,n8`
+    LdImm(R8, u8),
+    /// `LD rr,n16`
+    LdImm16(R16, u16),
+    /// `LD (rr),A`
+    LdIndA(Ind),
+    /// `LD A,(rr)`
+    LdAInd(Ind),
+    /// `LD (n16),A`
+    LdAddrA(u16),
+    /// `LD A,(n16)`
+    LdAAddr(u16),
+    /// `LDH (n8),A`
+    LdhAddrA(u8),
+    /// `LDH A,(n8)`
+    LdhAAddr(u8),
+    /// `LDH (C),A`
+    LdhCA,
+    /// `LDH A,(C)`
+    LdhAC,
+    /// `LD (n16),SP`
+    LdAddrSp(u16),
+    /// `LD SP,HL`
+    LdSpHl,
+    /// `LD HL,SP+e8`
+    LdHlSp(i8),
+    Push(R16),
+    Pop(R16),
+    Inc(R8),
+    Dec(R8),
+    Inc16(R16),
+    Dec16(R16),
+    /// `ADD HL,rr`
+    AddHl(R16),
+    /// `ADD SP,e8`
+    AddSp(i8),
+    /// Operation between A and a register.
+    Alu(Alu, R8),
+    /// Operation between A and an immediate value.
+    AluImm(Alu, u8),
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Jp(Option<Cond>, u16),
+    /// `JP HL`
+    JpHl,
+    Jr(Option<Cond>, u16),
+    Call(Option<Cond>, u16),
+    Ret(Option<Cond>),
+    Reti,
+    Rst(u8),
+    Shift(Shift, R8),
+    Bit(u8, R8),
+    Res(u8, R8),
+    Set(u8, R8),
+    /// Undefined opcode.
+    Invalid(u8),
+}
+
+const R8: [R8; 8] = [R8::B, R8::C, R8::D, R8::E, R8::H, R8::L, R8::HLInd, R8::A];
+const R16: [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::SP];
+const R16_STK: [R16; 4] = [R16::BC, R16::DE, R16::HL, R16::AF];
+const IND: [Ind; 4] = [Ind::BC, Ind::DE, Ind::HLI, Ind::HLD];
+const COND: [Cond; 4] = [Cond::NZ, Cond::Z, Cond::NC, Cond::C];
+const ALU: [Alu; 8] = [Alu::Add,
+                       Alu::Adc,
+                       Alu::Sub,
+                       Alu::Sbc,
+                       Alu::And,
+                       Alu::Xor,
+                       Alu::Or,
+                       Alu::Cp];
+const SHIFT: [Shift; 8] = [Shift::Rlc,
+                           Shift::Rrc,
+                           Shift::Rl,
+                           Shift::Rr,
+                           Shift::Sla,
+                           Shift::Sra,
+                           Shift::Swap,
+                           Shift::Srl];
+
+/// Decode the instruction at the given address.
+pub fn decode<D: Device + ?Sized>(mem: &D, addr: u16) -> Instruction {
+    use Instruction::*;
+
+    let opcode = mem.read(addr);
+    let n8 = || mem.read(addr.wrapping_add(1));
+    let e8 = || n8() as i8;
+    let n16 = || u16::from(n8()) | u16::from(mem.read(addr.wrapping_add(2))) << 8;
+    let rel = || addr.wrapping_add(2).wrapping_add(e8() as u16);
+
+    // opcode fields: xxyyyzzz, yyy = ppq
+    let x = opcode >> 6;
+    let y = (opcode >> 3) & 0x7;
+    let z = opcode & 0x7;
+    let p = usize::from(y >> 1);
+    let q = y & 0x1;
+
+    match (x, z) {
+        (0, 0) => match y {
+            0 => Nop,
+            1 => LdAddrSp(n16()),
+            2 => Stop,
+            3 => Jr(None, rel()),
+            _ => Jr(Some(COND[usize::from(y - 4)]), rel()),
+        },
+        (0, 1) if q == 0 => LdImm16(R16[p], n16()),
+        (0, 1) => AddHl(R16[p]),
+        (0, 2) if q == 0 => LdIndA(IND[p]),
+        (0, 2) => LdAInd(IND[p]),
+        (0, 3) if q == 0 => Inc16(R16[p]),
+        (0, 3) => Dec16(R16[p]),
+        (0, 4) => Inc(R8[usize::from(y)]),
+        (0, 5) => Dec(R8[usize::from(y)]),
+        (0, 6) => LdImm(R8[usize::from(y)], n8()),
+        (0, _) => [Rlca, Rrca, Rla, Rra, Daa, Cpl, Scf, Ccf][usize::from(y)],
+        (1, 6) if y == 6 => Halt,
+        (1, _) => Ld(R8[usize::from(y)], R8[usize::from(z)]),
+        (2, _) => Alu(ALU[usize::from(y)], R8[usize::from(z)]),
+        (_, 0) => match y {
+            0..=3 => Ret(Some(COND[usize::from(y)])),
+            4 => LdhAddrA(n8()),
+            5 => AddSp(e8()),
+            6 => LdhAAddr(n8()),
+            _ => LdHlSp(e8()),
+        },
+        (_, 1) if q == 0 => Pop(R16_STK[p]),
+        (_, 1) => [Ret(None), Reti, JpHl, LdSpHl][p],
+        (_, 2) => match y {
+            0..=3 => Jp(Some(COND[usize::from(y)]), n16()),
+            4 => LdhCA,
+            5 => LdAddrA(n16()),
+            6 => LdhAC,
+            _ => LdAAddr(n16()),
+        },
+        (_, 3) => match y {
+            0 => Jp(None, n16()),
+            1 => decode_cb(n8()),
+            6 => Di,
+            7 => Ei,
+            _ => Invalid(opcode),
+        },
+        (_, 4) if y <= 3 => Call(Some(COND[usize::from(y)]), n16()),
+        (_, 5) if q == 0 => Push(R16_STK[p]),
+        (_, 5) if y == 1 => Call(None, n16()),
+        (_, 6) => AluImm(ALU[usize::from(y)], n8()),
+        (_, 7) => Rst(y * 8),
+        _ => Invalid(opcode),
+    }
+}
+
+fn decode_cb(opcode: u8) -> Instruction {
+    let y = (opcode >> 3) & 0x7;
+    let r = R8[usize::from(opcode & 0x7)];
+    match opcode >> 6 {
+        0 => Instruction::Shift(SHIFT[usize::from(y)], r),
+        1 => Instruction::Bit(y, r),
+        2 => Instruction::Res(y, r),
+        _ => Instruction::Set(y, r),
+    }
+}
+
+/// Iterate over the instructions starting at the given address. Returns the
+/// address of each one along with it.
+pub fn iter<D: Device + ?Sized>(mem: &D,
+                                addr: u16)
+                                -> impl Iterator<Item = (u16, Instruction)> + '_ {
+    let mut addr = addr;
+    std::iter::repeat_with(move || {
+        let instr = decode(mem, addr);
+        let item = (addr, instr);
+        addr = addr.wrapping_add(instr.size());
+        item
+    })
+}
+
+impl Instruction {
+    /// Size of the instruction in bytes, including the operands.
+    pub fn size(&self) -> u16 {
+        use Instruction::*;
+        match self {
+            LdImm16(..) | LdAddrA(_) | LdAAddr(_) | LdAddrSp(_) | Jp(_, _) | Call(_, _) => 3,
+            Stop | LdImm(..) | LdhAddrA(_) | LdhAAddr(_) | LdHlSp(_) | AddSp(_) | AluImm(..)
+            | Jr(..) | Shift(..) | Bit(..) | Res(..) | Set(..) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Duration of the instruction, in cycles of the 4MHz clock. For
+    /// conditional branches, this is the duration when the branch is not
+    /// taken (see [`Instruction::branch_cycles`]).
+    ///
+    /// [`Instruction::branch_cycles`]: #
+    pub fn cycles(&self) -> u64 {
+        use Instruction::*;
+        let hl = |r: &R8, reg, ind| if *r == R8::HLInd { ind } else { reg };
+        let m = match self {
+            Ld(dst, src) => hl(dst, 1, 2).max(hl(src, 1, 2)),
+            LdImm(r, _) => hl(r, 2, 3),
+            Inc(r) | Dec(r) => hl(r, 1, 3),
+            Alu(_, r) => hl(r, 1, 2),
+            Shift(_, r) | Res(_, r) | Set(_, r) => hl(r, 2, 4),
+            Bit(_, r) => hl(r, 2, 3),
+            LdIndA(_) | LdAInd(_) | LdhCA | LdhAC | LdSpHl | Inc16(_) | Dec16(_) | AddHl(_)
+            | AluImm(..) => 2,
+            LdImm16(..) | LdhAddrA(_) | LdhAAddr(_) | LdHlSp(_) | Pop(_) => 3,
+            LdAddrA(_) | LdAAddr(_) | Push(_) | AddSp(_) | Ret(None) | Reti | Rst(_) => 4,
+            LdAddrSp(_) => 5,
+            Jp(None, _) => 4,
+            Jp(Some(_), _) => 3,
+            Jr(None, _) => 3,
+            Jr(Some(_), _) => 2,
+            Call(None, _) => 6,
+            Call(Some(_), _) => 3,
+            Ret(Some(_)) => 2,
+            _ => 1,
+        };
+        m * 4
+    }
+
+    /// Duration of a conditional branch when it is taken, in cycles of the
+    /// 4MHz clock. Returns `None` for the rest of the instructions.
+    pub fn branch_cycles(&self) -> Option<u64> {
+        use Instruction::*;
+        match self {
+            Jp(Some(_), _) => Some(16),
+            Jr(Some(_), _) => Some(12),
+            Call(Some(_), _) => Some(24),
+            Ret(Some(_)) => Some(20),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for R8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            R8::B => "b",
+            R8::C => "c",
+            R8::D => "d",
+            R8::E => "e",
+            R8::H => "h",
+            R8::L => "l",
+            R8::HLInd => "[hl]",
+            R8::A => "a",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for R16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            R16::BC => "bc",
+            R16::DE => "de",
+            R16::HL => "hl",
+            R16::SP => "sp",
+            R16::AF => "af",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Ind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Ind::BC => "[bc]",
+            Ind::DE => "[de]",
+            Ind::HLI => "[hl+]",
+            Ind::HLD => "[hl-]",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Cond {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Cond::NZ => "nz",
+            Cond::Z => "z",
+            Cond::NC => "nc",
+            Cond::C => "c",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Alu {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Alu::Add => "add",
+            Alu::Adc => "adc",
+            Alu::Sub => "sub",
+            Alu::Sbc => "sbc",
+            Alu::And => "and",
+            Alu::Xor => "xor",
+            Alu::Or => "or",
+            Alu::Cp => "cp",
+        };
+        f.write_str(name)
+    }
+}
+
+impl fmt::Display for Shift {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Shift::Rlc => "rlc",
+            Shift::Rrc => "rrc",
+            Shift::Rl => "rl",
+            Shift::Rr => "rr",
+            Shift::Sla => "sla",
+            Shift::Sra => "sra",
+            Shift::Swap => "swap",
+            Shift::Srl => "srl",
+        };
+        f.write_str(name)
+    }
+}
+
+// Signed offset, as in "sp+5" or "sp-5".
+struct Offset(i8);
+
+impl fmt::Display for Offset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 < 0 {
+            write!(f, "-{}", self.0.unsigned_abs())
+        } else {
+            write!(f, "+{}", self.0)
+        }
+    }
+}
+
+/// An instruction formatted with labels in place of addresses (see
+/// [`Instruction::with_symbols`]).
+///
+/// [`Instruction::with_symbols`]: #
+pub struct WithSymbols<'a> {
+    instr: Instruction,
+    symbols: &'a Symbols,
+    rom_bank: usize,
+    wram_bank: usize,
+}
+
+impl fmt::Display for WithSymbols<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.instr
+            .write(f, Some((self.symbols, self.rom_bank, self.wram_bank)))
+    }
+}
+
+// Address operand.
+struct Addr<'a> {
+    addr: u16,
+    symbols: Option<(&'a Symbols, usize, usize)>,
+    // only use labels at the exact address (the operand may not be an address)
+    exact: bool,
+}
+
+impl fmt::Display for Addr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (symbols, rom_bank, wram_bank) = match self.symbols {
+            Some(symbols) => symbols,
+            None => return write!(f, "${:04x}", self.addr),
+        };
+        let bank = match self.addr {
+            0x4000..=0x7fff => rom_bank,
+            0xd000..=0xdfff => wram_bank,
+            _ => 0,
+        };
+        match symbols.lookup(bank, self.addr) {
+            Some((name, 0)) => write!(f, "{}", name),
+            Some((name, offset)) if !self.exact => write!(f, "{}+{}", name, offset),
+            _ => write!(f, "${:04x}", self.addr),
+        }
+    }
+}
+
+impl Instruction {
+    /// Format the instruction using the labels of the given symbol table for
+    /// the address operands. Addresses in the switchable ROM and WRAM areas are
+    /// looked up in `rom_bank` and `wram_bank` (see [`symbols::bank`]).
+    ///
+    /// [`symbols::bank`]: #
+    pub fn with_symbols(self,
+                        symbols: &Symbols,
+                        rom_bank: usize,
+                        wram_bank: usize)
+                        -> WithSymbols<'_> {
+        WithSymbols { instr: self,
+                      symbols,
+                      rom_bank,
+                      wram_bank }
+    }
+
+    fn write(&self,
+             f: &mut fmt::Formatter<'_>,
+             symbols: Option<(&Symbols, usize, usize)>)
+             -> fmt::Result {
+        use Instruction::*;
+
+        let addr = |addr| Addr { addr,
+                                 symbols,
+                                 exact: false };
+        let hram = |n: &u8| addr(0xff00 | u16::from(*n));
+
+        // "jp nz, " or "jp "
+        let branch = |f: &mut fmt::Formatter<'_>, name, cond: &Option<Cond>| match cond {
+            Some(cond) => write!(f, "{} {}, ", name, cond),
+            None => write!(f, "{} ", name),
+        };
+
+        match self {
+            Nop => write!(f, "nop"),
+            Stop => write!(f, "stop"),
+            Halt => write!(f, "halt"),
+            Di => write!(f, "di"),
+            Ei => write!(f, "ei"),
+            Ld(dst, src) => write!(f, "ld {}, {}", dst, src),
+            LdImm(r, n) => write!(f, "ld {}, ${:02x}", r, n),
+            LdImm16(rr, n) => {
+                let n = Addr { addr: *n,
+                               symbols,
+                               exact: true };
+                write!(f, "ld {}, {}", rr, n)
+            }
+            LdIndA(ind) => write!(f, "ld {}, a", ind),
+            LdAInd(ind) => write!(f, "ld a, {}", ind),
+            LdAddrA(n) => write!(f, "ld [{}], a", addr(*n)),
+            LdAAddr(n) => write!(f, "ld a, [{}]", addr(*n)),
+            LdhAddrA(n) => write!(f, "ldh [{}], a", hram(n)),
+            LdhAAddr(n) => write!(f, "ldh a, [{}]", hram(n)),
+            LdhCA => write!(f, "ldh [c], a"),
+            LdhAC => write!(f, "ldh a, [c]"),
+            LdAddrSp(n) => write!(f, "ld [{}], sp", addr(*n)),
+            LdSpHl => write!(f, "ld sp, hl"),
+            LdHlSp(e) => write!(f, "ld hl, sp{}", Offset(*e)),
+            Push(rr) => write!(f, "push {}", rr),
+            Pop(rr) => write!(f, "pop {}", rr),
+            Inc(r) => write!(f, "inc {}", r),
+            Dec(r) => write!(f, "dec {}", r),
+            Inc16(rr) => write!(f, "inc {}", rr),
+            Dec16(rr) => write!(f, "dec {}", rr),
+            AddHl(rr) => write!(f, "add hl, {}", rr),
+            AddSp(e) => write!(f, "add sp, {}", e),
+            Alu(op, r) => write!(f, "{} a, {}", op, r),
+            AluImm(op, n) => write!(f, "{} a, ${:02x}", op, n),
+            Rlca => write!(f, "rlca"),
+            Rrca => write!(f, "rrca"),
+            Rla => write!(f, "rla"),
+            Rra => write!(f, "rra"),
+            Daa => write!(f, "daa"),
+            Cpl => write!(f, "cpl"),
+            Scf => write!(f, "scf"),
+            Ccf => write!(f, "ccf"),
+            Jp(cond, n) => {
+                branch(f, "jp", cond)?;
+                write!(f, "{}", addr(*n))
+            }
+            JpHl => write!(f, "jp hl"),
+            Jr(cond, n) => {
+                branch(f, "jr", cond)?;
+                write!(f, "{}", addr(*n))
+            }
+            Call(cond, n) => {
+                branch(f, "call", cond)?;
+                write!(f, "{}", addr(*n))
+            }
+            Ret(Some(cond)) => write!(f, "ret {}", cond),
+            Ret(None) => write!(f, "ret"),
+            Reti => write!(f, "reti"),
+            Rst(n) => write!(f, "rst ${:02x}", n),
+            Shift(op, r) => write!(f, "{} {}", op, r),
+            Bit(b, r) => write!(f, "bit {}, {}", b, r),
+            Res(b, r) => write!(f, "res {}, {}", b, r),
+            Set(b, r) => write!(f, "set {}, {}", b, r),
+            Invalid(op) => write!(f, "db ${:02x}", op),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        disasm::{decode, iter, Alu, Cond, Instruction::*, R16, R8},
+        symbols::Symbols,
+    };
+
+    fn text(bytes: &[u8]) -> String {
+        decode(bytes, 0x0000).to_string()
+    }
+
+    #[test]
+    fn decode_all() {
+        for opcode in 0..=0xff {
+            let mut bytes = [opcode, 0x00, 0x00];
+            assert!(decode(&bytes[..], 0).size() <= 3);
+            bytes[0] = 0xcb;
+            bytes[1] = opcode;
+            assert_eq!(2, decode(&bytes[..], 0).size());
+        }
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(LdImm16(R16::HL, 0xc000), decode(&[0x21, 0x00, 0xc0][..], 0));
+        assert_eq!(Ld(R8::HLInd, R8::A), decode(&[0x77][..], 0));
+        assert_eq!(Halt, decode(&[0x76][..], 0));
+        assert_eq!(AluImm(Alu::Cp, 0x90), decode(&[0xfe, 0x90][..], 0));
+        assert_eq!(Jr(Some(Cond::NZ), 0x0000), decode(&[0x20, 0xfe][..], 0));
+        assert_eq!(Push(R16::AF), decode(&[0xf5][..], 0));
+        assert_eq!(Invalid(0xd3), decode(&[0xd3][..], 0));
+    }
+
+    #[test]
+    fn rgbds() {
+        assert_eq!("ld hl, $c000", text(&[0x21, 0x00, 0xc0]));
+        assert_eq!("ld [hl+], a", text(&[0x22]));
+        assert_eq!("ldh [$ff44], a", text(&[0xe0, 0x44]));
+        assert_eq!("ld hl, sp-2", text(&[0xf8, 0xfe]));
+        assert_eq!("add sp, 4", text(&[0xe8, 0x04]));
+        assert_eq!("call nz, $1234", text(&[0xc4, 0x34, 0x12]));
+        assert_eq!("bit 7, [hl]", text(&[0xcb, 0x7e]));
+        assert_eq!("swap a", text(&[0xcb, 0x37]));
+        assert_eq!("rst $38", text(&[0xff]));
+        assert_eq!("db $fc", text(&[0xfc]));
+    }
+
+    #[test]
+    fn symbols() {
+        let mut symbols = Symbols::new();
+        symbols.insert(0, 0x0150, "Main");
+        symbols.insert(2, 0x4000, "Banked");
+        symbols.insert(0, 0xff80, "hFrame");
+        symbols.insert(1, 0xd000, "wBank1");
+        symbols.insert(3, 0xd000, "wBank3");
+        let text =
+            |bytes: &[u8], bank| decode(bytes, 0).with_symbols(&symbols, bank, 1).to_string();
+
+        assert_eq!("call Main", text(&[0xcd, 0x50, 0x01], 1));
+        assert_eq!("jp Main+3", text(&[0xc3, 0x53, 0x01], 1));
+        assert_eq!("jp Banked+16", text(&[0xc3, 0x10, 0x40], 2));
+        assert_eq!("jp $4010", text(&[0xc3, 0x10, 0x40], 1));
+        assert_eq!("ldh a, [hFrame]", text(&[0xf0, 0x80], 1));
+        assert_eq!("ld hl, Main", text(&[0x21, 0x50, 0x01], 1));
+        assert_eq!("ld hl, $0151", text(&[0x21, 0x51, 0x01], 1));
+        assert_eq!("ld a, [wBank1]", text(&[0xfa, 0x00, 0xd0], 1));
+
+        let wram = |bank| {
+            decode(&[0xfa, 0x01, 0xd0][..], 0).with_symbols(&symbols, 1, bank)
+                                              .to_string()
+        };
+        assert_eq!("ld a, [wBank3+1]", wram(3));
+        assert_eq!("ld a, [$d001]", wram(2));
+    }
+
+    #[test]
+    fn cycles() {
+        assert_eq!(4, decode(&[0x00][..], 0).cycles());
+        assert_eq!(8, decode(&[0x7e][..], 0).cycles());
+        assert_eq!(12, decode(&[0x34][..], 0).cycles());
+        assert_eq!(16, decode(&[0xcb, 0x06][..], 0).cycles());
+        assert_eq!(12, decode(&[0xcb, 0x46][..], 0).cycles());
+        assert_eq!(12, decode(&[0xc4, 0, 0][..], 0).cycles());
+        assert_eq!(Some(24), decode(&[0xc4, 0, 0][..], 0).branch_cycles());
+        assert_eq!(None, decode(&[0xcd, 0, 0][..], 0).branch_cycles());
+    }
+
+    #[test]
+    fn addresses() {
+        // LD HL,$C000 ; INC A ; JR $0003
+        let rom = [0x21, 0x00, 0xc0, 0x3c, 0x18, 0xfd];
+        let addrs: Vec<_> = iter(&rom[..], 0).take(4).map(|(addr, _)| addr).collect();
+        assert_eq!(vec![0, 3, 4, 6], addrs);
+        assert_eq!(Jr(None, 0x0003), decode(&rom[..], 4));
+    }
+}
@@ -0,0 +1,308 @@
+//! GBS (Game Boy Sound System) music file player.
+//!
+//! GBS files contain the sound driver and music data ripped from a game, along
+//! with the addresses of the routines to initialize and play each song. Only
+//! the CPU, the timer and the APU are emulated (the LCD is never turned on).
+use crate::{
+    apu::{device::Audio, Apu},
+    cartridge::{gbs::RETURN_ADDR, Gbs},
+    cpu::Cpu,
+    device::Device,
+    mmu::Mmu,
+    Model,
+};
+
+const HEADER_SIZE: usize = 0x70;
+
+/// Errors when loading GBS files.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// The file is too short or doesn't start with the "GBS" identifier.
+    InvalidHeader,
+    /// The load address is outside of the 0100-7FFF range.
+    InvalidLoadAddr(u16),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidHeader => write!(f, "Invalid GBS header"),
+            Error::InvalidLoadAddr(addr) => write!(f, "Invalid GBS load address ({:04x})", addr),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+// Play routine rate when the timer is not used (VBlank rate).
+const FRAME_CYCLES: u64 = 70224;
+
+// Cycles emulated at once while waiting for the next call to the play routine.
+const IDLE_CYCLES: u64 = 64;
+
+/// GBS file header.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Header {
+    /// Format version (1).
+    pub version: u8,
+    /// Number of songs.
+    pub songs: u8,
+    /// First song (1 based).
+    pub first_song: u8,
+    /// Address where the data is loaded.
+    pub load_addr: u16,
+    /// Address of the init routine. Called with the (0 based) song number in
+    /// the A register.
+    pub init_addr: u16,
+    /// Address of the play routine.
+    pub play_addr: u16,
+    /// Initial value of the stack pointer.
+    pub sp: u16,
+    /// Value of the TMA register.
+    pub tma: u8,
+    /// Value of the TAC register. If bit 2 is set, the play routine is called
+    /// from the timer interrupt, otherwise it's called at VBlank rate. If bit 7
+    /// is set, the CPU runs at double speed.
+    pub tac: u8,
+    /// Title of the music.
+    pub title: String,
+    /// Author of the music.
+    pub author: String,
+    /// Copyright information.
+    pub copyright: String,
+}
+
+impl Header {
+    /// Parse the header of a GBS file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < HEADER_SIZE || &bytes[0..3] != b"GBS" {
+            return Err(Error::InvalidHeader);
+        }
+
+        let word = |addr: usize| u16::from(bytes[addr]) | u16::from(bytes[addr + 1]) << 8;
+        let string = |addr: usize| {
+            bytes[addr..addr + 0x20].iter()
+                                    .take_while(|b| **b != 0)
+                                    .map(|b| *b as char)
+                                    .collect()
+        };
+        let header = Self { version: bytes[0x03],
+                            songs: bytes[0x04],
+                            first_song: bytes[0x05],
+                            load_addr: word(0x06),
+                            init_addr: word(0x08),
+                            play_addr: word(0x0a),
+                            sp: word(0x0c),
+                            tma: bytes[0x0e],
+                            tac: bytes[0x0f],
+                            title: string(0x10),
+                            author: string(0x30),
+                            copyright: string(0x50) };
+
+        // the area below the load address is used by the RST vectors and the return
+        // address of the routines.
+        if header.load_addr < 0x0100 || header.load_addr >= 0x8000 {
+            return Err(Error::InvalidLoadAddr(header.load_addr));
+        }
+        Ok(header)
+    }
+}
+
+/// GBS music player.
+pub struct Player<D: Audio> {
+    header: Header,
+    cpu: Cpu,
+    mmu: Mmu<Gbs, (), D>,
+    // Set when the play routine is due, and called as soon as the CPU returns
+    // from the previous routine.
+    play: bool,
+    // Cycles since the last call to the play routine (VBlank rate only).
+    frame: u64,
+}
+
+impl<D: Audio> Player<D> {
+    /// Load a GBS file, and start playing its first song.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let header = Header::from_bytes(bytes)?;
+        let cartridge = Gbs::new(header.load_addr, &bytes[HEADER_SIZE..]);
+        let mut player = Self { cpu: Cpu::default(),
+                                mmu: Mmu::new(Model::Dmg, cartridge, ()),
+                                play: false,
+                                frame: 0,
+                                header };
+        player.start(player.header.first_song.saturating_sub(1));
+        Ok(player)
+    }
+
+    /// Return the GBS header.
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+
+    /// Return the APU.
+    pub fn apu(&self) -> &Apu<D> {
+        self.mmu.apu()
+    }
+
+    /// Return the APU as mutable.
+    pub fn apu_mut(&mut self) -> &mut Apu<D> {
+        self.mmu.apu_mut()
+    }
+
+    /// Reset the player and call the init routine of the given (0 based) song.
+    pub fn start(&mut self, song: u8) {
+        // clear RAM
+        for addr in (0xa000..=0xbfff).chain(0xc000..=0xdfff)
+                                     .chain(0xff80..=0xfffe)
+        {
+            self.mmu.write(addr, 0);
+        }
+        self.mmu.write(0x2000, 0x01);
+        self.mmu.write(0xff0f, 0x00); // IF
+        self.mmu.write(0xffff, 0x00); // IE
+        self.mmu.write(0xff40, 0x00); // LCDC
+
+        // initialize sound
+        self.mmu.write(0xff26, 0x00);
+        self.mmu.write(0xff26, 0x80);
+        self.mmu.write(0xff25, 0xff);
+        self.mmu.write(0xff24, 0x77);
+
+        // timer
+        self.mmu.write(0xff06, self.header.tma);
+        self.mmu.write(0xff07, self.header.tac & 0x7);
+        if self.header.tac & 0x80 != 0 {
+            self.mmu.write(0xff4d, 0x01);
+        }
+
+        self.cpu = Cpu::default();
+        self.cpu.reg_mut().a = song;
+        self.cpu.reg_mut().sp = self.header.sp;
+        self.call(self.header.init_addr);
+        self.play = false;
+        self.frame = 0;
+    }
+
+    /// Emulate the given number of cycles of the 4MHz clock.
+    pub fn run(&mut self, cycles: u64) {
+        let mut elapsed = 0;
+        while elapsed < cycles {
+            let mut cycles = if self.cpu.reg().pc != RETURN_ADDR {
+                self.cpu.step(&mut self.mmu)
+            } else if self.play {
+                self.play = false;
+                self.call(self.header.play_addr);
+                continue;
+            } else {
+                IDLE_CYCLES
+            };
+
+            // KEY1
+            if self.mmu.read(0xff4d) & 0x80 != 0 {
+                cycles /= 2;
+            }
+
+            self.step(cycles);
+            elapsed += cycles;
+        }
+    }
+
+    /// Emulate a single frame (~1/60 seconds).
+    pub fn emulate_frame(&mut self) {
+        self.run(FRAME_CYCLES);
+    }
+
+    // Call a routine. Once it returns, the CPU lands on RETURN_ADDR and sits there
+    // until the next call.
+    fn call(&mut self, addr: u16) {
+        let reg = self.cpu.reg_mut();
+        reg.sp = reg.sp.wrapping_sub(2);
+        let sp = reg.sp;
+        reg.pc = addr;
+        self.mmu.write_word(sp, RETURN_ADDR);
+    }
+
+    fn step(&mut self, cycles: u64) {
+        let timer = self.mmu.timer_mut();
+        timer.step(cycles);
+        let timer_int = timer.take_timer_int().is_some();
+
+        if self.header.tac & 0x4 != 0 {
+            self.play |= timer_int;
+        } else {
+            self.frame += cycles;
+            if self.frame >= FRAME_CYCLES {
+                self.frame -= FRAME_CYCLES;
+                self.play = true;
+            }
+        }
+
+        self.mmu.apu().lock().step(cycles);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        apu::Channel,
+        device::Device,
+        gbs::{Error, Header, Player, FRAME_CYCLES},
+        CLOCK,
+    };
+
+    // Builds a GBS file with the following code (loaded at 0x400):
+    //
+    // init: LD A,$F0     ; 0400
+    //       LDH ($17),A  ; 0402 (NR22)
+    //       LD A,$87     ; 0404
+    //       LDH ($19),A  ; 0406 (trigger channel 2)
+    //       RET          ; 0408
+    // play: LD HL,$C000  ; 0409
+    //       INC (HL)     ; 040c (count calls)
+    //       RET          ; 040d
+    fn gbs(tac: u8) -> Vec<u8> {
+        let mut gbs = vec![0; 0x70];
+        gbs[0..3].copy_from_slice(b"GBS");
+        gbs[0x03] = 1;
+        gbs[0x04] = 2;
+        gbs[0x05] = 1;
+        gbs[0x06..0x08].copy_from_slice(&[0x00, 0x04]);
+        gbs[0x08..0x0a].copy_from_slice(&[0x00, 0x04]);
+        gbs[0x0a..0x0c].copy_from_slice(&[0x09, 0x04]);
+        gbs[0x0c..0x0e].copy_from_slice(&[0xfe, 0xff]);
+        gbs[0x0e] = 0x00;
+        gbs[0x0f] = tac;
+        gbs[0x10..0x14].copy_from_slice(b"Test");
+        gbs.extend_from_slice(&[0x3e, 0xf0, 0xe0, 0x17, 0x3e, 0x87, 0xe0, 0x19, 0xc9]);
+        gbs.extend_from_slice(&[0x21, 0x00, 0xc0, 0x34, 0xc9]);
+        gbs
+    }
+
+    #[test]
+    fn header() {
+        let header = Header::from_bytes(&gbs(0)).unwrap();
+        assert_eq!(2, header.songs);
+        assert_eq!(0x400, header.load_addr);
+        assert_eq!(0x409, header.play_addr);
+        assert_eq!("Test", header.title);
+        assert_eq!("", header.author);
+
+        assert_eq!(Err(Error::InvalidHeader), Header::from_bytes(&[0; 0x70]));
+    }
+
+    #[test]
+    fn vblank_rate() {
+        let mut player = Player::<()>::from_bytes(&gbs(0)).unwrap();
+        player.run(60 * FRAME_CYCLES + FRAME_CYCLES / 2);
+        assert!(player.apu().channel_state(Channel::Square2).enabled);
+        assert_eq!(60, player.mmu.read(0xc000));
+    }
+
+    #[test]
+    fn timer_rate() {
+        // 4096Hz timer with TMA = 0, play is called at 16Hz
+        let mut player = Player::<()>::from_bytes(&gbs(0x04)).unwrap();
+        player.run(CLOCK + CLOCK / 32);
+        assert_eq!(16, player.mmu.read(0xc000));
+    }
+}
@@ -0,0 +1,541 @@
+//! GDB remote serial protocol stub.
+//!
+//! Lets a GDB client (one that knows about the SM83, or given the target
+//! description sent by the stub) debug a [`GameBoy`] over TCP:
+//!
+//! ```no_run
+//! use dmg_lib::{gdb::Server, Builder};
+//!
+//! let mut dmg = Builder::default().build();
+//! let server = Server::bind("127.0.0.1:2345").unwrap();
+//! let mut session = server.accept().unwrap();
+//! session.run(&mut dmg).unwrap();
+//! ```
+//!
+//! Registers are exposed as six 16-bit pairs: AF, BC, DE, HL, SP & PC. Memory
+//! is accessed through the [`Mmu`], so writes to the ROM area go to the bank
+//! controller of the cartridge. Breakpoints and watchpoints are set on the
+//! [`Debugger`] attached to the emulator (one is attached if there isn't).
+//!
+//! [`GameBoy`]: #
+//! [`Mmu`]: #
+//! [`Debugger`]: #
+use crate::{
+    apu::device::Audio,
+    cartridge::Cartridge,
+    debug::{Access, Breakpoint, Debugger, Hit, Id, Watchpoint},
+    device::Device,
+    mmu::FRAME_CYCLES,
+    ppu::Video,
+    GameBoy,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, ErrorKind, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.sm83.core">
+    <reg name="af" bitsize="16" type="int"/>
+    <reg name="bc" bitsize="16" type="int"/>
+    <reg name="de" bitsize="16" type="int"/>
+    <reg name="hl" bitsize="16" type="int"/>
+    <reg name="sp" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const REGISTERS: usize = 6;
+
+// Ctrl-C sent by the client while the target runs.
+const INTERRUPT: u8 = 0x03;
+
+/// Listens for GDB clients.
+pub struct Server {
+    listener: TcpListener,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        Ok(Self { listener: TcpListener::bind(addr)? })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Wait for a client to connect.
+    pub fn accept(&self) -> io::Result<Session> {
+        let (stream, _) = self.listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Session { stream,
+                     input: Vec::new(),
+                     ack: true,
+                     points: HashMap::new() })
+    }
+}
+
+// Reason the target stopped.
+enum Stop {
+    Step,
+    Interrupt,
+    Hit(Hit),
+}
+
+/// Connection with a GDB client.
+pub struct Session {
+    stream: TcpStream,
+    // received bytes, not processed yet
+    input: Vec<u8>,
+    // acknowledge packets (until the client requests no-ack mode)
+    ack: bool,
+    // (Z packet type, address) -> breakpoint or watchpoint
+    points: HashMap<(u8, u16), Id>,
+}
+
+impl Session {
+    /// Serve the requests of the client until it detaches, kills the target,
+    /// or closes the connection. The breakpoints and watchpoints set by the
+    /// client are removed on return.
+    pub fn run<C: Cartridge, V: Video, D: Audio>(&mut self,
+                                                 dmg: &mut GameBoy<C, V, D>)
+                                                 -> io::Result<()> {
+        if dmg.mmu().debugger().is_none() {
+            dmg.mmu_mut().attach_debugger(Debugger::new());
+        }
+        let result = self.serve(dmg);
+        if let Some(debugger) = dmg.mmu_mut().debugger_mut() {
+            for (_, id) in self.points.drain() {
+                debugger.remove(id);
+            }
+        }
+        match result {
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => Ok(()),
+            result => result,
+        }
+    }
+
+    fn serve<C: Cartridge, V: Video, D: Audio>(&mut self,
+                                               dmg: &mut GameBoy<C, V, D>)
+                                               -> io::Result<()> {
+        loop {
+            let packet = self.read_packet()?;
+            let packet = String::from_utf8_lossy(&packet).into_owned();
+            let reply = match packet.as_bytes().first() {
+                Some(b'D') => return self.send("OK"),
+                Some(b'k') => return Ok(()),
+                Some(b'c') => {
+                    let stop = self.resume(dmg)?;
+                    stop_reply(stop)
+                }
+                Some(b's') => stop_reply(step(dmg)),
+                _ => self.handle(&packet, dmg)
+                         .unwrap_or_else(|| "E01".to_string()),
+            };
+            self.send(&reply)?;
+        }
+    }
+
+    // Reply to the packets that don't resume the target. Returns None if the
+    // packet is malformed.
+    fn handle<C: Cartridge, V: Video, D: Audio>(&mut self,
+                                                packet: &str,
+                                                dmg: &mut GameBoy<C, V, D>)
+                                                -> Option<String> {
+        let (cmd, args) = match (packet.get(..1), packet.get(1..)) {
+            (Some(cmd), Some(args)) => (cmd, args),
+            _ => return Some(String::new()),
+        };
+        let reply = match cmd {
+            "?" => "S05".to_string(),
+            "g" => {
+                let mut reply = String::new();
+                for reg in 0..REGISTERS {
+                    push_hex16(&mut reply, read_reg(dmg, reg));
+                }
+                reply
+            }
+            "G" => {
+                for reg in 0..REGISTERS {
+                    let data = args.get(reg * 4..reg * 4 + 4)?;
+                    write_reg(dmg, reg, parse_hex16(data)?);
+                }
+                "OK".to_string()
+            }
+            "p" => {
+                let reg = usize::from_str_radix(args, 16).ok()?;
+                if reg >= REGISTERS {
+                    return None;
+                }
+                let mut reply = String::new();
+                push_hex16(&mut reply, read_reg(dmg, reg));
+                reply
+            }
+            "P" => {
+                let (reg, data) = args.split_once('=')?;
+                let reg = usize::from_str_radix(reg, 16).ok()?;
+                if reg >= REGISTERS {
+                    return None;
+                }
+                write_reg(dmg, reg, parse_hex16(data)?);
+                "OK".to_string()
+            }
+            "m" => {
+                let (addr, len) = parse_range(args)?;
+                let mut reply = String::new();
+                for i in 0..len {
+                    let data = dmg.mmu().peek(addr.wrapping_add(i));
+                    write!(reply, "{:02x}", data).unwrap();
+                }
+                reply
+            }
+            "M" => {
+                let (range, data) = args.split_once(':')?;
+                let (addr, len) = parse_range(range)?;
+                if data.len() != usize::from(len) * 2 {
+                    return None;
+                }
+                for i in 0..len {
+                    let offset = usize::from(i) * 2;
+                    let byte = u8::from_str_radix(data.get(offset..offset + 2)?, 16).ok()?;
+                    dmg.mmu_mut().write(addr.wrapping_add(i), byte);
+                }
+                "OK".to_string()
+            }
+            "Z" | "z" => self.breakpoint(cmd == "Z", args, dmg)?,
+            "H" => "OK".to_string(),
+            "q" | "Q" | "v" => query(packet, &mut self.ack)?,
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    // Set (Z) or remove (z) a breakpoint or watchpoint.
+    fn breakpoint<C: Cartridge, V: Video, D: Audio>(&mut self,
+                                                    set: bool,
+                                                    args: &str,
+                                                    dmg: &mut GameBoy<C, V, D>)
+                                                    -> Option<String> {
+        let mut args = args.split(',');
+        let kind = args.next()?.parse::<u8>().ok()?;
+        let addr = u16::from_str_radix(args.next()?, 16).ok()?;
+        let len = args.next()
+                      .and_then(|len| u16::from_str_radix(len, 16).ok());
+        let end = addr.saturating_add(len.unwrap_or(1).max(1) - 1);
+        let watchpoint = Watchpoint::new(addr..=end);
+        let point = match kind {
+            0 | 1 => None,
+            2 => Some(watchpoint.write()),
+            3 => Some(watchpoint.read()),
+            4 => Some(watchpoint.read().write()),
+            _ => return Some(String::new()),
+        };
+        let debugger = dmg.mmu_mut().debugger_mut()?;
+        if set {
+            let id = match point {
+                Some(watchpoint) => debugger.add_watchpoint(watchpoint),
+                None => debugger.add_breakpoint(Breakpoint::new(addr)),
+            };
+            if let Some(old) = self.points.insert((kind, addr), id) {
+                debugger.remove(old);
+            }
+        } else if let Some(id) = self.points.remove(&(kind, addr)) {
+            debugger.remove(id);
+        }
+        Some("OK".to_string())
+    }
+
+    // Run until a breakpoint or watchpoint is hit, or the client interrupts.
+    fn resume<C: Cartridge, V: Video, D: Audio>(&mut self,
+                                                dmg: &mut GameBoy<C, V, D>)
+                                                -> io::Result<Stop> {
+        loop {
+            if let Some(hit) = dmg.run_cycles(FRAME_CYCLES).hit {
+                return Ok(Stop::Hit(hit));
+            }
+            self.stream.set_nonblocking(true)?;
+            let received = self.receive();
+            self.stream.set_nonblocking(false)?;
+            match received {
+                Ok(()) => {}
+                Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+            if let Some(i) = self.input.iter().position(|b| *b == INTERRUPT) {
+                self.input.remove(i);
+                return Ok(Stop::Interrupt);
+            }
+        }
+    }
+
+    // Read available bytes into the input buffer.
+    fn receive(&mut self) -> io::Result<()> {
+        let mut buf = [0; 1024];
+        let len = self.stream.read(&mut buf)?;
+        if len == 0 {
+            return Err(ErrorKind::UnexpectedEof.into());
+        }
+        self.input.extend_from_slice(&buf[..len]);
+        Ok(())
+    }
+
+    // Read the next packet ($data#checksum) and acknowledge it.
+    fn read_packet(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            // skip acks & interrupts received while stopped
+            match self.input.iter().position(|b| *b == b'$') {
+                Some(start) => self.input.drain(..start),
+                None => {
+                    self.input.clear();
+                    self.receive()?;
+                    continue;
+                }
+            };
+            let end = match self.input.iter().position(|b| *b == b'#') {
+                Some(end) if self.input.len() >= end + 3 => end,
+                _ => {
+                    self.receive()?;
+                    continue;
+                }
+            };
+            let data = self.input[1..end].to_vec();
+            let checksum = std::str::from_utf8(&self.input[end + 1..end + 3]).ok()
+                                                                        .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+            self.input.drain(..end + 3);
+            let valid = checksum == Some(checksum_of(&data));
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(data);
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+// Reply to general queries & the 'v' packets.
+fn query(packet: &str, ack: &mut bool) -> Option<String> {
+    let reply = if packet.starts_with("qSupported") {
+        "PacketSize=1000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string()
+    } else if packet == "QStartNoAckMode" {
+        *ack = false;
+        "OK".to_string()
+    } else if packet == "qAttached" {
+        "1".to_string()
+    } else if packet == "qC" {
+        "QC1".to_string()
+    } else if packet == "qfThreadInfo" {
+        "m1".to_string()
+    } else if packet == "qsThreadInfo" {
+        "l".to_string()
+    } else if let Some(args) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        let (offset, len) = args.split_once(',')?;
+        let offset = usize::from_str_radix(offset, 16).ok()?
+                                                      .min(TARGET_XML.len());
+        let len = usize::from_str_radix(len, 16).ok()?;
+        let data = &TARGET_XML[offset..];
+        if data.len() > len {
+            format!("m{}", &data[..len])
+        } else {
+            format!("l{}", data)
+        }
+    } else {
+        String::new()
+    };
+    Some(reply)
+}
+
+fn step<C: Cartridge, V: Video, D: Audio>(dmg: &mut GameBoy<C, V, D>) -> Stop {
+    match dmg.step_instruction().hit {
+        // stopping at a breakpoint is the expected outcome of a step
+        Some(hit) if hit.access != Access::Execute => Stop::Hit(hit),
+        _ => Stop::Step,
+    }
+}
+
+fn stop_reply(stop: Stop) -> String {
+    match stop {
+        Stop::Step => "S05".to_string(),
+        Stop::Interrupt => "S02".to_string(),
+        Stop::Hit(Hit { access: Access::Execute,
+                        .. }) => "T05swbreak:;".to_string(),
+        Stop::Hit(Hit { access: Access::Write,
+                        addr,
+                        .. }) => format!("T05watch:{:x};", addr),
+        Stop::Hit(Hit { access: Access::Read,
+                        addr,
+                        .. }) => format!("T05rwatch:{:x};", addr),
+    }
+}
+
+fn read_reg<C: Cartridge, V: Video, D: Audio>(dmg: &GameBoy<C, V, D>, reg: usize) -> u16 {
+    let regs = dmg.cpu().reg();
+    match reg {
+        0 => regs.af(),
+        1 => regs.bc(),
+        2 => regs.de(),
+        3 => regs.hl(),
+        4 => regs.sp,
+        _ => regs.pc,
+    }
+}
+
+fn write_reg<C: Cartridge, V: Video, D: Audio>(dmg: &mut GameBoy<C, V, D>, reg: usize, data: u16) {
+    let regs = dmg.cpu_mut().reg_mut();
+    match reg {
+        0 => regs.set_af(data),
+        1 => regs.set_bc(data),
+        2 => regs.set_de(data),
+        3 => regs.set_hl(data),
+        4 => regs.sp = data,
+        _ => regs.pc = data,
+    }
+}
+
+// Registers are sent in target byte order (little endian).
+fn push_hex16(out: &mut String, data: u16) {
+    write!(out, "{:02x}{:02x}", data & 0xff, data >> 8).unwrap();
+}
+
+fn parse_hex16(data: &str) -> Option<u16> {
+    let lo = u8::from_str_radix(data.get(0..2)?, 16).ok()?;
+    let hi = u8::from_str_radix(data.get(2..4)?, 16).ok()?;
+    Some(u16::from_le_bytes([lo, hi]))
+}
+
+// "addr,len"
+fn parse_range(args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((u16::from_str_radix(addr, 16).ok()?, u16::from_str_radix(len, 16).ok()?))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{cartridge::Rom, gdb::Server, Builder};
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        thread,
+        time::Duration,
+    };
+
+    // Minimal RSP client.
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn send(&mut self, data: &str) {
+            let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+            write!(self.stream, "${}#{:02x}", data, sum).unwrap();
+        }
+
+        fn recv(&mut self) -> String {
+            let mut packet = Vec::new();
+            let mut byte = [0];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                match byte[0] {
+                    b'+' if packet.is_empty() => {}
+                    b'#' => break,
+                    b => packet.push(b),
+                }
+            }
+            let mut sum = [0; 2];
+            self.stream.read_exact(&mut sum).unwrap();
+            String::from_utf8(packet[1..].to_vec()).unwrap()
+        }
+
+        fn cmd(&mut self, data: &str) -> String {
+            self.send(data);
+            self.recv()
+        }
+    }
+
+    #[test]
+    fn session() {
+        let mut rom = vec![0; 0x8000];
+        // 0100: LD HL,$C000
+        // 0103: INC A
+        // 0104: LD (HL),A
+        // 0105: INC L
+        // 0106: JR $0103
+        rom[0x0100..0x0108].copy_from_slice(&[0x21, 0x00, 0xc0, 0x3c, 0x77, 0x2c, 0x18, 0xfb]);
+        let mut dmg = Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                                        .build();
+
+        let server = Server::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut c = Client { stream: TcpStream::connect(addr).unwrap() };
+            assert!(c.cmd("qSupported:swbreak+")
+                     .contains("qXfer:features:read+"));
+            assert!(c.cmd("qXfer:features:read:target.xml:0,1000")
+                     .starts_with("l<?xml"));
+            assert_eq!("S05", c.cmd("?"));
+
+            // pc = 0x0100, sp = 0xfffe
+            let regs = c.cmd("g");
+            assert_eq!(24, regs.len());
+            assert_eq!("feff0001", &regs[16..]);
+            assert_eq!("0001", c.cmd("p5"));
+
+            // step over LD HL,$C000
+            assert_eq!("S05", c.cmd("s"));
+            assert_eq!("0301", c.cmd("p5"));
+            assert_eq!("00c0", c.cmd("p3"));
+
+            // memory
+            assert_eq!("2100c0", c.cmd("m100,3"));
+            assert_eq!("OK", c.cmd("Mc100,2:abcd"));
+            assert_eq!("abcd", c.cmd("mc100,2"));
+            // the unused APU registers (FF27-FF2F) read as FF
+            let io = c.cmd("mff00,80");
+            assert_eq!(0x100, io.len());
+            assert_eq!("ffffffffffffffffff", &io[0x4e..0x60]);
+
+            // breakpoint
+            assert_eq!("OK", c.cmd("Z0,105,1"));
+            assert_eq!("T05swbreak:;", c.cmd("c"));
+            assert_eq!("0501", c.cmd("p5"));
+            assert_eq!("OK", c.cmd("z0,105,1"));
+
+            // watchpoint
+            assert_eq!("OK", c.cmd("Z2,c010,1"));
+            assert_eq!("T05watch:c010;", c.cmd("c"));
+            assert_eq!("OK", c.cmd("z2,c010,1"));
+
+            // write registers
+            assert_eq!("OK", c.cmd("P5=0301"));
+            assert_eq!("0301", c.cmd("p5"));
+
+            // interrupt
+            c.send("c");
+            thread::sleep(Duration::from_millis(50));
+            c.stream.write_all(&[0x03]).unwrap();
+            assert_eq!("S02", c.recv());
+
+            assert_eq!("OK", c.cmd("D"));
+        });
+
+        let mut session = server.accept().unwrap();
+        session.run(&mut dmg).unwrap();
+        client.join().unwrap();
+
+        assert_eq!(0, dmg.mmu().debugger().unwrap().breakpoints().count());
+    }
+}
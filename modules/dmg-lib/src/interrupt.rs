@@ -1,4 +1,7 @@
-use crate::device::Device;
+use crate::{
+    device::Device,
+    state::{Error, Reader, State, Writer},
+};
 
 #[repr(u8)]
 pub enum Flag {
@@ -57,6 +60,19 @@ impl Device for Interrupts {
     }
 }
 
+impl State for Interrupts {
+    fn save_state(&self, state: &mut Writer) {
+        state.u8(self.if_);
+        state.u8(self.ie);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.if_ = state.u8()?;
+        self.ie = state.u8()?;
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::{
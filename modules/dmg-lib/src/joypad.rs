@@ -1,8 +1,16 @@
-use crate::{device::Device, interrupt::Flag};
+use crate::{
+    device::Device,
+    interrupt::Flag,
+    sgb::PACKET_SIZE,
+    state::{Error, Reader, State, Writer},
+};
 
 const BTN_ROW_FLAG: u8 = 0x10;
 const DIR_ROW_FLAG: u8 = 0x20;
 
+/// Maximum number of joypads (SGB multiplayer).
+pub const MAX_PLAYERS: usize = 4;
+
 /// Buttons.
 #[derive(Debug, Clone, Copy)]
 pub enum Key {
@@ -12,7 +20,7 @@ pub enum Key {
 
 /// Action buttons.
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Btn {
     Start = 0x8,
     Select = 0x4,
@@ -22,7 +30,7 @@ pub enum Btn {
 
 /// Directional buttons.
 #[repr(u8)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum Dir {
     Down = 0x8,
     Up = 0x4,
@@ -30,29 +38,69 @@ pub enum Dir {
     Right = 0x1,
 }
 
+// State of the keys of a single joypad (0=Pressed).
+#[derive(Clone, Copy)]
+struct Pad {
+    btn: u8,
+    dir: u8,
+}
+
+impl Default for Pad {
+    fn default() -> Self {
+        Self { btn: 0xff,
+               dir: 0xff }
+    }
+}
+
+// SGB packet being received through the P14 & P15 lines.
+struct Packet {
+    data: [u8; PACKET_SIZE],
+    bit: usize,
+}
+
 /// Joypad emulation.
 pub struct Joypad {
     int: Option<Flag>,
     joyp: u8,
-    btn: u8,
-    dir: u8,
+    pads: [Pad; MAX_PLAYERS],
+    packet: Option<Packet>,
+    received: Option<[u8; PACKET_SIZE]>,
+    // SGB multiplayer (number of players & current joypad).
+    players: u8,
+    player: u8,
 }
 
 impl Default for Joypad {
     fn default() -> Self {
         Self { int: None,
                joyp: 0x00,
-               btn: 0xff,
-               dir: 0xff }
+               pads: [Pad::default(); MAX_PLAYERS],
+               packet: None,
+               received: None,
+               players: 1,
+               player: 0 }
     }
 }
 
 impl Joypad {
-    /// Register new keypad input.
+    /// Register new keypad input (first joypad).
     pub fn press(&mut self, key: Key) {
+        self.press_player(0, key);
+    }
+
+    /// Register the release of a keypad input (first joypad).
+    pub fn release(&mut self, key: Key) {
+        self.release_player(0, key);
+    }
+
+    /// Register new keypad input on the given joypad (0 to 3). Joypads other
+    /// than the first are only read in SGB multiplayer mode.
+    pub fn press_player(&mut self, player: usize, key: Key) {
+        assert!(player < MAX_PLAYERS, "Invalid player {}", player);
+        let pad = &mut self.pads[player];
         let (btn, dir) = match key {
-            Key::Btn(btn) => (self.btn & !(btn as u8), self.dir),
-            Key::Dir(dir) => (self.btn, self.dir & !(dir as u8)),
+            Key::Btn(btn) => (pad.btn & !(btn as u8), pad.dir),
+            Key::Dir(dir) => (pad.btn, pad.dir & !(dir as u8)),
         };
 
         // Joypad interrupt is requested when any of the above Input lines changes from
@@ -60,25 +108,113 @@ impl Joypad {
         // (provided that the button/direction key is enabled by above Bit4/5), however,
         // because of switch bounce, one or more High to Low transitions are usually
         // produced both when pressing or releasing a key.
-        if self.btn != btn || self.dir != dir {
+        if pad.btn != btn || pad.dir != dir {
             self.int = Some(Flag::Joypad);
         }
 
-        self.btn = btn;
-        self.dir = dir;
+        pad.btn = btn;
+        pad.dir = dir;
     }
 
-    /// Register the release of a keypad input.
-    pub fn release(&mut self, key: Key) {
+    /// Register the release of a keypad input on the given joypad (0 to 3).
+    pub fn release_player(&mut self, player: usize, key: Key) {
+        assert!(player < MAX_PLAYERS, "Invalid player {}", player);
+        let pad = &mut self.pads[player];
         match key {
-            Key::Btn(btn) => self.btn |= btn as u8,
-            Key::Dir(dir) => self.dir |= dir as u8,
+            Key::Btn(btn) => pad.btn |= btn as u8,
+            Key::Dir(dir) => pad.dir |= dir as u8,
         }
     }
 
+    /// Return the keys pressed on the given joypad (0 to 3), as a bitmask of
+    /// [`Btn`] (bits 0-3) and [`Dir`] (bits 4-7) values.
+    ///
+    /// [`Btn`]: #
+    /// [`Dir`]: #
+    pub fn keys(&self, player: usize) -> u8 {
+        let pad = self.pads[player];
+        !(pad.dir << 4 | pad.btn & 0xf)
+    }
+
+    /// Set all the keys of the given joypad (0 to 3) at once, from a bitmask
+    /// returned by [`Joypad::keys`].
+    ///
+    /// [`Joypad::keys`]: #
+    pub fn set_keys(&mut self, player: usize, keys: u8) {
+        let pad = &mut self.pads[player];
+        let btn = !keys & 0xf | 0xf0;
+        let dir = !keys >> 4 | 0xf0;
+        // same as press, only newly pressed keys request the interrupt
+        if pad.btn & !btn != 0 || pad.dir & !dir != 0 {
+            self.int = Some(Flag::Joypad);
+        }
+        pad.btn = btn;
+        pad.dir = dir;
+    }
+
     pub(crate) fn take_int(&mut self) -> Option<Flag> {
         self.int.take()
     }
+
+    /// Return the ID of the joypad being read (0 to 3) in SGB multiplayer
+    /// mode.
+    pub fn player(&self) -> u8 {
+        self.player
+    }
+
+    /// Return the number of joypads being read (1, 2 or 4). More than one
+    /// joypad is only read after the game requests it through the SGB.
+    pub fn players(&self) -> u8 {
+        self.players
+    }
+
+    // Take the last SGB packet received.
+    pub(crate) fn take_packet(&mut self) -> Option<[u8; PACKET_SIZE]> {
+        self.received.take()
+    }
+
+    // Set the number of players (SGB MLT_REQ command).
+    pub(crate) fn set_players(&mut self, players: u8) {
+        if self.players != players {
+            self.players = players;
+            self.player = 0;
+        }
+    }
+
+    // SGB packets are sent one bit at a time. A transfer starts with a reset
+    // pulse (P14 & P15 low), followed by 128 pulses of either line (P14 for
+    // 0, P15 for 1) separated by both lines high, and a stop bit (0).
+    //
+    // Outside of transfers, the current joypad changes on the rising edge of P15
+    // in multiplayer mode.
+    fn transfer(&mut self, prev: u8, next: u8) {
+        match (prev, next) {
+            (_, 0x00) => {
+                self.packet = Some(Packet { data: [0; PACKET_SIZE],
+                                            bit: 0 })
+            }
+            (0x30, 0x10) | (0x30, 0x20) => {
+                if let Some(packet) = &mut self.packet {
+                    if packet.bit < 8 * PACKET_SIZE {
+                        if next == 0x10 {
+                            packet.data[packet.bit / 8] |= 1 << (packet.bit % 8);
+                        }
+                        packet.bit += 1;
+                    } else {
+                        // a stop bit of 1 discards the packet
+                        if next == 0x20 {
+                            self.received = Some(packet.data);
+                        }
+                        self.packet = None;
+                    }
+                }
+            }
+            (_, 0x30) if prev & 0x20 == 0 && self.packet.is_none() && self.players > 1 => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => {}
+        }
+    }
 }
 
 // The eight gameboy buttons/direction keys are arranged in form of a 2x4
@@ -96,10 +232,12 @@ impl Joypad {
 impl Device for Joypad {
     fn read(&self, addr: u16) -> u8 {
         assert_eq!(0xff00, addr);
+        let Pad { btn, dir } = self.pads[self.player as usize];
         match self.joyp & 0x30 {
-            BTN_ROW_FLAG => BTN_ROW_FLAG | (self.btn & 0xf),
-            DIR_ROW_FLAG => DIR_ROW_FLAG | (self.dir & 0xf),
-            0x30 => 0x3f,
+            BTN_ROW_FLAG => BTN_ROW_FLAG | (btn & 0xf),
+            DIR_ROW_FLAG => DIR_ROW_FLAG | (dir & 0xf),
+            // joypad ID (0xf for the first one)
+            0x30 => 0x30 | (0xf - self.player),
             0x0 => 0xf,
             _ => unreachable!(),
         }
@@ -107,7 +245,65 @@ impl Device for Joypad {
 
     fn write(&mut self, addr: u16, data: u8) {
         assert_eq!(0xff00, addr);
+        let prev = self.joyp & 0x30;
         self.joyp = data;
+        self.transfer(prev, data & 0x30);
+    }
+}
+
+impl State for Joypad {
+    fn save_state(&self, state: &mut Writer) {
+        state.bool(self.int.is_some());
+        state.u8(self.joyp);
+        for pad in self.pads.iter() {
+            state.u8(pad.btn);
+            state.u8(pad.dir);
+        }
+        state.bool(self.packet.is_some());
+        if let Some(packet) = &self.packet {
+            state.bytes(&packet.data);
+            state.u8(packet.bit as u8);
+        }
+        state.bool(self.received.is_some());
+        if let Some(received) = &self.received {
+            state.bytes(received);
+        }
+        state.u8(self.players);
+        state.u8(self.player);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.int = if state.bool()? {
+            Some(Flag::Joypad)
+        } else {
+            None
+        };
+        self.joyp = state.u8()?;
+        for pad in self.pads.iter_mut() {
+            pad.btn = state.u8()?;
+            pad.dir = state.u8()?;
+        }
+        self.packet = if state.bool()? {
+            let mut data = [0; PACKET_SIZE];
+            state.bytes(&mut data)?;
+            let bit = state.u8()? as usize;
+            Some(Packet { data, bit })
+        } else {
+            None
+        };
+        self.received = if state.bool()? {
+            let mut data = [0; PACKET_SIZE];
+            state.bytes(&mut data)?;
+            Some(data)
+        } else {
+            None
+        };
+        self.players = state.u8()?;
+        self.player = state.u8()?;
+        if self.player >= self.players || self.players as usize > MAX_PLAYERS {
+            return Err(Error::InvalidData);
+        }
+        Ok(())
     }
 }
 
@@ -116,8 +312,105 @@ mod tests {
     use crate::{
         device::Device,
         joypad::{Btn::*, Dir::*, Joypad, Key, BTN_ROW_FLAG, DIR_ROW_FLAG},
+        sgb::PACKET_SIZE,
     };
 
+    fn send_packet(joypad: &mut Joypad, data: &[u8; PACKET_SIZE], stop: u8) {
+        joypad.write(0xff00, 0x00);
+        joypad.write(0xff00, 0x30);
+        for bit in 0..8 * PACKET_SIZE {
+            let one = data[bit / 8] >> (bit % 8) & 0x1 != 0;
+            joypad.write(0xff00, if one { 0x10 } else { 0x20 });
+            joypad.write(0xff00, 0x30);
+        }
+        joypad.write(0xff00, stop);
+        joypad.write(0xff00, 0x30);
+    }
+
+    #[test]
+    fn sgb_packet() {
+        let mut joypad = Joypad::default();
+        let mut data = [0; PACKET_SIZE];
+        data[0] = 0x89; // MLT_REQ
+        data[1] = 0x01;
+        data[15] = 0x80;
+
+        send_packet(&mut joypad, &data, 0x20);
+        assert_eq!(Some(data), joypad.take_packet());
+        assert_eq!(None, joypad.take_packet());
+
+        // invalid stop bit
+        send_packet(&mut joypad, &data, 0x10);
+        assert_eq!(None, joypad.take_packet());
+    }
+
+    #[test]
+    fn sgb_multiplayer() {
+        let mut joypad = Joypad::default();
+        joypad.write(0xff00, 0x30);
+        joypad.set_players(2);
+        joypad.press(Key::Btn(A));
+        assert_eq!(0x3f, joypad.read(0xff00));
+        joypad.write(0xff00, DIR_ROW_FLAG);
+        joypad.write(0xff00, BTN_ROW_FLAG);
+        assert_eq!(BTN_ROW_FLAG | 0b1101, joypad.read(0xff00));
+        joypad.write(0xff00, 0x30);
+        assert_eq!(0x3e, joypad.read(0xff00));
+        joypad.write(0xff00, BTN_ROW_FLAG);
+        assert_eq!(BTN_ROW_FLAG | 0xf, joypad.read(0xff00));
+        joypad.write(0xff00, 0x30);
+        assert_eq!(0x3f, joypad.read(0xff00));
+    }
+
+    #[test]
+    fn four_players() {
+        let mut joypad = Joypad::default();
+        joypad.write(0xff00, 0x30);
+        joypad.set_players(4);
+        joypad.press_player(2, Key::Dir(Up));
+        joypad.press_player(3, Key::Btn(Start));
+
+        let read = |joypad: &mut Joypad| {
+            joypad.write(0xff00, DIR_ROW_FLAG);
+            let dir = joypad.read(0xff00);
+            joypad.write(0xff00, BTN_ROW_FLAG);
+            let btn = joypad.read(0xff00);
+            joypad.write(0xff00, 0x30);
+            (dir & 0xf, btn & 0xf)
+        };
+        assert_eq!((0xf, 0xf), read(&mut joypad)); // player 1
+        assert_eq!((0xf, 0xf), read(&mut joypad)); // player 2
+        assert_eq!((0b1011, 0xf), read(&mut joypad));
+        assert_eq!((0xf, 0b0111), read(&mut joypad));
+        assert_eq!(0x3f, joypad.read(0xff00));
+
+        joypad.release_player(2, Key::Dir(Up));
+        read(&mut joypad);
+        read(&mut joypad);
+        assert_eq!((0xf, 0xf), read(&mut joypad));
+    }
+
+    #[test]
+    fn keys() {
+        let mut joypad = Joypad::default();
+        joypad.press_player(1, Key::Btn(A));
+        joypad.press_player(1, Key::Dir(Left));
+        assert_eq!(0x00, joypad.keys(0));
+        assert_eq!(0x22, joypad.keys(1));
+
+        joypad.set_keys(0, 0x81);
+        joypad.write(0xff00, DIR_ROW_FLAG);
+        assert_eq!(DIR_ROW_FLAG | 0b0111, joypad.read(0xff00));
+        joypad.write(0xff00, BTN_ROW_FLAG);
+        assert_eq!(BTN_ROW_FLAG | 0b1110, joypad.read(0xff00));
+        assert_eq!(0x81, joypad.keys(0));
+        assert!(joypad.take_int().is_some());
+
+        // releasing keys doesn't request the interrupt
+        joypad.set_keys(0, 0x01);
+        assert!(joypad.take_int().is_none());
+    }
+
     #[test]
     fn joypad_never_0() {
         let mut joypad = Joypad::default();
@@ -2,36 +2,169 @@
             warn(dead_code, unused_imports, unused_variables))]
 #![deny(clippy::style, clippy::correctness, clippy::complexity, clippy::perf)]
 use crate::{
-    apu::device::Audio, cartridge::Cartridge, cpu::Cpu, device::Device, mmu::Mmu, ppu::Video,
+    apu::device::Audio,
+    boot::BootRom,
+    cartridge::Cartridge,
+    cpu::Cpu,
+    debug::Hit,
+    device::Device,
+    joypad::{Btn, Dir},
+    mmu::{Mmu, FRAME_CYCLES},
+    movie::{Boot, Movie, Session, Status},
+    ppu::Video,
+    state::{Reader, State, Writer},
 };
-use std::marker::PhantomData;
+use std::{fmt, marker::PhantomData};
 
 pub mod apu;
+pub mod boot;
 pub mod cartridge;
+pub mod cdl;
+pub mod cheats;
 mod clock;
 pub mod cpu;
+pub mod debug;
 pub mod device;
+pub mod disasm;
+pub mod gbs;
+pub mod gdb;
 pub mod interrupt;
 pub mod joypad;
 pub mod mmu;
+pub mod movie;
 pub mod ppu;
+pub mod profile;
+pub mod search;
+pub mod sgb;
+pub mod state;
+pub mod symbols;
 pub mod timer;
+pub mod trace;
+pub mod vgm;
 pub mod vram;
 pub mod wram;
 
 const CLOCK: u64 = 4_194_304;
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+/// Errors returned by the emulator.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// The size of the boot ROM image (in bytes) doesn't match any of the
+    /// supported models.
+    BootRomSize(usize),
+    /// The boot ROM image is for another model (a DMG boot ROM on the CGB, or
+    /// the other way around).
+    BootRomModel(Model),
+    /// The cartridge only runs on the CGB, but a monochrome model was
+    /// requested.
+    CgbOnly,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::BootRomSize(size) => write!(f, "Invalid boot ROM size ({} bytes)", size),
+            Error::BootRomModel(model) => {
+                write!(f, "The boot ROM doesn't match the model ({:?})", model)
+            }
+            Error::CgbOnly => write!(f, "The cartridge requires a Game Boy Color"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum Mode {
     GB,
     CGB,
 }
 
+impl State for Mode {
+    fn save_state(&self, state: &mut Writer) {
+        state.u8(*self as u8);
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), state::Error> {
+        *self = match state.u8()? {
+            0 => Mode::GB,
+            1 => Mode::CGB,
+            _ => return Err(state::Error::InvalidData),
+        };
+        Ok(())
+    }
+}
+
+/// Emulated hardware model.
+///
+/// Besides selecting the emulation [`Mode`], the model determines the state
+/// left by the boot ROM (CPU registers, I/O registers & VRAM), which some games
+/// rely on to detect the hardware they are running on.
+///
+/// [`Mode`]: #
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Model {
+    /// Original Game Boy with the early (DMG-CPU 0) boot ROM.
+    Dmg0,
+    /// Original Game Boy.
+    Dmg,
+    /// Game Boy Pocket & Game Boy Light.
+    Mgb,
+    /// Super Game Boy.
+    Sgb,
+    /// Super Game Boy 2.
+    Sgb2,
+    /// Game Boy Color.
+    Cgb,
+    /// Game Boy Advance.
+    Agb,
+}
+
+impl Model {
+    /// Return the emulation mode of the model.
+    pub fn mode(self) -> Mode {
+        match self {
+            Model::Dmg0 | Model::Dmg | Model::Mgb | Model::Sgb | Model::Sgb2 => Mode::GB,
+            Model::Cgb | Model::Agb => Mode::CGB,
+        }
+    }
+
+    /// Returns true for the Super Game Boy models.
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+}
+
+/// Result of advancing the emulation with one of the stepping methods of
+/// [`GameBoy`].
+///
+/// [`GameBoy`]: #
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Step {
+    /// Elapsed cycles of the 4MHz clock (the CPU runs twice as many in CGB
+    /// double speed mode).
+    pub cycles: u64,
+    /// A frame was completed (the same amount of time emulated by a call to
+    /// [`GameBoy::emulate_frame`]).
+    ///
+    /// [`GameBoy::emulate_frame`]: #
+    pub frame: bool,
+    /// The breakpoint or watchpoint that stopped the emulation, if any (see the
+    /// [`debug`] module).
+    ///
+    /// [`debug`]: #
+    pub hit: Option<Hit>,
+}
+
 // TODO consider not abusing generics.
 pub struct GameBoy<C: Cartridge, V: Video, D: Audio> {
     cpu: Cpu,
     mmu: Mmu<C, V, D>,
     carry: u64,
+    boot: Boot,
+    movie: Option<Session>,
+    // Set when the last call to emulate_frame was stopped by the debugger.
+    interrupted: bool,
 }
 
 impl Default for GameBoy<(), (), ()> {
@@ -41,8 +174,99 @@ impl Default for GameBoy<(), (), ()> {
 }
 
 impl<C: Cartridge, V: Video, D: Audio> GameBoy<C, V, D> {
-    pub fn emulate_frame(&mut self) {
-        self.carry = self.mmu.emulate_frame(&mut self.cpu, self.carry);
+    /// Emulate until the end of the current frame.
+    ///
+    /// If a breakpoint or a watchpoint is hit (see the [`debug`] module), it
+    /// returns early. The next call resumes the same frame.
+    ///
+    /// The GameShark codes (see the [`cheats`] module) are applied at the start
+    /// of every frame.
+    ///
+    /// [`debug`]: #
+    /// [`cheats`]: #
+    pub fn emulate_frame(&mut self) -> Step {
+        if !self.interrupted {
+            self.mmu.apply_cheats();
+        }
+        let mut movie = self.movie.take();
+        if let Some(movie) = &mut movie {
+            if !self.interrupted {
+                movie.begin_frame(self.mmu.joypad_mut());
+            }
+        }
+        let step = self.run_while(|step, _| !step.frame);
+        self.interrupted = !step.frame;
+        if let Some(movie) = &mut movie {
+            if step.frame {
+                movie.end_frame(|| self.state_hash());
+            }
+        }
+        self.movie = movie;
+        step
+    }
+
+    /// Execute a single CPU instruction, keeping the rest of the components in
+    /// sync. If an interrupt is serviced, or the CPU is halted, no instruction
+    /// is executed, but time still advances.
+    ///
+    /// Unlike [`GameBoy::emulate_frame`], the stepping methods don't advance
+    /// the movie being recorded or played, if any.
+    ///
+    /// [`GameBoy::emulate_frame`]: #
+    pub fn step_instruction(&mut self) -> Step {
+        self.step()
+    }
+
+    /// Run instructions until at least the given number of cycles (of the 4MHz
+    /// clock) have elapsed.
+    pub fn run_cycles(&mut self, cycles: u64) -> Step {
+        self.run_while(|step, _| step.cycles < cycles)
+    }
+
+    /// Run instructions until the PPU starts drawing the given scanline (the
+    /// LY register changes to `ly`). Lines 144 to 153 are the vertical blank.
+    ///
+    /// If the scanline isn't reached within a frame (the LCD is off, or `ly`
+    /// is out of range), it stops after one frame.
+    pub fn run_until_scanline(&mut self, ly: u8) -> Step {
+        let mut last = self.mmu.read(0xff44);
+        self.run_while(|step, mmu| {
+                let line = mmu.read(0xff44);
+                let reached = line == ly && line != last;
+                last = line;
+                !reached && step.cycles < FRAME_CYCLES
+            })
+    }
+
+    /// Run instructions until the start of the next vertical blank period.
+    pub fn run_until_vblank(&mut self) -> Step {
+        self.run_until_scanline(144)
+    }
+
+    // Run instructions while the given function returns true, or until the
+    // debugger stops the emulation. It is called after each instruction with the
+    // accumulated result.
+    fn run_while(&mut self, mut cond: impl FnMut(&Step, &Mmu<C, V, D>) -> bool) -> Step {
+        let mut result = Step::default();
+        loop {
+            let step = self.step();
+            result.cycles += step.cycles;
+            result.frame |= step.frame;
+            result.hit = step.hit;
+            if result.hit.is_some() || !cond(&result, &self.mmu) {
+                return result;
+            }
+        }
+    }
+
+    fn step(&mut self) -> Step {
+        let (cycles, hit) = self.mmu.step_cpu(&mut self.cpu);
+        self.carry += cycles;
+        let frame = self.carry >= FRAME_CYCLES;
+        if frame {
+            self.carry -= FRAME_CYCLES;
+        }
+        Step { cycles, frame, hit }
     }
 
     /// Return the Memory Manager Unit (MMU).
@@ -64,12 +288,105 @@ impl<C: Cartridge, V: Video, D: Audio> GameBoy<C, V, D> {
     pub fn cpu_mut(&mut self) -> &mut Cpu {
         &mut self.cpu
     }
+
+    /// Save the state of the emulator (see the [`state`] module).
+    ///
+    /// [`state`]: #
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Writer::new(self.mmu.model());
+        self.cpu.save_state(&mut state);
+        self.mmu.save_state(&mut state);
+        state.u64(self.carry);
+        state.into_bytes()
+    }
+
+    /// Restore a state returned by [`GameBoy::save_state`]. The emulator must
+    /// have been built with the same model and cartridge.
+    ///
+    /// If an error is returned, the emulator may have been left with part of
+    /// the state loaded, and should be reset.
+    ///
+    /// [`GameBoy::save_state`]: #
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), state::Error> {
+        let mut state = Reader::new(data, self.mmu.model())?;
+        self.cpu.load_state(&mut state)?;
+        self.mmu.load_state(&mut state)?;
+        self.carry = state.u64()?;
+        if !state.is_empty() {
+            return Err(state::Error::InvalidData);
+        }
+        Ok(())
+    }
+
+    /// Return a hash of the state of the emulator. Two emulators in the same
+    /// state return the same hash, regardless of the platform.
+    pub fn state_hash(&self) -> u64 {
+        state::hash(&self.save_state())
+    }
+
+    /// Return the options the emulator was built with.
+    pub fn boot(&self) -> &Boot {
+        &self.boot
+    }
+
+    /// Start recording the joypad input into a movie (see the [`movie`]
+    /// module). The `rom` is only used to compute its hash.
+    ///
+    /// If `savestate` is false, the movie is expected to start from power on
+    /// (the emulator has just been built), otherwise the current state is
+    /// stored in the movie. Any movie being recorded or played is dropped.
+    ///
+    /// [`movie`]: #
+    pub fn start_recording(&mut self, rom: &[u8], savestate: bool) {
+        let start = if savestate {
+            Some(self.save_state())
+        } else {
+            None
+        };
+        let movie = Movie::new(rom, self.boot, start, self.state_hash());
+        self.movie = Some(Session::record(movie));
+    }
+
+    /// Play a movie back. The input recorded for each frame is set on the
+    /// joypads on every call to [`GameBoy::emulate_frame`].
+    ///
+    /// The emulator must have been built with the options of the movie (see
+    /// [`Boot::configure`]), from the same ROM. Movies that don't start from a
+    /// savestate must be played right after the emulator is built.
+    ///
+    /// [`GameBoy::emulate_frame`]: #
+    /// [`Boot::configure`]: #
+    pub fn play_movie(&mut self, rom: &[u8], movie: Movie) -> Result<(), movie::Error> {
+        movie.check_rom(rom)?;
+        if *movie.boot() != self.boot {
+            return Err(movie::Error::Boot);
+        }
+        if let Some(start) = movie.start_state() {
+            self.load_state(start)?;
+        }
+        let session = Session::play(movie);
+        session.check_start(self.state_hash())?;
+        self.movie = Some(session);
+        Ok(())
+    }
+
+    /// Stop recording or playing a movie, and return it.
+    pub fn stop_movie(&mut self) -> Option<Movie> {
+        self.movie.take().map(Session::into_movie)
+    }
+
+    /// Return the status of the movie being recorded or played, if any.
+    pub fn movie_status(&self) -> Option<Status> {
+        self.movie.as_ref().map(Session::status)
+    }
 }
 
 pub struct Builder<C: Cartridge, V: Video, D: Audio> {
     _phantom: PhantomData<D>,
-    mode: Option<Mode>,
+    model: Option<Model>,
     skip_boot: bool,
+    boot_rom: Option<BootRom>,
+    compat_keys: Option<(Dir, Option<Btn>)>,
     cartridge: C,
     video: V,
 }
@@ -77,8 +394,10 @@ pub struct Builder<C: Cartridge, V: Video, D: Audio> {
 impl Default for Builder<(), (), ()> {
     fn default() -> Self {
         Self { _phantom: PhantomData,
-               mode: None,
+               model: None,
                skip_boot: false,
+               boot_rom: None,
+               compat_keys: None,
                cartridge: (),
                video: () }
     }
@@ -87,114 +406,277 @@ impl Default for Builder<(), (), ()> {
 impl<C: Cartridge, V: Video, D: Audio> Builder<C, V, D> {
     pub fn audio<D2: Audio>(self) -> Builder<C, V, D2> {
         Builder { _phantom: PhantomData,
-                  mode: self.mode,
+                  model: self.model,
                   skip_boot: self.skip_boot,
+                  boot_rom: self.boot_rom,
+                  compat_keys: self.compat_keys,
                   cartridge: self.cartridge,
                   video: self.video }
     }
 
     pub fn cartridge<C2: Cartridge>(self, cartridge: C2) -> Builder<C2, V, D> {
         Builder { _phantom: PhantomData,
-                  mode: self.mode,
+                  model: self.model,
                   skip_boot: self.skip_boot,
+                  boot_rom: self.boot_rom,
+                  compat_keys: self.compat_keys,
                   cartridge,
                   video: self.video }
     }
 
     pub fn video<V2: Video>(self, video: V2) -> Builder<C, V2, D> {
         Builder { _phantom: PhantomData,
-                  mode: self.mode,
+                  model: self.model,
                   skip_boot: self.skip_boot,
+                  boot_rom: self.boot_rom,
+                  compat_keys: self.compat_keys,
                   cartridge: self.cartridge,
                   video }
     }
 
-    /// Disable dmg-data rom. If the crate is not built using the *dmg-data*
-    /// feature flag, this is a no-op as the dmg-data rom will be always
-    /// skipped.
+    /// Run the given boot ROM before the cartridge.
+    pub fn boot_rom(mut self, boot_rom: BootRom) -> Self {
+        self.boot_rom = Some(boot_rom);
+        self
+    }
+
+    /// Skip the boot ROM, even if one has been given with
+    /// [`Builder::boot_rom`]. If there isn't a boot ROM, this is a no-op as
+    /// the boot sequence will always be skipped.
+    ///
+    /// [`Builder::boot_rom`]: #
     pub fn skip_boot(mut self) -> Self {
         self.skip_boot = true;
         self
     }
 
-    fn with_mode(mut self, mode: Mode) -> Self {
-        self.mode = Some(mode);
+    /// Set the emulated hardware model. By default, it is picked from the
+    /// cartridge header (see [`Builder::auto_mode`]).
+    ///
+    /// [`Builder::auto_mode`]: #
+    pub fn model(mut self, model: Model) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Pick the model from the CGB flag of the cartridge header (0143): games
+    /// that support the CGB (80h) or require it (C0h) run on the CGB, the rest
+    /// of them on the DMG. This is the default.
+    pub fn auto_mode(mut self) -> Self {
+        self.model = None;
+        self
+    }
+
+    /// Colorize DMG games on the CGB with one of the alternate palettes, as if
+    /// the given direction (and optionally A or B) had been held during the
+    /// boot animation. Only used when the boot ROM is skipped.
+    pub fn compat_palette(mut self, dir: Dir, btn: Option<Btn>) -> Self {
+        self.compat_keys = Some((dir, btn));
         self
     }
 
     /// Set up for Color Game Boy emulation.
-    pub fn gbc_mode(mut self) -> Self {
-        self.with_mode(Mode::CGB)
+    pub fn gbc_mode(self) -> Self {
+        self.model(Model::Cgb)
     }
 
     /// Set up for Non-color Game Boy emulation.
-    pub fn gb_mode(mut self) -> Self {
-        self.with_mode(Mode::GB)
+    pub fn gb_mode(self) -> Self {
+        self.model(Model::Dmg)
     }
 
+    /// Build the emulator.
+    ///
+    /// # Panics
+    /// Panics if the cartridge requires a CGB and a monochrome model was
+    /// requested. See [`Builder::try_build`].
+    ///
+    /// [`Builder::try_build`]: #
     pub fn build(self) -> GameBoy<C, V, D> {
+        self.try_build().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Build the emulator, returning an error if the cartridge can't run on
+    /// the requested model.
+    pub fn try_build(self) -> Result<GameBoy<C, V, D>, Error> {
+        let cgb_flag = self.cartridge.read(0x0143);
+        let model = match self.model {
+            Some(model) => model,
+            None if cgb_flag & 0x80 != 0 => Model::Cgb,
+            None => Model::Dmg,
+        };
+        if cgb_flag == 0xc0 && model.mode() == Mode::GB {
+            return Err(Error::CgbOnly);
+        }
+
+        let boot_rom = if self.skip_boot { None } else { self.boot_rom };
+        if let Some(boot_rom) = &boot_rom {
+            if boot_rom.is_cgb() != (model.mode() == Mode::CGB) {
+                return Err(Error::BootRomModel(model));
+            }
+        }
+        let boot = Boot { model,
+                          boot_rom: boot_rom.is_some(),
+                          compat_keys: self.compat_keys };
+
         let cartridge = self.cartridge;
-        let mode = self.mode.unwrap_or(Mode::CGB);
         let video = self.video;
         let mut dmg = GameBoy { cpu: Cpu::default(),
-                                mmu: Mmu::new(mode, cartridge, video),
-                                carry: 0 };
-
-        // FIXME Bugs:
-        //  - GB game on CGB mode (color palette is not set).
-        if self.skip_boot {
-            let cpu = dmg.cpu_mut();
-
-            // Initialize CPU
-            cpu.reg_mut().set_af(0x01b0);
-            cpu.reg_mut().set_bc(0x0013);
-            cpu.reg_mut().set_de(0x00d8);
-            cpu.reg_mut().set_hl(0x014d);
-            cpu.reg_mut().sp = 0xfffe;
-            cpu.reg_mut().pc = 0x0100;
-
-            // Games that work on both GB and CGB check the A register to detect CGB
-            // hardware and enhance games (mostly add color and better tiles).
-            if let Mode::CGB = mode {
-                cpu.reg_mut().a = 0x11;
+                                mmu: Mmu::new(model, cartridge, video),
+                                carry: 0,
+                                boot,
+                                movie: None,
+                                interrupted: false };
+
+        if let Some(boot_rom) = boot_rom {
+            dmg.mmu_mut().map_boot_rom(boot_rom);
+        } else {
+            boot::skip(model, self.compat_keys, &mut dmg.cpu, &mut dmg.mmu);
+        }
+        Ok(dmg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        cartridge::Rom,
+        device::Device,
+        joypad::{Btn, Key},
+        mmu::FRAME_CYCLES,
+        movie::{self, Status},
+        state, Builder, Error, Mode, Model,
+    };
+
+    fn rom(cgb_flag: u8) -> Rom {
+        Rom::new(rom_bytes(cgb_flag).into_boxed_slice())
+    }
+
+    fn rom_bytes(cgb_flag: u8) -> Vec<u8> {
+        let mut rom = vec![0; 0x8000];
+        rom[0x0143] = cgb_flag;
+        // LD HL,$C000
+        // loop: INC A
+        //       LD (HL),A
+        //       INC L
+        //       JR loop
+        rom[0x0100..0x0108].copy_from_slice(&[0x21, 0x00, 0xc0, 0x3c, 0x77, 0x2c, 0x18, 0xfb]);
+        rom
+    }
+
+    #[test]
+    fn auto_mode() {
+        let model = |cgb_flag| {
+            Builder::default().cartridge(rom(cgb_flag))
+                              .build()
+                              .mmu()
+                              .model()
+        };
+        assert_eq!(Model::Dmg, model(0x00));
+        assert_eq!(Model::Cgb, model(0x80));
+        assert_eq!(Model::Cgb, model(0xc0));
+
+        let dmg = Builder::default().cartridge(rom(0x80)).gb_mode().build();
+        assert_eq!(Mode::GB, dmg.mmu().mode());
+    }
+
+    #[test]
+    fn cgb_only() {
+        let dmg = Builder::default().cartridge(rom(0xc0))
+                                    .gb_mode()
+                                    .try_build();
+        assert_eq!(Some(Error::CgbOnly), dmg.err());
+    }
+
+    #[test]
+    fn save_state() {
+        let mut dmg = Builder::default().cartridge(rom(0x00)).build();
+        dmg.emulate_frame();
+        let state = dmg.save_state();
+        dmg.emulate_frame();
+        dmg.emulate_frame();
+        let hash = dmg.state_hash();
+        let wram = dmg.mmu().read(0xc000);
+
+        dmg.load_state(&state).unwrap();
+        assert_ne!(hash, dmg.state_hash());
+        dmg.emulate_frame();
+        dmg.emulate_frame();
+        assert_eq!(hash, dmg.state_hash());
+        assert_eq!(wram, dmg.mmu().read(0xc000));
+
+        let mut cgb = Builder::default().cartridge(rom(0x00)).gbc_mode().build();
+        assert_eq!(Err(state::Error::Model), cgb.load_state(&state));
+        assert_eq!(Err(state::Error::UnexpectedEof),
+                   dmg.load_state(&state[..state.len() - 1]));
+    }
+
+    #[test]
+    fn movie() {
+        let bytes = rom_bytes(0x00);
+        let mut dmg = Builder::default().cartridge(rom(0x00)).build();
+        dmg.start_recording(&bytes, false);
+        for frame in 0..150 {
+            match frame {
+                10 => dmg.mmu_mut().joypad_mut().press(Key::Btn(Btn::A)),
+                20 => dmg.mmu_mut().joypad_mut().release(Key::Btn(Btn::A)),
+                _ => {}
             }
+            dmg.emulate_frame();
+        }
+        assert_eq!(Some(Status::Recording(150)), dmg.movie_status());
+        let hash = dmg.state_hash();
+        let movie = dmg.stop_movie().unwrap();
+        assert_eq!(150, movie.len());
+        assert_eq!(Some([0x02, 0, 0, 0]), movie.input(10));
 
-            let mmu = dmg.mmu_mut();
-
-            // Initialize memory map
-            mmu.write(0xFF05, 0x00); // TIMA
-            mmu.write(0xFF06, 0x00); // TMA
-            mmu.write(0xFF07, 0x00); // TAC
-            mmu.write(0xFF10, 0x80); // NR10
-            mmu.write(0xFF11, 0xBF); // NR11
-            mmu.write(0xFF12, 0xF3); // NR12
-            mmu.write(0xFF14, 0xBF); // NR14
-            mmu.write(0xFF16, 0x3F); // NR21
-            mmu.write(0xFF17, 0x00); // NR22
-            mmu.write(0xFF19, 0xBF); // NR24
-            mmu.write(0xFF1A, 0x7F); // NR30
-            mmu.write(0xFF1B, 0xFF); // NR31
-            mmu.write(0xFF1C, 0x9F); // NR32
-            mmu.write(0xFF1E, 0xBF); // NR33
-            mmu.write(0xFF20, 0xFF); // NR41
-            mmu.write(0xFF21, 0x00); // NR42
-            mmu.write(0xFF22, 0x00); // NR43
-            mmu.write(0xFF23, 0xBF); // NR30
-            mmu.write(0xFF24, 0x77); // NR50
-            mmu.write(0xFF25, 0xF3); // NR51
-            mmu.write(0xFF26, 0xF1); // NR52
-            mmu.write(0xFF40, 0x91); // LCDC
-            mmu.write(0xFF42, 0x00); // SCY
-            mmu.write(0xFF43, 0x00); // SCX
-            mmu.write(0xFF45, 0x00); // LYC
-            mmu.write(0xFF47, 0xFC); // BGP
-            mmu.write(0xFF48, 0xFF); // OBP0
-            mmu.write(0xFF49, 0xFF); // OBP1
-            mmu.write(0xFF4A, 0x00); // WY
-            mmu.write(0xFF4B, 0x00); // WX
-            mmu.write(0xFFFF, 0x00); // IE
-            mmu.write(0xFF50, 0x01); // BOOT
+        let mut dmg = movie.boot()
+                           .configure(Builder::default().cartridge(rom(0x00)))
+                           .build();
+        dmg.play_movie(&bytes, movie.clone()).unwrap();
+        for _ in 0..150 {
+            dmg.emulate_frame();
         }
-        dmg
+        assert_eq!(Some(Status::Finished), dmg.movie_status());
+        assert_eq!(hash, dmg.state_hash());
+
+        // not at power on
+        let mut dmg = Builder::default().cartridge(rom(0x00)).build();
+        dmg.emulate_frame();
+        assert_eq!(Err(movie::Error::Desync(0)),
+                   dmg.play_movie(&bytes, movie.clone()));
+        assert_eq!(Err(movie::Error::Rom), dmg.play_movie(&bytes[1..], movie));
+    }
+
+    #[test]
+    fn step() {
+        let mut dmg = Builder::default().cartridge(rom(0x00)).build();
+
+        // LD HL,$C000
+        let step = dmg.step_instruction();
+        assert_eq!(12, step.cycles);
+        assert!(!step.frame);
+        assert_eq!(0x0103, dmg.cpu().reg().pc);
+
+        assert!(dmg.run_cycles(1000).cycles >= 1000);
+
+        let step = dmg.run_until_scanline(10);
+        assert!(!step.frame);
+        assert_eq!(10, dmg.mmu().read(0xff44));
+
+        let step = dmg.run_until_vblank();
+        assert_eq!(144, dmg.mmu().read(0xff44));
+        assert!(step.cycles < FRAME_CYCLES);
+
+        // from one vblank to the next
+        let step = dmg.run_until_vblank();
+        assert!(step.frame);
+        assert!(FRAME_CYCLES.abs_diff(step.cycles) < 24);
+
+        // LCD off
+        dmg.mmu_mut().write(0xff40, 0);
+        let step = dmg.run_until_vblank();
+        assert!(step.frame);
+        assert!(step.cycles >= FRAME_CYCLES);
     }
 }
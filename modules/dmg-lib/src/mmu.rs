@@ -1,14 +1,23 @@
 use crate::{
     apu::{device::Audio, Apu},
+    boot::BootRom,
     cartridge::Cartridge,
+    cdl::{self, CodeDataLog},
+    cheats::Cheats,
     cpu::Cpu,
+    debug::{Access, Debugger, Hit},
     device::Device,
     interrupt::Interrupts,
     joypad::Joypad,
     ppu::{Ppu, Video, HBLANK, PIXELS, SEARCH, VBLANK},
+    profile::{Event, Profiler},
+    state::{Error, Reader, State, Writer},
+    symbols,
     timer::Timer,
+    trace::Trace,
+    vgm::VgmLog,
     wram::WRam,
-    Mode, CLOCK,
+    Mode, Model,
 };
 
 // return value for the HDMA5 register some games expect all the bits to be set,
@@ -21,6 +30,9 @@ const HDMA5_DATA: u8 = 0xff;
 const HDMA_DATA: u8 = 0xff; // HDMA1..4
 const HRAM_SIZE: usize = 0x7f;
 
+/// Length of a frame, in cycles of the 4MHz clock.
+pub(crate) const FRAME_CYCLES: u64 = 144 * (SEARCH + PIXELS + HBLANK) + VBLANK;
+
 /// HRam memory
 pub type HRam = Box<[u8; HRAM_SIZE]>;
 
@@ -61,9 +73,11 @@ impl Default for VRamDma {
 // FF80-FFFE   High RAM (HRAM)
 // FFFF        Interrupt Enable Register
 pub struct Mmu<C: Cartridge, V: Video, D: Audio> {
-    #[cfg_attr(not(feature = "dmg-data"), allow(dead_code))]
+    model: Model,
     mode: Mode,
+    // Set when the boot ROM is unmapped (by writing to FF50).
     boot: bool,
+    boot_rom: Option<BootRom>,
     cartridge: C,
     ppu: Ppu<V>,
     apu: Apu<D>,
@@ -74,14 +88,27 @@ pub struct Mmu<C: Cartridge, V: Video, D: Audio> {
     vram_dma: VRamDma,
     int: Interrupts,
     speed: Speed,
+    vgm: Option<VgmLog>,
+    debugger: Option<Box<Debugger>>,
+    trace: Option<Box<Trace>>,
+    profiler: Option<Box<Profiler>>,
+    cdl: Option<Box<CodeDataLog>>,
+    cheats: Cheats,
 }
 
 impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
-    pub(crate) fn new(mode: Mode, cartridge: C, video_out: V) -> Self {
-        Self { mode,
+    pub(crate) fn new(model: Model, cartridge: C, video_out: V) -> Self {
+        let mode = model.mode();
+        let mut ppu = Ppu::new(mode, video_out);
+        if model.is_sgb() {
+            ppu.enable_sgb();
+        }
+        Self { model,
+               mode,
                cartridge,
-               boot: false,
-               ppu: Ppu::new(mode, video_out),
+               boot: true,
+               boot_rom: None,
+               ppu,
                timer: Timer::default(),
                wram: WRam::default(),
                joy: Joypad::default(),
@@ -89,7 +116,35 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
                hram: Box::new([0; HRAM_SIZE]),
                vram_dma: VRamDma::default(),
                int: Interrupts::default(),
-               speed: Speed::X1 }
+               speed: Speed::X1,
+               vgm: None,
+               debugger: None,
+               trace: None,
+               profiler: None,
+               cdl: None,
+               cheats: Cheats::default() }
+    }
+
+    // Map the boot ROM over the cartridge, until it is unmapped by writing to FF50.
+    pub(crate) fn map_boot_rom(&mut self, boot_rom: BootRom) {
+        self.boot = false;
+        self.boot_rom = Some(boot_rom);
+    }
+
+    // Run a DMG game on the CGB, with the colors given by the CGB palettes.
+    pub(crate) fn set_dmg_compat(&mut self) {
+        self.mode = Mode::GB;
+        self.ppu.set_dmg_compat();
+    }
+
+    /// Return the emulated hardware model.
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Return the emulation mode.
+    pub fn mode(&self) -> Mode {
+        self.mode
     }
 
     pub fn cartridge(&self) -> &C {
@@ -148,25 +203,269 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
         &mut self.hram
     }
 
-    pub(crate) fn emulate_frame(&mut self, cpu: &mut Cpu, carry: u64) -> u64 {
-        const FRAME_CYCLES: u64 = 144 * (SEARCH + PIXELS + HBLANK) + VBLANK;
+    /// Start logging writes to the APU registers. If the log had already been
+    /// started, it is restarted.
+    pub fn start_vgm_log(&mut self) {
+        let title = (0x134..=0x143).map(|addr| self.cartridge.read(addr))
+                                   .take_while(|b| b.is_ascii_graphic() || *b == b' ')
+                                   .map(char::from)
+                                   .collect::<String>();
+        let mut registers = [0; 0x30];
+        for (i, reg) in registers.iter_mut().enumerate() {
+            let addr = 0xff10 + i as u16;
+            if let 0xff10..=0xff26 | 0xff30..=0xff3f = addr {
+                *reg = self.read(addr);
+            }
+        }
+        self.vgm = Some(VgmLog::new(title.trim_end().to_string(), &registers));
+    }
 
-        let mut cycles = carry;
-        let mut cpu_rem = 0;
-        while cycles < FRAME_CYCLES {
-            let mut cpu_cycles = cpu.step(self);
+    /// Return the VGM log, if it has been started.
+    pub fn vgm_log(&self) -> Option<&VgmLog> {
+        self.vgm.as_ref()
+    }
 
-            if self.speed == Speed::X2 {
-                cpu_cycles /= 2;
+    /// Return the VGM log as mutable, if it has been started.
+    pub fn vgm_log_mut(&mut self) -> Option<&mut VgmLog> {
+        self.vgm.as_mut()
+    }
+
+    /// Stop logging writes to the APU registers, and return the log.
+    pub fn stop_vgm_log(&mut self) -> Option<VgmLog> {
+        self.vgm.take()
+    }
+
+    /// Attach a debugger (see the [`debug`] module), replacing the current one.
+    ///
+    /// [`debug`]: #
+    pub fn attach_debugger(&mut self, debugger: Debugger) {
+        self.debugger = Some(Box::new(debugger));
+    }
+
+    /// Return the debugger, if attached.
+    pub fn debugger(&self) -> Option<&Debugger> {
+        self.debugger.as_deref()
+    }
+
+    /// Return the debugger as mutable, if attached.
+    pub fn debugger_mut(&mut self) -> Option<&mut Debugger> {
+        self.debugger.as_deref_mut()
+    }
+
+    /// Detach the debugger, and return it.
+    pub fn detach_debugger(&mut self) -> Option<Debugger> {
+        self.debugger.take().map(|debugger| *debugger)
+    }
+
+    /// Start logging the executed instructions (see the [`trace`] module),
+    /// replacing the current trace.
+    ///
+    /// [`trace`]: #
+    pub fn start_trace(&mut self, trace: Trace) {
+        self.trace = Some(Box::new(trace));
+    }
+
+    /// Stop logging the executed instructions, and return the trace.
+    pub fn stop_trace(&mut self) -> Option<Trace> {
+        self.trace.take().map(|trace| *trace)
+    }
+
+    /// Start profiling the executed instructions (see the [`profile`] module),
+    /// replacing the current profiler.
+    ///
+    /// [`profile`]: #
+    pub fn start_profile(&mut self, profiler: Profiler) {
+        self.profiler = Some(Box::new(profiler));
+    }
+
+    /// Return the profiler, if profiling.
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_deref()
+    }
+
+    /// Stop profiling the executed instructions, and return the profiler.
+    pub fn stop_profile(&mut self) -> Option<Profiler> {
+        self.profiler.take().map(|profiler| *profiler)
+    }
+
+    /// Start logging the accesses to the cartridge ROM (see the [`cdl`]
+    /// module), replacing the current log.
+    ///
+    /// [`cdl`]: #
+    pub fn start_cdl(&mut self, cdl: CodeDataLog) {
+        self.cdl = Some(Box::new(cdl));
+    }
+
+    /// Return the code/data log, if logging.
+    pub fn cdl(&self) -> Option<&CodeDataLog> {
+        self.cdl.as_deref()
+    }
+
+    /// Stop logging the accesses to the cartridge ROM, and return the log.
+    pub fn stop_cdl(&mut self) -> Option<CodeDataLog> {
+        self.cdl.take().map(|cdl| *cdl)
+    }
+
+    pub fn cheats(&self) -> &Cheats {
+        &self.cheats
+    }
+
+    pub fn cheats_mut(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
+
+    // Apply the GameShark codes. Called at the start of every frame.
+    pub(crate) fn apply_cheats(&mut self) {
+        let writes: Vec<_> = self.cheats.writes().collect();
+        for (bank, addr, value) in writes {
+            match bank {
+                Some(bank) => self.wram.bank_mut(bank)[usize::from(addr - 0xd000)] = value,
+                None => self.bus_write(addr, value),
             }
+        }
+    }
+
+    /// Read memory without notifying the debugging tools (debugger, code/data
+    /// log).
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.bus_read(addr)
+    }
 
-            self.step(cpu_rem + cpu_cycles);
-            cycles += cpu_cycles;
+    /// The memory as a [`Device`] read with [`Mmu::peek`], for the tools that
+    /// take one (the disassembler...). Writes are ignored.
+    ///
+    /// [`Device`]: #
+    /// [`Mmu::peek`]: #
+    pub fn peeker(&self) -> Peek<'_, C, V, D> {
+        Peek(self)
+    }
+
+    // Called by the CPU to read the bytes of the instruction at PC (`opcode` is
+    // set for the first one).
+    pub(crate) fn fetch(&self, addr: u16, opcode: bool) -> u8 {
+        self.read_as(addr, if opcode { cdl::CODE } else { cdl::OPERAND })
+    }
+
+    // Read memory, notifying the debugger and the code/data log (with the given
+    // flags).
+    fn read_as(&self, addr: u16, flags: u8) -> u8 {
+        let data = self.bus_read(addr);
+        if let Some(debugger) = &self.debugger {
+            debugger.access(Access::Read, addr, data);
         }
+        self.log_cdl(addr, flags);
+        data
+    }
 
-        // return carry. This value should be passed as carry argument on the next call
-        // to this method.
-        cycles % FRAME_CYCLES
+    fn log_cdl(&self, addr: u16, flags: u8) {
+        let cdl = match &self.cdl {
+            Some(cdl) => cdl,
+            None => return,
+        };
+        // the boot ROM is mapped over the cartridge
+        if !self.boot
+           && self.boot_rom
+                  .as_ref()
+                  .and_then(|boot| boot.read(addr))
+                  .is_some()
+        {
+            return;
+        }
+        match addr {
+            0x0000..=0x3fff => cdl.mark(usize::from(addr), flags),
+            0x4000..=0x7fff => {
+                let bank = self.cartridge.rom_bank();
+                cdl.mark(bank * 0x4000 + usize::from(addr - 0x4000), flags)
+            }
+            _ => {}
+        }
+    }
+
+    // Called after the CPU is stepped, with the bank, SP & opcode at PC from
+    // before the step, to tell calls and returns apart.
+    fn profile_step(&mut self,
+                    cpu: &Cpu,
+                    pc: u16,
+                    (bank, sp, opcode): (usize, u16, u8),
+                    cycles: u64) {
+        let reg = cpu.reg();
+        let call = matches!(opcode, 0xc4 | 0xcc | 0xcd | 0xd4 | 0xdc) || opcode & 0xc7 == 0xc7;
+        let ret = matches!(opcode, 0xc0 | 0xc8 | 0xc9 | 0xd0 | 0xd8 | 0xd9);
+        // interrupts push the address of the instruction they interrupt
+        let interrupt = || {
+            let addr =
+                u16::from_le_bytes([self.bus_read(reg.sp), self.bus_read(reg.sp.wrapping_add(1))]);
+            matches!(reg.pc, 0x40 | 0x48 | 0x50 | 0x58 | 0x60) && addr == pc
+        };
+        let event = if reg.sp == sp.wrapping_sub(2) && (call || interrupt()) {
+            Event::Call((symbols::bank(self, reg.pc), reg.pc), reg.sp)
+        } else if reg.sp == sp.wrapping_add(2) && ret {
+            Event::Return(reg.sp)
+        } else {
+            Event::None
+        };
+        if let Some(profiler) = &mut self.profiler {
+            profiler.sample((bank, pc), cycles, event);
+        }
+    }
+
+    // Called by the CPU before it executes the instruction at PC.
+    pub(crate) fn trace_instruction(&mut self, cpu: &Cpu) {
+        if self.trace.is_none() {
+            return;
+        }
+        let pc = cpu.reg().pc;
+        let mut pcmem = [0; 4];
+        for (i, byte) in pcmem.iter_mut().enumerate() {
+            *byte = self.bus_read(pc.wrapping_add(i as u16));
+        }
+        let banks = (self.cartridge.rom_bank(), symbols::bank(self, 0xd000));
+        let ly = self.ppu.read(0xff44);
+        if let Some(trace) = &mut self.trace {
+            trace.log(cpu.reg(), pcmem, banks, ly);
+        }
+    }
+
+    // Execute one CPU instruction (or service an interrupt) and advance the rest
+    // of the components by the same amount of time. Returns the elapsed cycles of
+    // the 4MHz clock, and the breakpoint or watchpoint hit by the instruction.
+    pub(crate) fn step_cpu(&mut self, cpu: &mut Cpu) -> (u64, Option<Hit>) {
+        let pc = cpu.reg().pc;
+        let profile =
+            self.profiler
+                .as_ref()
+                .map(|_| (symbols::bank(self, pc), cpu.reg().sp, self.bus_read(pc)));
+        if let Some(debugger) = &mut self.debugger {
+            debugger.begin();
+        }
+        if let Some(cdl) = &mut self.cdl {
+            cdl.begin();
+        }
+        let mut cycles = cpu.step(self);
+        if let Some(cdl) = &mut self.cdl {
+            cdl.end();
+        }
+        let hit = match self.debugger.take() {
+            Some(mut debugger) => {
+                let hit = debugger.check(pc, cpu, self);
+                self.debugger = Some(debugger);
+                hit
+            }
+            None => None,
+        };
+
+        if self.speed == Speed::X2 {
+            cycles /= 2;
+        }
+
+        self.step(cycles);
+        if let Some(trace) = &mut self.trace {
+            trace.step(cycles);
+        }
+        if let Some(profile) = profile {
+            self.profile_step(cpu, pc, profile, cycles);
+        }
+        (cycles, hit)
     }
 
     // Advance the mapped components by the given amount of cycles of the internal
@@ -178,6 +477,9 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
         self.ppu.step(cycles);
         self.timer.step(cycles);
         self.apu.lock().step(cycles);
+        if let Some(vgm) = &mut self.vgm {
+            vgm.step(cycles);
+        }
 
         // request generated interrupts
         if let Some(flag) = self.ppu.take_vblank_int() {
@@ -210,7 +512,8 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
         for addr in 0..=0x9f {
             let src = src | (addr as u16);
             let dst = dst | (addr as u16);
-            self.write(dst, self.read(src));
+            self.log_cdl(src, cdl::DMA);
+            self.bus_write(dst, self.bus_read(src));
         }
     }
 
@@ -231,28 +534,73 @@ impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
         let src = src..src + len;
         let dst = dst..dst + len;
         for (src, dst) in src.zip(dst) {
-            let src = self.read(src);
-            self.write(dst, src);
+            self.log_cdl(src, cdl::DMA);
+            let src = self.bus_read(src);
+            self.bus_write(dst, src);
         }
     }
 }
 
-impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
-    fn read(&self, addr: u16) -> u8 {
-        #[cfg(feature = "dmg-data")]
-        use dmg_boot::{cgb, gb};
+// The model is checked by the savestate header.
+impl<C: Cartridge, V: Video, D: Audio> State for Mmu<C, V, D> {
+    fn save_state(&self, state: &mut Writer) {
+        self.mode.save_state(state);
+        state.bool(self.boot);
+        self.cartridge.save_state(state);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+        self.timer.save_state(state);
+        self.wram.save_state(state);
+        self.joy.save_state(state);
+        state.bytes(&self.hram[..]);
+        let dma = self.vram_dma;
+        state.bytes(&[dma.hdma1, dma.hdma2, dma.hdma3, dma.hdma4]);
+        self.int.save_state(state);
+        state.u8(self.speed as u8);
+    }
 
-        static BOOT_GB: &[u8] = include_bytes!("../data/boot.gb");
-        static BOOT_CGB: &[u8] = include_bytes!("../data/boot.gbc");
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.mode.load_state(state)?;
+        self.boot = state.bool()?;
+        if !self.boot && self.boot_rom.is_none() {
+            // the boot ROM was running, but this emulator doesn't have one
+            return Err(Error::InvalidData);
+        }
+        self.cartridge.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+        self.timer.load_state(state)?;
+        self.wram.load_state(state)?;
+        self.joy.load_state(state)?;
+        state.bytes(&mut self.hram[..])?;
+        let mut dma = [0; 4];
+        state.bytes(&mut dma)?;
+        let [hdma1, hdma2, hdma3, hdma4] = dma;
+        self.vram_dma = VRamDma { hdma1,
+                                  hdma2,
+                                  hdma3,
+                                  hdma4 };
+        self.int.load_state(state)?;
+        self.speed = match state.u8()? {
+            0x00 => Speed::X1,
+            0x80 => Speed::X2,
+            _ => return Err(Error::InvalidData),
+        };
+        Ok(())
+    }
+}
 
-        match addr {
-            // data roms
-            0x0000..=0x00ff if !self.boot && self.mode == Mode::GB => BOOT_GB[addr as usize],
-            0x0000..=0x00ff | 0x0150..=0x0900 if !self.boot && self.mode == Mode::CGB => {
-                BOOT_CGB[addr as usize]
+impl<C: Cartridge, V: Video, D: Audio> Mmu<C, V, D> {
+    // Memory access without notifying the debugger.
+    fn bus_read(&self, addr: u16) -> u8 {
+        if !self.boot {
+            if let Some(data) = self.boot_rom.as_ref().and_then(|boot| boot.read(addr)) {
+                return data;
             }
+        }
 
-            0x0000..=0x7fff => self.cartridge.read(addr),
+        match addr {
+            0x0000..=0x7fff => self.cheats.patch(addr, self.cartridge.read(addr)),
             0x8000..=0x9fff => self.ppu.read(addr),
             0xa000..=0xbfff => self.cartridge.read(addr),
             0xc000..=0xdfff => self.wram.read(addr),
@@ -287,7 +635,7 @@ impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
         }
     }
 
-    fn write(&mut self, addr: u16, data: u8) {
+    fn bus_write(&mut self, addr: u16, data: u8) {
         match addr {
             0x0000..=0x7fff => self.cartridge.write(addr, data),
             0x8000..=0x9fff => self.ppu.write(addr, data),
@@ -297,7 +645,15 @@ impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
             0xfe00..=0xfe9f => self.ppu.write(addr, data),
             0xfea0..=0xfeff => { /* Not Usable */ }
             0xff00..=0xff7f => match addr {
-                0xff00 => self.joy.write(addr, data),
+                0xff00 => {
+                    self.joy.write(addr, data);
+                    if let Some(packet) = self.joy.take_packet() {
+                        if let Some(sgb) = self.ppu.sgb_mut() {
+                            sgb.receive(&packet);
+                            self.joy.set_players(sgb.players());
+                        }
+                    }
+                }
                 0xff01 | 0xff02 => { /* serial data transfer (link cable) */ }
                 0xff04..=0xff07 => self.timer.write(addr, data),
                 0xff0f => self.int.write(addr, data),
@@ -306,7 +662,12 @@ impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
                 | 0xff1a..=0xff1e
                 | 0xff30..=0xff3f
                 | 0xff20..=0xff26
-                | 0xff27..=0xff2f => self.apu.write(addr, data),
+                | 0xff27..=0xff2f => {
+                    if let Some(vgm) = &mut self.vgm {
+                        vgm.log(addr, data);
+                    }
+                    self.apu.write(addr, data)
+                }
                 0xff40..=0xff45 | 0xff47..=0xff4b | 0xff4f | 0xff68..=0xff6b => {
                     self.ppu.write(addr, data)
                 }
@@ -322,6 +683,11 @@ impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
                 0xff54 => self.vram_dma.hdma4 = data,
                 0xff55 => self.vram_dma(data),
 
+                // KEY0 (CGB boot ROM only)
+                // Bit 2 - DMG compatibility mode
+                0xff4c if !self.boot && self.mode == Mode::CGB && data & 0x4 != 0 => {
+                    self.set_dmg_compat()
+                }
                 // KEY1
                 0xff4d => {
                     if data & 0x1 != 0 {
@@ -337,13 +703,40 @@ impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
     }
 }
 
+impl<C: Cartridge, V: Video, D: Audio> Device for Mmu<C, V, D> {
+    fn read(&self, addr: u16) -> u8 {
+        self.read_as(addr, cdl::DATA)
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let Some(debugger) = &self.debugger {
+            debugger.access(Access::Write, addr, data);
+        }
+        self.bus_write(addr, data);
+    }
+}
+
+/// Memory of the MMU, read without notifying the debugging tools (see
+/// [`Mmu::peeker`]).
+///
+/// [`Mmu::peeker`]: #
+pub struct Peek<'a, C: Cartridge, V: Video, D: Audio>(&'a Mmu<C, V, D>);
+
+impl<C: Cartridge, V: Video, D: Audio> Device for Peek<'_, C, V, D> {
+    fn read(&self, addr: u16) -> u8 {
+        self.0.peek(addr)
+    }
+
+    fn write(&mut self, _: u16, _: u8) {}
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{device::Device, mmu::Mmu, Mode};
+    use crate::{device::Device, mmu::Mmu, Model};
 
     #[test]
     fn oam_dma() {
-        let mut mmu = Mmu::<_, _, ()>::new(Mode::GB, (), ());
+        let mut mmu = Mmu::<_, _, ()>::new(Model::Dmg, (), ());
 
         mmu.write(0xff46, 0);
 
@@ -353,4 +746,24 @@ mod tests {
             assert_eq!(rom, oam);
         }
     }
+
+    #[test]
+    fn vgm_log() {
+        let mut mmu = Mmu::<_, _, ()>::new(Model::Dmg, (), ());
+
+        mmu.write(0xff26, 0x80);
+        mmu.start_vgm_log();
+        mmu.write(0xff12, 0xf0);
+        mmu.step(456);
+        mmu.write(0xff40, 0x91);
+        mmu.write(0xff30, 0x12);
+
+        let log = mmu.stop_vgm_log().unwrap();
+        let writes: Vec<_> = log.writes()
+                                .iter()
+                                .map(|w| (w.cycle, w.addr, w.data))
+                                .collect();
+        assert_eq!(vec![(0, 0xff12, 0xf0), (456, 0xff30, 0x12)], writes);
+        assert!(mmu.vgm_log().is_none());
+    }
 }
@@ -0,0 +1,407 @@
+//! Input movies.
+//!
+//! A movie is a recording of the state of the joypads on every frame. Along
+//! with the input, it stores the hash of the ROM, the options the emulator was
+//! built with and, optionally, a savestate to start from, so a run can be
+//! played back exactly as it was recorded (tool-assisted runs, bug reports...).
+//!
+//! The hash of the state of the emulator is also recorded every
+//! [`HASH_INTERVAL`] frames. During playback, the hashes are compared to
+//! detect desyncs.
+//!
+//! [`HASH_INTERVAL`]: #
+use crate::{
+    apu::device::Audio,
+    cartridge::Cartridge,
+    joypad::{Btn, Dir, Joypad, MAX_PLAYERS},
+    ppu::Video,
+    state::{self, Reader, Writer},
+    Builder, Model,
+};
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"DMGM";
+const VERSION: u8 = 1;
+
+/// Number of frames between two state hashes.
+pub const HASH_INTERVAL: u64 = 60;
+
+/// Errors when loading or playing movies.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// The data isn't a valid movie file.
+    InvalidData,
+    /// The movie was written by an incompatible version of the emulator.
+    Version(u8),
+    /// The movie was recorded on a different ROM.
+    Rom,
+    /// The emulator was built with different options than the movie.
+    Boot,
+    /// The start savestate couldn't be loaded.
+    State(state::Error),
+    /// The state of the emulator doesn't match the recording at the given
+    /// frame.
+    Desync(u64),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidData => write!(f, "Invalid movie data"),
+            Error::Version(v) => write!(f, "Unsupported movie version ({})", v),
+            Error::Rom => write!(f, "The movie was recorded on a different ROM"),
+            Error::Boot => write!(f, "The movie was recorded with different boot options"),
+            Error::State(err) => write!(f, "Error loading the start savestate: {}", err),
+            Error::Desync(frame) => write!(f, "Movie desync at frame {}", frame),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<state::Error> for Error {
+    fn from(err: state::Error) -> Self {
+        match err {
+            state::Error::UnexpectedEof | state::Error::InvalidData => Error::InvalidData,
+            err => Error::State(err),
+        }
+    }
+}
+
+/// Options the emulator was built with (see [`Builder`]).
+///
+/// [`Builder`]: #
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Boot {
+    /// Emulated hardware model.
+    pub model: Model,
+    /// A boot ROM was run. It must be given to the [`Builder`] again to play
+    /// the movie back.
+    ///
+    /// [`Builder`]: #
+    pub boot_rom: bool,
+    /// Keys selecting the CGB compatibility palette (see
+    /// [`Builder::compat_palette`]).
+    ///
+    /// [`Builder::compat_palette`]: #
+    pub compat_keys: Option<(Dir, Option<Btn>)>,
+}
+
+impl Boot {
+    /// Set up a builder with these options. If [`Boot::boot_rom`] is set,
+    /// the boot ROM must be given separately.
+    ///
+    /// [`Boot::boot_rom`]: #
+    pub fn configure<C: Cartridge, V: Video, D: Audio>(&self,
+                                                       builder: Builder<C, V, D>)
+                                                       -> Builder<C, V, D> {
+        let mut builder = builder.model(self.model);
+        if !self.boot_rom {
+            builder = builder.skip_boot();
+        }
+        if let Some((dir, btn)) = self.compat_keys {
+            builder = builder.compat_palette(dir, btn);
+        }
+        builder
+    }
+}
+
+/// Recorded input.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Movie {
+    rom_hash: u64,
+    boot: Boot,
+    start: Option<Vec<u8>>,
+    // Keys of each joypad (see Joypad::keys), one entry per frame.
+    input: Vec<[u8; MAX_PLAYERS]>,
+    // Hash of the state at the start of the movie, then every HASH_INTERVAL
+    // frames.
+    hashes: Vec<u64>,
+}
+
+impl Movie {
+    pub(crate) fn new(rom: &[u8], boot: Boot, start: Option<Vec<u8>>, hash: u64) -> Self {
+        Self { rom_hash: state::hash(rom),
+               boot,
+               start,
+               input: Vec::new(),
+               hashes: vec![hash] }
+    }
+
+    /// Hash of the ROM the movie was recorded on.
+    pub fn rom_hash(&self) -> u64 {
+        self.rom_hash
+    }
+
+    /// Options the emulator must be built with to play the movie.
+    pub fn boot(&self) -> &Boot {
+        &self.boot
+    }
+
+    /// Savestate the movie starts from. If there isn't one, the movie starts
+    /// from power on.
+    pub fn start_state(&self) -> Option<&[u8]> {
+        self.start.as_deref()
+    }
+
+    /// Number of recorded frames.
+    pub fn len(&self) -> usize {
+        self.input.len()
+    }
+
+    /// Returns true if no frames have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    /// Keys pressed on each joypad during the given frame (see
+    /// [`Joypad::keys`]).
+    ///
+    /// [`Joypad::keys`]: #
+    pub fn input(&self, frame: usize) -> Option<[u8; MAX_PLAYERS]> {
+        self.input.get(frame).copied()
+    }
+
+    /// Check that the movie was recorded on the given ROM.
+    pub fn check_rom(&self, rom: &[u8]) -> Result<(), Error> {
+        if state::hash(rom) == self.rom_hash {
+            Ok(())
+        } else {
+            Err(Error::Rom)
+        }
+    }
+
+    /// Serialize the movie.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Writer::default();
+        data.bytes(MAGIC);
+        data.u8(VERSION);
+        data.u64(self.rom_hash);
+        data.u8(self.boot.model as u8);
+        data.bool(self.boot.boot_rom);
+        match self.boot.compat_keys {
+            Some((dir, btn)) => data.u8(dir as u8 | btn.map(|btn| btn as u8).unwrap_or(0) << 4),
+            None => data.u8(0),
+        }
+        data.u64(self.input.len() as u64);
+        for keys in &self.input {
+            data.bytes(keys);
+        }
+        data.u64(self.hashes.len() as u64);
+        for hash in &self.hashes {
+            data.u64(*hash);
+        }
+        match &self.start {
+            Some(start) => {
+                data.u64(start.len() as u64);
+                data.bytes(start);
+            }
+            None => data.u64(0),
+        }
+        data.into_bytes()
+    }
+
+    /// Load a movie serialized with [`Movie::to_bytes`].
+    ///
+    /// [`Movie::to_bytes`]: #
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < MAGIC.len() || &bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidData);
+        }
+        let mut data = Reader::from_bytes(&bytes[MAGIC.len()..]);
+        match data.u8()? {
+            VERSION => {}
+            version => return Err(Error::Version(version)),
+        }
+        let rom_hash = data.u64()?;
+        let model = match data.u8()? {
+            0 => Model::Dmg0,
+            1 => Model::Dmg,
+            2 => Model::Mgb,
+            3 => Model::Sgb,
+            4 => Model::Sgb2,
+            5 => Model::Cgb,
+            6 => Model::Agb,
+            _ => return Err(Error::InvalidData),
+        };
+        let boot_rom = data.bool()?;
+        let compat_keys = match data.u8()? {
+            0 => None,
+            keys => {
+                let dir = match keys & 0xf {
+                    0x8 => Dir::Down,
+                    0x4 => Dir::Up,
+                    0x2 => Dir::Left,
+                    0x1 => Dir::Right,
+                    _ => return Err(Error::InvalidData),
+                };
+                let btn = match keys >> 4 {
+                    0x0 => None,
+                    0x8 => Some(Btn::Start),
+                    0x4 => Some(Btn::Select),
+                    0x2 => Some(Btn::A),
+                    0x1 => Some(Btn::B),
+                    _ => return Err(Error::InvalidData),
+                };
+                Some((dir, btn))
+            }
+        };
+        let boot = Boot { model,
+                          boot_rom,
+                          compat_keys };
+
+        let mut input = Vec::new();
+        for _ in 0..data.u64()? {
+            let mut keys = [0; MAX_PLAYERS];
+            data.bytes(&mut keys)?;
+            input.push(keys);
+        }
+        let mut hashes = Vec::new();
+        for _ in 0..data.u64()? {
+            hashes.push(data.u64()?);
+        }
+        let start = match data.u64()? as usize {
+            0 => None,
+            len if len > bytes.len() => return Err(Error::InvalidData),
+            len => {
+                let mut start = vec![0; len];
+                data.bytes(&mut start)?;
+                Some(start)
+            }
+        };
+        if hashes.is_empty() || !data.is_empty() {
+            return Err(Error::InvalidData);
+        }
+        Ok(Self { rom_hash,
+                  boot,
+                  start,
+                  input,
+                  hashes })
+    }
+}
+
+/// State of the movie being recorded or played.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Status {
+    /// The input is being recorded. Holds the number of recorded frames.
+    Recording(u64),
+    /// The movie is being played. Holds the number of frames played so far.
+    Playing(u64),
+    /// All the frames of the movie have been played, and the joypads have
+    /// been released.
+    Finished,
+    /// The state of the emulator diverged from the recording at the given
+    /// frame. Playback goes on anyway.
+    Desync(u64),
+}
+
+// Movie attached to the emulator.
+pub(crate) struct Session {
+    movie: Movie,
+    recording: bool,
+    frame: u64,
+    desync: Option<u64>,
+}
+
+impl Session {
+    pub(crate) fn record(movie: Movie) -> Self {
+        Self { movie,
+               recording: true,
+               frame: 0,
+               desync: None }
+    }
+
+    pub(crate) fn play(movie: Movie) -> Self {
+        Self { movie,
+               recording: false,
+               frame: 0,
+               desync: None }
+    }
+
+    pub(crate) fn into_movie(self) -> Movie {
+        self.movie
+    }
+
+    pub(crate) fn status(&self) -> Status {
+        match (self.recording, self.desync) {
+            (true, _) => Status::Recording(self.frame),
+            (false, Some(frame)) => Status::Desync(frame),
+            (false, None) if self.frame >= self.movie.len() as u64 => Status::Finished,
+            (false, None) => Status::Playing(self.frame),
+        }
+    }
+
+    // Called before a frame is emulated. Records the joypads, or sets them to the
+    // recorded input.
+    pub(crate) fn begin_frame(&mut self, joypad: &mut Joypad) {
+        if self.recording {
+            let mut keys = [0; MAX_PLAYERS];
+            for (player, keys) in keys.iter_mut().enumerate() {
+                *keys = joypad.keys(player);
+            }
+            self.movie.input.push(keys);
+        } else {
+            let keys = self.movie
+                           .input(self.frame as usize)
+                           .unwrap_or([0; MAX_PLAYERS]);
+            for (player, keys) in keys.iter().enumerate() {
+                joypad.set_keys(player, *keys);
+            }
+        }
+    }
+
+    // Called after a frame is emulated, with a function that hashes the state of
+    // the emulator.
+    pub(crate) fn end_frame(&mut self, hash: impl FnOnce() -> u64) {
+        if !self.recording && self.frame >= self.movie.len() as u64 {
+            return;
+        }
+        self.frame += 1;
+        let index = self.frame / HASH_INTERVAL;
+        if index * HASH_INTERVAL != self.frame {
+            return;
+        }
+        if self.recording {
+            self.movie.hashes.push(hash());
+        } else if let Some(expected) = self.movie.hashes.get(index as usize) {
+            if self.desync.is_none() && hash() != *expected {
+                self.desync = Some(self.frame);
+            }
+        }
+    }
+
+    // Check the hash of the state the movie starts from.
+    pub(crate) fn check_start(&self, hash: u64) -> Result<(), Error> {
+        if self.movie.hashes[0] == hash {
+            Ok(())
+        } else {
+            Err(Error::Desync(0))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        joypad::{Btn, Dir},
+        movie::{Boot, Error, Movie},
+        Model,
+    };
+
+    #[test]
+    fn bytes() {
+        let boot = Boot { model: Model::Cgb,
+                          boot_rom: false,
+                          compat_keys: Some((Dir::Left, Some(Btn::B))) };
+        let mut movie = Movie::new(&[1, 2, 3], boot, Some(vec![4, 5]), 42);
+        movie.input.push([0x01, 0x00, 0x00, 0x80]);
+        movie.input.push([0x00; 4]);
+
+        let bytes = movie.to_bytes();
+        assert_eq!(Ok(movie.clone()), Movie::from_bytes(&bytes));
+        assert_eq!(Err(Error::InvalidData),
+                   Movie::from_bytes(&bytes[..bytes.len() - 1]));
+        assert_eq!(Ok(()), movie.check_rom(&[1, 2, 3]));
+        assert_eq!(Err(Error::Rom), movie.check_rom(&[1, 2]));
+    }
+}
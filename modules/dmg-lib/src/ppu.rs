@@ -2,6 +2,7 @@ use crate::{
     device::Device,
     interrupt::Flag,
     ppu::{
+        compat::CompatPalette,
         oam::{Entry, Oam},
         palette::Color,
         reg::{
@@ -9,12 +10,15 @@ use crate::{
             STAT_SEARCH_FLAG, STAT_VBLANK_FLAG,
         },
     },
+    sgb::{Sgb, SGB_HEIGHT, SGB_WIDTH, TRANSFER_SIZE},
+    state::{Error, Reader, State, Writer},
     vram::VRam,
     Mode,
 };
 use reg::{ColorPal, Line, Pal, Scroll, Window};
 use std::mem;
 
+pub mod compat;
 pub mod oam;
 pub mod palette;
 pub mod reg;
@@ -30,6 +34,13 @@ pub(crate) const VBLANK: u64 = 4560; // 4560 dots (1087 us, 10 scanlines)
 /// Display scanline renderer.
 pub trait Video {
     fn draw_video(&mut self, pixels: &[[Color; LCD_WIDTH]; LCD_HEIGHT]);
+
+    /// Draw the Super Game Boy frame (the colorized screen surrounded by the
+    /// border). Only called when emulating the SGB, after
+    /// [`Video::draw_video`].
+    ///
+    /// [`Video::draw_video`]: #
+    fn draw_sgb_video(&mut self, _pixels: &[[Color; SGB_WIDTH]; SGB_HEIGHT]) {}
 }
 
 impl Video for () {
@@ -39,12 +50,18 @@ impl Video for () {
 pub struct Ppu<V: Video> {
     video: V,
     mode: Mode,
+    // Set when a DMG game runs on the CGB. The GB mode palette registers index
+    // the colors of CGB palettes (BG palette 0 and OB palettes 0 & 1).
+    dmg_compat: bool,
     // Same as cycles, but documentation often refers to it as "dots" instead of cycles.
     dots: u64,
     buffer: Box<[[Color; LCD_WIDTH]; LCD_HEIGHT]>,
     // Color index within color palette.
     // Used to tell when a sprite is behind a BG tile.
     color_index: Box<[[u8; LCD_WIDTH]; LCD_HEIGHT]>,
+    // Shade (0-3) of each pixel in GB mode, used to colorize the SGB screen.
+    shades: Box<[[u8; LCD_WIDTH]; LCD_HEIGHT]>,
+    sgb: Option<Box<Sgb>>,
     stat_mode: StatMode,
     vram: VRam,
     oam: Oam,
@@ -63,8 +80,11 @@ impl<V: Video> Ppu<V> {
         Self { dots: 0,
                video: output,
                mode,
+               dmg_compat: false,
                buffer: Box::new([[[0xff, 0xff, 0xff]; LCD_WIDTH]; LCD_HEIGHT]),
                color_index: Box::new([[0; LCD_WIDTH]; LCD_HEIGHT]),
+               shades: Box::new([[0; LCD_WIDTH]; LCD_HEIGHT]),
+               sgb: None,
                vram: VRam::default(),
                oam: Oam::default(),
                stat_mode: StatMode::HBlank,
@@ -78,6 +98,37 @@ impl<V: Video> Ppu<V> {
                lcdc_int: None }
     }
 
+    // Switch a CGB to DMG compatibility mode.
+    pub(crate) fn set_dmg_compat(&mut self) {
+        self.mode = Mode::GB;
+        self.dmg_compat = true;
+    }
+
+    pub(crate) fn enable_sgb(&mut self) {
+        self.sgb = Some(Box::default());
+    }
+
+    /// Return the Super Game Boy state (SGB models only).
+    pub fn sgb(&self) -> Option<&Sgb> {
+        self.sgb.as_deref()
+    }
+
+    pub(crate) fn sgb_mut(&mut self) -> Option<&mut Sgb> {
+        self.sgb.as_deref_mut()
+    }
+
+    /// Returns true if a DMG game is being colorized by the CGB.
+    pub fn is_dmg_compat(&self) -> bool {
+        self.dmg_compat
+    }
+
+    // Load the colors picked by the boot ROM in DMG compatibility mode.
+    pub(crate) fn set_compat_palette(&mut self, palette: &CompatPalette) {
+        self.color_pal.set_bg_pal(0, palette.bg);
+        self.color_pal.set_ob_pal(0, palette.obj0);
+        self.color_pal.set_ob_pal(1, palette.obj1);
+    }
+
     pub fn lcdc_stat(&self) -> &LcdcStat {
         &self.lcdc_stat
     }
@@ -185,7 +236,7 @@ impl<V: Video> Ppu<V> {
                 if self.lcdc_stat.stat & STAT_VBLANK_FLAG != 0 {
                     self.request_lcdc();
                 }
-                self.video.draw_video(&self.buffer);
+                self.draw_frame();
                 line = 144;
             }
             (StatMode::HBlank, StatMode::Search) => {
@@ -240,6 +291,37 @@ impl<V: Video> Ppu<V> {
         self.lcdc_stat.stat_set_mode(self.stat_mode);
     }
 
+    fn draw_frame(&mut self) {
+        if let Some(sgb) = &mut self.sgb {
+            if sgb.transfer_pending() {
+                let data = Self::transfer_data(&self.vram, &self.lcdc_stat);
+                sgb.transfer(&data);
+            }
+            sgb.render(&self.shades, &mut self.buffer);
+            self.video.draw_video(&self.buffer);
+            self.video.draw_sgb_video(sgb.frame());
+        } else {
+            self.video.draw_video(&self.buffer);
+        }
+    }
+
+    // SGB VRAM transfers read the data from the screen: the first 256 tiles of the
+    // BG map (20 per row) are sent in order, 16 bytes each.
+    fn transfer_data(vram: &VRam, lcdc_stat: &LcdcStat) -> Box<[u8; TRANSFER_SIZE]> {
+        let mut data = Box::new([0; TRANSFER_SIZE]);
+        let vram = vram.bank(0);
+        let map = lcdc_stat.bg_tile_map() as usize - 0x8000;
+        for (i, tile_data) in data.chunks_exact_mut(16).enumerate() {
+            let tile = vram[map + 32 * (i / 20) + i % 20];
+            let offset = match lcdc_stat.bg_win_tile_data() {
+                TileDataAddr::X8000 => 16 * tile as usize,
+                TileDataAddr::X8800 => 0x800 + 16 * (tile as i8 as isize + 128) as usize,
+            };
+            tile_data.copy_from_slice(&vram[offset..offset + 16]);
+        }
+        data
+    }
+
     fn request_vblank(&mut self) {
         self.vblank_int = Some(Flag::VBlank);
     }
@@ -260,6 +342,7 @@ impl<V: Video> Ppu<V> {
 
     fn clear_video(&mut self) {
         let color = match self.mode {
+            Mode::GB if self.dmg_compat => self.color_pal.bg_pal_color(0, self.pal.bg_shade(0)),
             Mode::GB => self.pal.clear_color(),
             Mode::CGB => self.color_pal.clear_color(),
         };
@@ -327,6 +410,10 @@ impl<V: Video> Ppu<V> {
         let mut color_index = lo | (hi << 1);
         // return pixel color
         match self.mode {
+            Mode::GB if self.dmg_compat => {
+                let shade = self.pal.bg_shade(color_index as usize);
+                (self.color_pal.bg_pal_color(0, shade), color_index)
+            }
             Mode::GB => (self.pal.bg_color(color_index as usize), color_index),
             Mode::CGB => {
                 let palette = (flags & 0x7) as usize;
@@ -355,6 +442,7 @@ impl<V: Video> Ppu<V> {
             let (color, index) = self.point_color(y, x, map, data);
             self.buffer[ly as usize][lcd_x] = color;
             self.color_index[ly as usize][lcd_x] = index;
+            self.shades[ly as usize][lcd_x] = self.pal.bg_shade(index as usize) as u8;
         }
     }
 
@@ -382,6 +470,7 @@ impl<V: Video> Ppu<V> {
             let (color, index) = self.point_color(y, x, map, data);
             self.buffer[ly as usize][lcd_x as usize] = color;
             self.color_index[ly as usize][lcd_x as usize] = index;
+            self.shades[ly as usize][lcd_x as usize] = self.pal.bg_shade(index as usize) as u8;
         }
     }
 
@@ -452,8 +541,15 @@ impl<V: Video> Ppu<V> {
 
                 // draw pixel color
                 self.buffer[ly as usize][lcd_x as usize] = match self.mode {
+                    Mode::GB if self.dmg_compat => {
+                        let pal = (flags >> 4 & 0x1) as usize;
+                        let shade = self.pal.obp_shade(pal, color_index as usize);
+                        self.color_pal.ob_pal_color(pal, shade)
+                    }
                     Mode::GB => {
                         let pal = (flags >> 4 & 0x1) as usize;
+                        let shade = self.pal.obp_shade(pal, color_index as usize);
+                        self.shades[ly as usize][lcd_x as usize] = shade as u8;
                         self.pal.obp_color(pal, color_index as usize)
                     }
                     Mode::CGB => {
@@ -573,13 +669,88 @@ impl<V: Video> Device for Ppu<V> {
     }
 }
 
+impl<V: Video> State for Ppu<V> {
+    fn save_state(&self, state: &mut Writer) {
+        self.mode.save_state(state);
+        state.bool(self.dmg_compat);
+        state.u64(self.dots);
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(state);
+        }
+        state.u8(self.stat_mode as u8);
+        self.vram.save_state(state);
+        self.oam.save_state(state);
+        state.bytes(&[self.lcdc_stat.lcdc,
+                      self.lcdc_stat.stat,
+                      self.scroll.scy,
+                      self.scroll.scx,
+                      self.line.ly,
+                      self.line.lyc,
+                      self.win.wy,
+                      self.win.wx,
+                      self.pal.bgp,
+                      self.pal.obp0,
+                      self.pal.obp1,
+                      self.color_pal.bgpi,
+                      self.color_pal.obpi]);
+        state.bytes(&self.color_pal.bgp);
+        state.bytes(&self.color_pal.obp);
+        state.bool(self.vblank_int.is_some());
+        state.bool(self.lcdc_int.is_some());
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.mode.load_state(state)?;
+        self.dmg_compat = state.bool()?;
+        self.dots = state.u64()?;
+        if let Some(sgb) = &mut self.sgb {
+            sgb.load_state(state)?;
+        }
+        self.stat_mode = match state.u8()? {
+            0x00 => StatMode::HBlank,
+            0x01 => StatMode::VBlank,
+            0x02 => StatMode::Search,
+            0x03 => StatMode::Pixels,
+            _ => return Err(Error::InvalidData),
+        };
+        self.vram.load_state(state)?;
+        self.oam.load_state(state)?;
+        let mut data = [0; 13];
+        state.bytes(&mut data)?;
+        let [lcdc, stat, scy, scx, ly, lyc, wy, wx, bgp, obp0, obp1, bgpi, obpi] = data;
+        self.lcdc_stat.lcdc = lcdc;
+        self.lcdc_stat.stat = stat;
+        self.scroll = Scroll { scy, scx };
+        self.line = Line { ly, lyc };
+        self.win = Window { wy, wx };
+        self.pal.bgp = bgp;
+        self.pal.obp0 = obp0;
+        self.pal.obp1 = obp1;
+        self.color_pal.bgpi = bgpi;
+        self.color_pal.obpi = obpi;
+        state.bytes(&mut self.color_pal.bgp)?;
+        state.bytes(&mut self.color_pal.obp)?;
+        self.vblank_int = if state.bool()? {
+            Some(Flag::VBlank)
+        } else {
+            None
+        };
+        self.lcdc_int = if state.bool()? {
+            Some(Flag::LCDCStat)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{device::Device, mmu::Mmu, Mode};
+    use crate::{device::Device, mmu::Mmu, Model};
 
     #[test]
     fn vram() {
-        let mut mmu = Mmu::<_, _, ()>::new(Mode::GB, (), ());
+        let mut mmu = Mmu::<_, _, ()>::new(Model::Dmg, (), ());
 
         mmu.write(0x8000, 1);
         mmu.write(0x9fff, 2);
@@ -590,7 +761,7 @@ mod tests {
 
     #[test]
     fn oam() {
-        let mut mmu = Mmu::<_, _, ()>::new(Mode::GB, (), ());
+        let mut mmu = Mmu::<_, _, ()>::new(Model::Dmg, (), ());
 
         mmu.write(0xfe00, 1);
         mmu.write(0xfe9f, 2);
@@ -601,7 +772,7 @@ mod tests {
 
     #[test]
     fn registers() {
-        let mut mmu = Mmu::<_, _, ()>::new(Mode::GB, (), ());
+        let mut mmu = Mmu::<_, _, ()>::new(Model::Dmg, (), ());
 
         mmu.write(0xff42, 1);
         mmu.write(0xff43, 2);
@@ -0,0 +1,330 @@
+//! Palettes used by the CGB to colorize DMG games.
+//!
+//! When a game without CGB support runs on the CGB, the boot ROM picks a
+//! palette for the BG and the two OBJ palettes from a table of games licensed
+//! by Nintendo, keyed by the checksum of the title. If more than one game has
+//! the same checksum, the 4th letter of the title is also checked. Holding a
+//! direction (plus A or B) during the boot animation selects one of 12
+//! alternate palettes instead.
+use crate::{
+    joypad::{Btn, Dir},
+    ppu::palette::Color,
+};
+
+/// Colors of the BG and OBJ palettes of a DMG game running on the CGB.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CompatPalette {
+    pub bg: [Color; 4],
+    pub obj0: [Color; 4],
+    pub obj1: [Color; 4],
+}
+
+impl CompatPalette {
+    /// Palette picked by the boot ROM for the given title checksum (sum of the
+    /// bytes of the title) and 4th letter of the title. Games that aren't in
+    /// the table get the default (dark green) palette.
+    pub fn from_title(checksum: u8, fourth: u8) -> Self {
+        TITLES.iter()
+              .find(|(c, f, _)| *c == checksum && f.map(|f| f == fourth).unwrap_or(true))
+              .map(|(_, _, comb)| Self::combination(*comb))
+              .unwrap_or_else(|| Self::combination(0))
+    }
+
+    /// Palette selected by holding the given direction (and optionally A or
+    /// B) while the boot ROM is running. Other buttons are ignored.
+    pub fn from_keys(dir: Dir, btn: Option<Btn>) -> Self {
+        let combination = match (dir, btn) {
+            (Dir::Up, Some(Btn::A)) => 43,
+            (Dir::Up, Some(Btn::B)) => 28,
+            (Dir::Up, _) => 5,
+            (Dir::Left, Some(Btn::A)) => 40,
+            (Dir::Left, Some(Btn::B)) => 7,
+            (Dir::Left, _) => 48,
+            (Dir::Down, Some(Btn::A)) => 3,
+            (Dir::Down, Some(Btn::B)) => 49,
+            (Dir::Down, _) => 8,
+            (Dir::Right, Some(Btn::A)) => 0,
+            (Dir::Right, Some(Btn::B)) => 6,
+            (Dir::Right, _) => 1,
+        };
+        Self::combination(combination)
+    }
+
+    fn combination(index: u8) -> Self {
+        let [obj0, obj1, bg] = COMBINATIONS[usize::from(index)];
+        Self { bg: colors(bg),
+               obj0: colors(obj0),
+               obj1: colors(obj1) }
+    }
+}
+
+// 4 colors of the table, starting at the given one.
+fn colors(offset: u8) -> [Color; 4] {
+    let mut colors = [[0; 3]; 4];
+    for (color, rgb) in colors.iter_mut().zip(&COLORS[usize::from(offset)..]) {
+        // same conversion as the color palette registers
+        *color = [(0xff * (rgb & 0x1f) / 0x1f) as u8,
+                  (0xff * ((rgb >> 5) & 0x1f) / 0x1f) as u8,
+                  (0xff * ((rgb >> 10) & 0x1f) / 0x1f) as u8];
+    }
+    colors
+}
+
+// Colors (RGB555) the palettes are made of, in groups of 4.
+#[rustfmt::skip]
+const COLORS: [u16; 120] = [
+    0x7fff, 0x32bf, 0x00d0, 0x0000, // 0
+    0x639f, 0x4279, 0x15b0, 0x04cb, // 1
+    0x7fff, 0x6e31, 0x454a, 0x0000, // 2
+    0x7fff, 0x1bef, 0x0200, 0x0000, // 3
+    0x7fff, 0x421f, 0x1cf2, 0x0000, // 4
+    0x7fff, 0x5294, 0x294a, 0x0000, // 5
+    0x7fff, 0x03ff, 0x012f, 0x0000, // 6
+    0x7fff, 0x03ef, 0x01d6, 0x0000, // 7
+    0x7fff, 0x42b5, 0x3dc8, 0x0000, // 8
+    0x7e74, 0x03ff, 0x0180, 0x0000, // 9
+    0x67ff, 0x77ac, 0x1a13, 0x2d6b, // 10
+    0x7ed6, 0x4bff, 0x2175, 0x0000, // 11
+    0x53ff, 0x4a5f, 0x7e52, 0x0000, // 12
+    0x4fff, 0x7ed2, 0x3a4c, 0x1ce0, // 13
+    0x03ed, 0x7fff, 0x255f, 0x0000, // 14
+    0x036a, 0x021f, 0x03ff, 0x7fff, // 15
+    0x7fff, 0x01df, 0x0112, 0x0000, // 16
+    0x231f, 0x035f, 0x00f2, 0x0009, // 17
+    0x7fff, 0x03ea, 0x011f, 0x0000, // 18
+    0x299f, 0x001a, 0x000c, 0x0000, // 19
+    0x7fff, 0x027f, 0x001f, 0x0000, // 20
+    0x7fff, 0x03e0, 0x0206, 0x0120, // 21
+    0x7fff, 0x7eeb, 0x001f, 0x7c00, // 22
+    0x7fff, 0x3fff, 0x7e00, 0x001f, // 23
+    0x7fff, 0x03ff, 0x001f, 0x0000, // 24
+    0x03ff, 0x001f, 0x000c, 0x0000, // 25
+    0x7fff, 0x033f, 0x0193, 0x0000, // 26
+    0x0000, 0x4200, 0x037f, 0x7fff, // 27
+    0x7fff, 0x7e8c, 0x7c00, 0x0000, // 28
+    0x7fff, 0x1bef, 0x6180, 0x0000, // 29
+];
+
+// Palette combinations: offsets into COLORS of the OBJ0, OBJ1 and BG palettes.
+// They are multiples of 4, except for a few combinations that mix the colors of
+// two groups.
+#[rustfmt::skip]
+const COMBINATIONS: [[u8; 3]; 51] = [
+    [16, 16, 116],  // 0, right + A, and titles that aren't in the table
+    [72, 72, 72],   // 1, right
+    [80, 80, 80],   // 2
+    [96, 96, 96],   // 3, down + A
+    [36, 36, 36],   // 4
+    [0, 0, 0],      // 5, up
+    [108, 108, 108],// 6, right + B
+    [20, 20, 20],   // 7, left + B
+    [48, 48, 48],   // 8, down
+    [104, 104, 104],// 9
+    [64, 32, 32],   // 10
+    [16, 112, 112], // 11
+    [16, 8, 8],     // 12
+    [12, 16, 16],   // 13
+    [16, 116, 116], // 14
+    [112, 16, 112], // 15
+    [8, 68, 8],     // 16
+    [64, 64, 32],   // 17
+    [16, 16, 28],   // 18
+    [16, 16, 72],   // 19
+    [16, 16, 80],   // 20
+    [76, 76, 36],   // 21
+    [15, 15, 44],   // 22, mixes two groups
+    [68, 68, 8],    // 23
+    [16, 16, 8],    // 24
+    [16, 16, 12],   // 25
+    [112, 112, 0],  // 26
+    [12, 12, 0],    // 27
+    [0, 0, 4],      // 28, up + B
+    [72, 88, 72],   // 29
+    [80, 88, 80],   // 30
+    [96, 88, 96],   // 31
+    [64, 88, 32],   // 32
+    [68, 16, 52],   // 33
+    [111, 0, 56],   // 34, mixes two groups
+    [111, 16, 60],  // 35, mixes two groups
+    [76, 88, 36],   // 36
+    [64, 112, 40],  // 37
+    [16, 92, 112],  // 38
+    [68, 88, 8],    // 39
+    [16, 0, 8],     // 40, left + A
+    [16, 112, 12],  // 41
+    [112, 12, 0],   // 42
+    [12, 112, 16],  // 43, up + A
+    [84, 112, 16],  // 44
+    [12, 112, 0],   // 45
+    [100, 12, 112], // 46
+    [0, 112, 32],   // 47
+    [16, 12, 112],  // 48, left
+    [112, 12, 24],  // 49, down + B
+    [16, 112, 116], // 50
+];
+
+// (title checksum, 4th letter, palette combination)
+//
+// The 4th letter is only given for checksums shared by more than one title. A
+// title that matches the checksum but none of the letters gets the default
+// combination. The titles left blank are unknown.
+#[rustfmt::skip]
+const TITLES: &[(u8, Option<u8>, u8)] = &[
+    (0x88, None, 4),          // ALLEY WAY
+    (0x16, None, 5),          // YAKUMAN
+    (0x36, None, 35),         // BASEBALL, GAME&WATCH 2
+    (0xd1, None, 34),         // TENNIS
+    (0xdb, None, 3),          // TETRIS
+    (0xf2, None, 31),         // QIX
+    (0x3c, None, 15),         // DR.MARIO
+    (0x8c, None, 10),         // RADARMISSION
+    (0x92, None, 5),          // F1RACE
+    (0x3d, None, 19),         // YOSSY NO TAMAGO
+    (0x5c, None, 36),
+    (0x58, None, 7),          // X
+    (0xc9, None, 37),         // MARIOLAND2
+    (0x3e, None, 30),         // YOSSY NO COOKIE
+    (0x70, None, 44),         // ZELDA
+    (0x1d, None, 21),
+    (0x59, None, 32),
+    (0x69, None, 31),         // TETRIS FLASH
+    (0x19, None, 20),         // DONKEY KONG
+    (0x35, None, 5),          // MARIO'S PICROSS
+    (0xa8, None, 33),
+    (0x14, None, 13),         // POKEMON RED, GAMEBOYCAMERA G
+    (0xaa, None, 14),         // POKEMON GREEN
+    (0x75, None, 5),          // PICROSS 2
+    (0x95, None, 29),         // YOSSY NO PANEPON
+    (0x99, None, 5),          // KIRAKIRA KIDS
+    (0x34, None, 18),         // GAMEBOY GALLERY
+    (0x6f, None, 9),          // POCKETCAMERA
+    (0x15, None, 3),
+    (0xff, None, 2),          // BALLOON KID
+    (0x97, None, 26),         // KINGOFTHEZOO
+    (0x4b, None, 25),         // DMG FOOTBALL
+    (0x90, None, 25),         // WORLD CUP
+    (0x17, None, 41),         // OTHELLO
+    (0x10, None, 42),         // SUPER RC PRO-AM
+    (0x39, None, 26),         // DYNABLASTER
+    (0xf7, None, 45),         // BOY AND BLOB GB2
+    (0xf6, None, 42),         // MEGAMAN
+    (0xa2, None, 45),         // STAR WARS-NOA
+    (0x49, None, 36),
+    (0x4e, None, 38),         // WAVERACE
+    (0x43, None, 26),
+    (0x68, None, 42),         // LOLO2
+    (0xe0, None, 30),         // YOSHI'S COOKIE
+    (0x8b, None, 41),         // MYSTIC QUEST
+    (0xf0, None, 34),
+    (0xce, None, 34),         // TOPRANKINGTENNIS
+    (0x0c, None, 5),          // MANSELL
+    (0x29, None, 42),         // MEGAMAN3
+    (0xe8, None, 6),          // SPACE INVADERS
+    (0xb7, None, 5),          // GAME&WATCH
+    (0x86, None, 33),         // DONKEYKONGLAND95
+    (0x9a, None, 25),         // ASTEROIDS/MISCMD
+    (0x52, None, 42),         // STREET FIGHTER 2
+    (0x01, None, 42),         // DEFENDER/JOUST
+    (0x9d, None, 40),         // KILLERINSTINCT95
+    (0x71, None, 2),          // TETRIS BLAST
+    (0x9c, None, 16),         // PINOCCHIO
+    (0xbd, None, 25),
+    (0x5d, None, 42),         // BA.TOSHINDEN
+    (0x6d, None, 42),         // NETTOU KOF 95
+    (0x67, None, 5),
+    (0x3f, None, 0),          // TETRIS PLUS
+    (0x6b, None, 39),         // DONKEYKONGLAND 3
+    (0xb3, Some(b'B'), 36),
+    (0x46, Some(b'E'), 22),   // SUPER MARIOLAND
+    (0x28, Some(b'F'), 25),   // GOLF
+    (0xa5, Some(b'A'), 6),    // SOLARSTRIKER
+    (0xc6, Some(b'A'), 32),   // GBWARS
+    (0xd3, Some(b'R'), 12),   // KAERUNOTAMENI
+    (0x27, Some(b'B'), 36),
+    (0x61, Some(b'E'), 11),   // POKEMON BLUE
+    (0x18, Some(b'K'), 39),   // DONKEYKONGLAND
+    (0x66, Some(b'E'), 18),   // GAMEBOY GALLERY2
+    (0x6a, Some(b'K'), 39),   // DONKEYKONGLAND 2
+    (0xbf, Some(b' '), 24),   // KID ICARUS
+    (0x0d, Some(b'R'), 31),   // TETRIS2
+    (0xf4, Some(b'-'), 50),
+    (0xb3, Some(b'U'), 17),   // MOGURANYA
+    (0x46, Some(b'R'), 46),
+    (0x28, Some(b'A'), 6),    // GALAGA&GALAXIAN
+    (0xa5, Some(b'R'), 27),   // BT2RAGNAROKWORLD
+    (0xc6, Some(b' '), 0),    // KEN GRIFFEY JR
+    (0xd3, Some(b'I'), 47),
+    (0x27, Some(b'N'), 41),   // MAGNETIC SOCCER
+    (0x61, Some(b'A'), 41),   // VEGAS STAKES
+    (0x18, Some(b'I'), 0),
+    (0x66, Some(b'L'), 0),    // MILLI/CENTI/PEDE
+    (0x6a, Some(b'I'), 19),   // MARIO & YOSHI
+    (0xbf, Some(b'C'), 34),   // SOCCER
+    (0x0d, Some(b'E'), 23),   // POKEBOM
+    (0xf4, Some(b' '), 18),   // G&W GALLERY
+    (0xb3, Some(b'R'), 29),   // TETRIS ATTACK
+];
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        joypad::{Btn, Dir},
+        ppu::compat::CompatPalette,
+    };
+
+    fn checksum(title: &[u8]) -> u8 {
+        title.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+    }
+
+    #[test]
+    fn from_title() {
+        let tetris = CompatPalette::from_title(checksum(b"TETRIS"), b'R');
+        assert_eq!([0xff, 0xff, 0x00], tetris.bg[1]);
+        assert_eq!([0xff, 0x00, 0x00], tetris.obj1[2]);
+
+        let zelda = CompatPalette::from_title(checksum(b"ZELDA"), b'D');
+        assert_eq!([0x00, 0xff, 0x00], zelda.obj0[1]);
+
+        // titles that aren't in the table
+        let default = CompatPalette::from_keys(Dir::Right, Some(Btn::A));
+        assert_eq!(default,
+                   CompatPalette::from_title(checksum(b"UNKNOWN TITLE"), b'N'));
+        assert_eq!(default, CompatPalette::from_title(0x00, b'A'));
+    }
+
+    #[test]
+    fn ambiguous() {
+        // 4th letter tells apart titles with the same checksum
+        let mogu = CompatPalette::from_title(checksum(b"MOGURANYA"), b'U');
+        let tetris = CompatPalette::from_title(checksum(b"TETRIS ATTACK"), b'R');
+        assert_eq!(checksum(b"MOGURANYA"), checksum(b"TETRIS ATTACK"));
+        assert_ne!(mogu, tetris);
+        assert_eq!(CompatPalette::combination(17), mogu);
+        assert_eq!(CompatPalette::combination(29), tetris);
+
+        // letters of the other titles with the same checksum
+        assert_eq!(CompatPalette::combination(36),
+                   CompatPalette::from_title(0xb3, b'B'));
+        // ... and no letter match
+        assert_eq!(CompatPalette::combination(0),
+                   CompatPalette::from_title(0xb3, b'X'));
+    }
+
+    #[test]
+    fn combinations() {
+        // combination that mixes two groups of colors (SUPER MARIOLAND)
+        let mario = CompatPalette::from_title(checksum(b"SUPER MARIOLAND"), b'E');
+        assert_eq!([0x00, 0x00, 0x00], mario.obj0[0]);
+        assert_eq!([0xff, 0xff, 0xff], mario.obj0[1]);
+        assert_eq!(mario.obj0, mario.obj1);
+    }
+
+    #[test]
+    fn from_keys() {
+        let gray = CompatPalette::from_keys(Dir::Left, Some(Btn::B));
+        assert_eq!([0xa4, 0xa4, 0xa4], gray.bg[1]);
+        assert_eq!(gray.bg, gray.obj0);
+        assert_eq!(CompatPalette::from_keys(Dir::Right, None),
+                   CompatPalette::from_keys(Dir::Right, Some(Btn::Start)));
+    }
+}
@@ -1,4 +1,7 @@
-use crate::device::Device;
+use crate::{
+    device::Device,
+    state::{Error, Reader, State, Writer},
+};
 
 const SIZE: usize = 40;
 
@@ -101,3 +104,24 @@ impl Device for Oam {
         }
     }
 }
+
+impl State for Oam {
+    fn save_state(&self, state: &mut Writer) {
+        for entry in self.entries.iter() {
+            state.bytes(&[entry.ypos, entry.xpos, entry.tile, entry.flags]);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        for entry in self.entries.iter_mut() {
+            let mut data = [0; 4];
+            state.bytes(&mut data)?;
+            let [ypos, xpos, tile, flags] = data;
+            *entry = Entry { ypos,
+                             xpos,
+                             tile,
+                             flags };
+        }
+        Ok(())
+    }
+}
@@ -113,8 +113,13 @@ impl Pal {
     /// Returns the color index given its index.
     /// The returned index may thenbe used to index a 4-color palette.
     pub fn bg_color(&self, index: usize) -> Color {
+        self.color_pal[self.bg_shade(index)]
+    }
+
+    /// Returns the shade (0-3) the BGP register maps the given color index to.
+    pub fn bg_shade(&self, index: usize) -> usize {
         let pal = self.bgp as usize;
-        self.color_pal[pal >> (2 * index) & 0x3]
+        pal >> (2 * index) & 0x3
     }
 
     /// Returns the BG palette.
@@ -138,12 +143,21 @@ impl Pal {
     /// # Panics
     /// Panics if `obp` is neither 0 nor 1.
     pub fn obp_color(&self, obp: usize, index: usize) -> Color {
+        self.color_pal[self.obp_shade(obp, index)]
+    }
+
+    /// Returns the shade (0-3) the OBP0 or OBP1 registers map the given color
+    /// index to.
+    ///
+    /// # Panics
+    /// Panics if `obp` is neither 0 nor 1.
+    pub fn obp_shade(&self, obp: usize, index: usize) -> usize {
         let pal = match obp {
             0 => self.obp0,
             1 => self.obp1,
             _ => panic!(),
         } as usize;
-        self.color_pal[pal >> (2 * index) & 0x3]
+        pal >> (2 * index) & 0x3
     }
 
     /// Returns the color of the LCD when it's turned off (color index 0).
@@ -256,6 +270,33 @@ impl ColorPal {
     pub fn clear_color(&self) -> Color {
         [0xff, 0xff, 0xff]
     }
+
+    /// Set the 4 colors of a BG color palette.
+    ///
+    /// # Panics
+    /// Panics if `palette` >= 8.
+    pub fn set_bg_pal(&mut self, palette: usize, colors: [Color; 4]) {
+        Self::set_pal(&mut self.bgp, palette, colors)
+    }
+
+    /// Set the 4 colors of an OB color palette.
+    ///
+    /// # Panics
+    /// Panics if `palette` >= 8.
+    pub fn set_ob_pal(&mut self, palette: usize, colors: [Color; 4]) {
+        Self::set_pal(&mut self.obp, palette, colors)
+    }
+
+    // Colors are stored as RGB555 (the lower 3 bits of each component are lost).
+    fn set_pal(pal: &mut [u8; COLOR_PAL_SIZE], palette: usize, colors: [Color; 4]) {
+        assert!(palette < COLOR_PAL_SIZE / 8);
+        for (i, [r, g, b]) in colors.iter().copied().enumerate() {
+            let color = u16::from(r >> 3) | u16::from(g >> 3) << 5 | u16::from(b >> 3) << 10;
+            let offset = 8 * palette + 2 * i;
+            pal[offset] = color as u8;
+            pal[offset + 1] = (color >> 8) as u8;
+        }
+    }
 }
 
 impl Device for ColorPal {
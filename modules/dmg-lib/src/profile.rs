@@ -0,0 +1,302 @@
+//! Cycle profiler.
+//!
+//! Counts the executions and the cycles spent on every instruction, and keeps
+//! track of the call stack by following the `CALL`, `RST` & `RET` instructions
+//! and the interrupts, to attribute the cycles to functions. Functions are
+//! identified by their entry point (bank & address), and named after the
+//! loaded [`Symbols`], if any.
+//!
+//! The results can be written as a text report, or as folded stacks (one line
+//! per call stack with the cycles spent on it), the input format of most
+//! flamegraph tools.
+//!
+//! [`Symbols`]: #
+use crate::symbols::Symbols;
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// A function or an instruction: (bank, address).
+pub type Location = (usize, u16);
+
+/// Cycles & executions of an instruction.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Hits {
+    pub count: u64,
+    /// Cycles of the 4MHz clock.
+    pub cycles: u64,
+}
+
+/// Statistics of a function.
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq)]
+pub struct Function {
+    pub calls: u64,
+    /// Cycles spent on the function itself.
+    pub self_cycles: u64,
+    /// Cycles spent on the function, including the functions it calls.
+    pub total_cycles: u64,
+}
+
+// Node of the call tree.
+struct Node {
+    parent: usize,
+    function: Location,
+    calls: u64,
+    cycles: u64,
+    // SP after the call (pointing to the return address)
+    sp: u16,
+}
+
+// What happened in a profiled step, reported by the MMU.
+pub(crate) enum Event {
+    None,
+    // Call to the given function (or interrupt), with SP after the call.
+    Call(Location, u16),
+    // Return, with SP after the return.
+    Return(u16),
+}
+
+/// Profiler (see [`Mmu::start_profile`]).
+///
+/// [`Mmu::start_profile`]: #
+pub struct Profiler {
+    hits: HashMap<Location, Hits>,
+    // call tree, the first node is the root (the code running when the profiler
+    // was started, until it returns)
+    nodes: Vec<Node>,
+    children: HashMap<(usize, Location), usize>,
+    current: usize,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let root = Node { parent: 0,
+                          function: (0, 0),
+                          calls: 0,
+                          cycles: 0,
+                          sp: 0 };
+        Self { hits: HashMap::new(),
+               nodes: vec![root],
+               children: HashMap::new(),
+               current: 0 }
+    }
+
+    /// Executions & cycles of each instruction.
+    pub fn hits(&self) -> &HashMap<Location, Hits> {
+        &self.hits
+    }
+
+    /// Statistics of each function.
+    pub fn functions(&self) -> HashMap<Location, Function> {
+        let mut functions: HashMap<Location, Function> = HashMap::new();
+        for (id, node) in self.nodes.iter().enumerate() {
+            let function = functions.entry(node.function).or_default();
+            function.calls += node.calls;
+            function.self_cycles += node.cycles;
+
+            // count the cycles once per function in the stack (recursion)
+            let mut stack = self.stack(id);
+            stack.sort_unstable();
+            stack.dedup();
+            for function in stack {
+                functions.entry(function).or_default().total_cycles += node.cycles;
+            }
+        }
+        functions
+    }
+
+    /// Functions in the current call stack, from the outermost one.
+    pub fn call_stack(&self) -> Vec<Location> {
+        let mut stack = self.stack(self.current);
+        stack.reverse();
+        stack
+    }
+
+    /// Write the hottest `top` functions (by self cycles) and instructions.
+    pub fn write_report<W: Write>(&self,
+                                  mut out: W,
+                                  symbols: Option<&Symbols>,
+                                  top: usize)
+                                  -> io::Result<()> {
+        let total: u64 = self.nodes.iter().map(|node| node.cycles).sum();
+        let percent = |cycles| 100.0 * cycles as f64 / total.max(1) as f64;
+
+        let mut functions: Vec<_> = self.functions().into_iter().collect();
+        functions.sort_by_key(|&(location, f)| (Reverse(f.self_cycles), location));
+        writeln!(out, "Total cycles: {}", total)?;
+        writeln!(out)?;
+        writeln!(out, "  self%  total%      calls  function")?;
+        for (location, f) in functions.iter().take(top) {
+            writeln!(out,
+                     "{:>6.2} {:>7.2} {:>10}  {}",
+                     percent(f.self_cycles),
+                     percent(f.total_cycles),
+                     f.calls,
+                     name(*location, symbols))?;
+        }
+
+        let mut hits: Vec<_> = self.hits.iter().collect();
+        hits.sort_by_key(|&(location, hits)| (Reverse(hits.cycles), *location));
+        writeln!(out)?;
+        writeln!(out, "cycles%      count  instruction")?;
+        for (location, hits) in hits.iter().take(top) {
+            writeln!(out,
+                     "{:>7.2} {:>10}  {}",
+                     percent(hits.cycles),
+                     hits.count,
+                     address(**location, symbols))?;
+        }
+        Ok(())
+    }
+
+    /// Write the folded stacks (`Main;Update;Draw 1234`), one line per call
+    /// stack with the cycles spent on it.
+    pub fn write_folded<W: Write>(&self, mut out: W, symbols: Option<&Symbols>) -> io::Result<()> {
+        for (id, node) in self.nodes.iter().enumerate() {
+            if node.cycles == 0 {
+                continue;
+            }
+            let stack: Vec<_> = self.stack(id)
+                                    .into_iter()
+                                    .rev()
+                                    .map(|function| name(function, symbols))
+                                    .collect();
+            writeln!(out, "{} {}", stack.join(";"), node.cycles)?;
+        }
+        Ok(())
+    }
+
+    // Functions in the call stack of a node, from the innermost one.
+    fn stack(&self, mut id: usize) -> Vec<Location> {
+        let mut stack = vec![self.nodes[id].function];
+        while id != 0 {
+            id = self.nodes[id].parent;
+            stack.push(self.nodes[id].function);
+        }
+        stack
+    }
+
+    // Called by the MMU after each CPU step, with the location of the executed
+    // instruction (or the interrupted one).
+    pub(crate) fn sample(&mut self, location: Location, cycles: u64, event: Event) {
+        // the root is named after the first instruction
+        if self.hits.is_empty() {
+            self.nodes[0].function = location;
+        }
+        let hits = self.hits.entry(location).or_default();
+        hits.count += 1;
+        hits.cycles += cycles;
+        self.nodes[self.current].cycles += cycles;
+
+        match event {
+            Event::None => {}
+            Event::Call(function, sp) => {
+                let nodes = &mut self.nodes;
+                let parent = self.current;
+                let id = *self.children.entry((parent, function)).or_insert_with(|| {
+                                                                     nodes.push(Node { parent,
+                                                                                       function,
+                                                                                       calls: 0,
+                                                                                       cycles:
+                                                                                           0,
+                                                                                       sp });
+                                                                     nodes.len() - 1
+                                                                 });
+                self.nodes[id].calls += 1;
+                self.nodes[id].sp = sp;
+                self.current = id;
+            }
+            // pop the frames below the stack pointer (functions may drop their
+            // return address and jump elsewhere)
+            Event::Return(sp) => {
+                while self.current != 0 && self.nodes[self.current].sp < sp {
+                    self.current = self.nodes[self.current].parent;
+                }
+            }
+        }
+    }
+}
+
+// Name of a function.
+fn name(location: Location, symbols: Option<&Symbols>) -> String {
+    let (bank, addr) = location;
+    match symbols.and_then(|symbols| symbols.label(bank, addr)) {
+        Some(label) => label.to_string(),
+        None => format!("{:02x}:{:04x}", bank, addr),
+    }
+}
+
+// Address of an instruction.
+fn address(location: Location, symbols: Option<&Symbols>) -> String {
+    let (bank, addr) = location;
+    match symbols.and_then(|symbols| symbols.lookup(bank, addr)) {
+        Some((label, 0)) => format!("{:02x}:{:04x} {}", bank, addr, label),
+        Some((label, offset)) => format!("{:02x}:{:04x} {}+{}", bank, addr, label, offset),
+        None => format!("{:02x}:{:04x}", bank, addr),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        cartridge::Rom,
+        profile::{Function, Profiler},
+        symbols::Symbols,
+        Builder,
+    };
+
+    #[test]
+    fn calls() {
+        let mut rom = vec![0; 0x8000];
+        // 0100: CALL $0150
+        // 0103: JR $0100
+        rom[0x0100..0x0105].copy_from_slice(&[0xcd, 0x50, 0x01, 0x18, 0xfb]);
+        // 0150: INC A
+        // 0151: RET
+        rom[0x0150..0x0152].copy_from_slice(&[0x3c, 0xc9]);
+        let mut dmg = Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                                        .build();
+
+        dmg.mmu_mut().start_profile(Profiler::new());
+        dmg.step_instruction();
+        assert_eq!(vec![(0, 0x0100), (0, 0x0150)],
+                   dmg.mmu().profiler().unwrap().call_stack());
+        for _ in 1..40 {
+            dmg.step_instruction();
+        }
+        let profiler = dmg.mmu_mut().stop_profile().unwrap();
+
+        let functions = profiler.functions();
+        assert_eq!(Some(&Function { calls: 10,
+                                    self_cycles: 200,
+                                    total_cycles: 200 }),
+                   functions.get(&(0, 0x0150)));
+        assert_eq!(Some(&Function { calls: 0,
+                                    self_cycles: 360,
+                                    total_cycles: 560 }),
+                   functions.get(&(0, 0x0100)));
+        assert_eq!(10, profiler.hits()[&(0, 0x0151)].count);
+
+        let mut symbols = Symbols::new();
+        symbols.parse_sym("00:0100 Main\n00:0150 Sub\n").unwrap();
+        let mut folded = Vec::new();
+        profiler.write_folded(&mut folded, Some(&symbols)).unwrap();
+        assert_eq!("Main 360\nMain;Sub 200\n",
+                   String::from_utf8(folded).unwrap());
+
+        let mut report = Vec::new();
+        profiler.write_report(&mut report, Some(&symbols), 10)
+                .unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.starts_with("Total cycles: 560\n"));
+        assert!(report.contains(" 35.71   35.71         10  Sub\n"));
+    }
+}
@@ -0,0 +1,275 @@
+//! RAM search.
+//!
+//! Locates the variables of a game by taking snapshots of the RAM (cartridge
+//! RAM, WRAM & HRAM) and keeping the addresses whose value changes in the
+//! expected way between them (the lives decrease when a life is lost, the
+//! score increases...).
+//!
+//! ```
+//! use dmg_lib::{device::Device, search::{Compare, Kind, Search}, GameBoy};
+//!
+//! let mut dmg = GameBoy::default();
+//! let mut search = Search::new(dmg.mmu(), Kind::U8);
+//!
+//! dmg.mmu_mut().write(0xc042, 3);
+//! search.filter(dmg.mmu(), Compare::Increased);
+//! search.filter(dmg.mmu(), Compare::Value(3));
+//!
+//! assert_eq!(&[(0, 0xc042)], search.candidates());
+//! ```
+use crate::{apu::device::Audio, cartridge::Cartridge, mmu::Mmu, ppu::Video, Mode};
+use std::{collections::BTreeMap, fmt};
+
+/// An address and its bank (the WRAM bank in D000-DFFF, 0 elsewhere).
+pub type Location = (usize, u16);
+
+/// Interpretation of the values.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Kind {
+    U8,
+    U16Le,
+    U16Be,
+    /// Two binary-coded decimal digits.
+    Bcd8,
+    /// Four BCD digits, little endian.
+    Bcd16Le,
+    /// Four BCD digits, big endian.
+    Bcd16Be,
+}
+
+impl Kind {
+    pub const ALL: [Kind; 6] = [Kind::U8,
+                                Kind::U16Le,
+                                Kind::U16Be,
+                                Kind::Bcd8,
+                                Kind::Bcd16Le,
+                                Kind::Bcd16Be];
+
+    /// Size in bytes.
+    pub fn size(self) -> u16 {
+        match self {
+            Kind::U8 | Kind::Bcd8 => 1,
+            _ => 2,
+        }
+    }
+
+    // Decode a value from its bytes (in memory order). Returns `None` for
+    // invalid BCD digits.
+    fn decode(self, bytes: [u8; 2]) -> Option<u32> {
+        let [lo, hi] = bytes;
+        match self {
+            Kind::U8 => Some(lo.into()),
+            Kind::U16Le => Some(u16::from_le_bytes([lo, hi]).into()),
+            Kind::U16Be => Some(u16::from_be_bytes([lo, hi]).into()),
+            Kind::Bcd8 => bcd(lo),
+            Kind::Bcd16Le => Some(bcd(hi)? * 100 + bcd(lo)?),
+            Kind::Bcd16Be => Some(bcd(lo)? * 100 + bcd(hi)?),
+        }
+    }
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Kind::U8 => "8-bit",
+            Kind::U16Le => "16-bit LE",
+            Kind::U16Be => "16-bit BE",
+            Kind::Bcd8 => "BCD 8-bit",
+            Kind::Bcd16Le => "BCD 16-bit LE",
+            Kind::Bcd16Be => "BCD 16-bit BE",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn bcd(byte: u8) -> Option<u32> {
+    let (hi, lo) = (byte >> 4, byte & 0xf);
+    if hi > 9 || lo > 9 {
+        None
+    } else {
+        Some(u32::from(hi) * 10 + u32::from(lo))
+    }
+}
+
+/// Comparison between the value in the previous snapshot and the current one.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Compare {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    /// The current value is the given one.
+    Value(u32),
+}
+
+impl Compare {
+    fn matches(self, old: u32, new: u32) -> bool {
+        match self {
+            Compare::Equal => new == old,
+            Compare::Changed => new != old,
+            Compare::Increased => new > old,
+            Compare::Decreased => new < old,
+            Compare::Value(value) => new == value,
+        }
+    }
+}
+
+/// Contents of the RAM at some point.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    mem: BTreeMap<Location, u8>,
+}
+
+impl Snapshot {
+    /// Snapshot of the mapped cartridge RAM (A000-BFFF), every WRAM bank, and
+    /// HRAM (FF80-FFFE).
+    pub fn new<C: Cartridge, V: Video, D: Audio>(mmu: &Mmu<C, V, D>) -> Self {
+        let mut mem = BTreeMap::new();
+        for addr in (0xa000..=0xbfff).chain(0xff80..=0xfffe) {
+            mem.insert((0, addr), mmu.peek(addr));
+        }
+        let banks = if mmu.mode() == Mode::CGB { 7 } else { 1 };
+        for bank in 0..=banks {
+            let start = if bank == 0 { 0xc000 } else { 0xd000 };
+            for (i, byte) in mmu.wram().bank(bank).iter().enumerate() {
+                mem.insert((bank, start + i as u16), *byte);
+            }
+        }
+        Self { mem }
+    }
+
+    pub fn len(&self) -> usize {
+        self.mem.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mem.is_empty()
+    }
+
+    pub fn get(&self, location: Location) -> Option<u8> {
+        self.mem.get(&location).copied()
+    }
+
+    /// Value at the given location (`None` if it's not in the snapshot).
+    pub fn value(&self, location: Location, kind: Kind) -> Option<u32> {
+        let (bank, addr) = location;
+        let lo = self.get(location)?;
+        let hi = match kind.size() {
+            1 => 0,
+            _ => self.get((bank, addr.checked_add(1)?))?,
+        };
+        kind.decode([lo, hi])
+    }
+}
+
+/// Search of the locations of a variable.
+#[derive(Debug, Clone)]
+pub struct Search {
+    kind: Kind,
+    snapshot: Snapshot,
+    candidates: Vec<Location>,
+}
+
+impl Search {
+    /// Start a search, with every location of the RAM as a candidate.
+    pub fn new<C: Cartridge, V: Video, D: Audio>(mmu: &Mmu<C, V, D>, kind: Kind) -> Self {
+        let snapshot = Snapshot::new(mmu);
+        let candidates = snapshot.mem
+                                 .keys()
+                                 .copied()
+                                 .filter(|location| snapshot.value(*location, kind).is_some())
+                                 .collect();
+        Self { kind,
+               snapshot,
+               candidates }
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// Remaining candidates.
+    pub fn candidates(&self) -> &[Location] {
+        &self.candidates
+    }
+
+    /// Value at the given location, in the last snapshot.
+    pub fn value(&self, location: Location) -> Option<u32> {
+        self.snapshot.value(location, self.kind)
+    }
+
+    /// Take a new snapshot, and keep the candidates whose value compares to
+    /// the one in the previous snapshot. Returns the number of candidates left.
+    pub fn filter<C: Cartridge, V: Video, D: Audio>(&mut self,
+                                                    mmu: &Mmu<C, V, D>,
+                                                    compare: Compare)
+                                                    -> usize {
+        let snapshot = Snapshot::new(mmu);
+        let kind = self.kind;
+        let old = &self.snapshot;
+        self.candidates.retain(|location| {
+                           match (old.value(*location, kind), snapshot.value(*location, kind)) {
+                               (Some(old), Some(new)) => compare.matches(old, new),
+                               _ => false,
+                           }
+                       });
+        self.snapshot = snapshot;
+        self.candidates.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        device::Device,
+        search::{Compare, Kind, Search},
+        GameBoy,
+    };
+
+    #[test]
+    fn filter() {
+        let mut dmg = GameBoy::default();
+        let mut search = Search::new(dmg.mmu(), Kind::U8);
+        // cartridge RAM, HRAM & 8 WRAM banks (CGB)
+        assert_eq!(0x2000 + 0x7f + 8 * 0x1000, search.len());
+
+        dmg.mmu_mut().write(0xc010, 1);
+        dmg.mmu_mut().write(0xd123, 1);
+        assert_eq!(2, search.filter(dmg.mmu(), Compare::Increased));
+        assert_eq!(2, search.filter(dmg.mmu(), Compare::Equal));
+
+        dmg.mmu_mut().write(0xc010, 2);
+        dmg.mmu_mut().write(0xd123, 0);
+        assert_eq!(1, search.filter(dmg.mmu(), Compare::Increased));
+        assert_eq!(&[(0, 0xc010)], search.candidates());
+        assert_eq!(Some(2), search.value((0, 0xc010)));
+        assert_eq!(0, search.filter(dmg.mmu(), Compare::Changed));
+    }
+
+    #[test]
+    fn bcd() {
+        let mut dmg = GameBoy::default();
+        dmg.mmu_mut().write(0xff90, 0x12);
+        dmg.mmu_mut().write(0xff91, 0x34);
+
+        let mut search = Search::new(dmg.mmu(), Kind::Bcd16Be);
+        search.filter(dmg.mmu(), Compare::Value(1234));
+        assert_eq!(&[(0, 0xff90)], search.candidates());
+
+        let mut search = Search::new(dmg.mmu(), Kind::Bcd16Le);
+        search.filter(dmg.mmu(), Compare::Value(3412));
+        assert_eq!(&[(0, 0xff90)], search.candidates());
+
+        // invalid BCD digits
+        dmg.mmu_mut().write(0xff90, 0x1a);
+        assert_eq!(0, search.filter(dmg.mmu(), Compare::Changed));
+    }
+}
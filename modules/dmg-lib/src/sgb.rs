@@ -0,0 +1,565 @@
+//! Super Game Boy emulation.
+//!
+//! Games talk to the SGB by sending 16-byte command packets, one bit at a time,
+//! through the P14 & P15 lines of the joypad register (FF00). Commands are used
+//! to colorize the screen (four palettes assigned to 8x8 cells of the screen),
+//! to draw a border around it and to enable multiplayer. Larger blocks of data
+//! (palettes, border tiles & attribute files) are transferred by displaying
+//! them on the screen for a frame.
+use crate::{
+    ppu::{palette::Color, LCD_HEIGHT, LCD_WIDTH},
+    state::{Error, Reader, State, Writer},
+};
+use std::mem;
+
+/// Width of the SGB frame (screen & border).
+pub const SGB_WIDTH: usize = 256;
+/// Height of the SGB frame (screen & border).
+pub const SGB_HEIGHT: usize = 224;
+
+pub(crate) const PACKET_SIZE: usize = 16;
+pub(crate) const TRANSFER_SIZE: usize = 0x1000;
+
+// Position of the Game Boy screen within the frame.
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+
+// The screen is divided in 20x18 cells of 8x8 pixels.
+const CELLS_X: usize = LCD_WIDTH / 8;
+const CELLS_Y: usize = LCD_HEIGHT / 8;
+
+const ATTR_FILES: usize = 45;
+const ATTR_FILE_SIZE: usize = 90;
+
+// Palette set by the SGB BIOS.
+const DEFAULT_PAL: [u16; 4] = [0x67bf, 0x265b, 0x10b5, 0x2866];
+
+// Command codes
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0a;
+const PAL_TRN: u8 = 0x0b;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+/// Screen mask (set with the MASK_EN command).
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Mask {
+    /// The screen is displayed normally.
+    None,
+    /// The last frame is kept on the screen.
+    Freeze,
+    /// The screen is black.
+    Black,
+    /// The screen is filled with color 0.
+    Color0,
+}
+
+// Data expected in the next VRAM transfer.
+#[derive(Debug, Clone, Copy)]
+enum Transfer {
+    Pal,
+    Chr(usize),
+    Pct,
+    Attr,
+}
+
+/// Super Game Boy state.
+pub struct Sgb {
+    // Packets of the command being received.
+    command: Vec<u8>,
+    pal: [[u16; 4]; 4],
+    sys_pal: Box<[[u16; 4]; 512]>,
+    attr: [[u8; CELLS_X]; CELLS_Y],
+    attr_files: Box<[u8; ATTR_FILES * ATTR_FILE_SIZE]>,
+    // Border tiles (4bpp), tile map & palettes (4-7).
+    tiles: Box<[u8; 2 * TRANSFER_SIZE]>,
+    map: Box<[u16; 32 * 28]>,
+    border_pal: [[u16; 16]; 4],
+    mask: Mask,
+    players: u8,
+    transfer: Option<Transfer>,
+    frame: Box<[[Color; SGB_WIDTH]; SGB_HEIGHT]>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self { command: Vec::with_capacity(7 * PACKET_SIZE),
+               pal: [DEFAULT_PAL; 4],
+               sys_pal: Box::new([[0; 4]; 512]),
+               attr: [[0; CELLS_X]; CELLS_Y],
+               attr_files: Box::new([0; ATTR_FILES * ATTR_FILE_SIZE]),
+               tiles: Box::new([0; 2 * TRANSFER_SIZE]),
+               map: Box::new([0; 32 * 28]),
+               border_pal: [[0; 16]; 4],
+               mask: Mask::None,
+               players: 1,
+               transfer: None,
+               frame: Box::new([[[0; 3]; SGB_WIDTH]; SGB_HEIGHT]) }
+    }
+}
+
+impl Sgb {
+    /// Return the colors of one of the 4 screen palettes.
+    ///
+    /// # Panics
+    /// Panics if `pal` >= 4.
+    pub fn palette(&self, pal: usize) -> [Color; 4] {
+        let [c0, c1, c2, c3] = self.pal[pal];
+        [rgb(c0), rgb(c1), rgb(c2), rgb(c3)]
+    }
+
+    /// Return the palette assigned to each 8x8 cell of the screen.
+    pub fn attr(&self) -> &[[u8; CELLS_X]; CELLS_Y] {
+        &self.attr
+    }
+
+    /// Return the screen mask.
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    /// Return the number of players requested with MLT_REQ (1, 2 or 4).
+    pub fn players(&self) -> u8 {
+        self.players
+    }
+
+    /// Return the last rendered frame.
+    pub fn frame(&self) -> &[[Color; SGB_WIDTH]; SGB_HEIGHT] {
+        &self.frame
+    }
+
+    // Receive a packet from the joypad. The lower 3 bits of the first byte give
+    // the number of packets of the command.
+    pub(crate) fn receive(&mut self, packet: &[u8; PACKET_SIZE]) {
+        self.command.extend_from_slice(packet);
+        let len = (self.command[0] & 0x7).max(1) as usize;
+        if self.command.len() >= len * PACKET_SIZE {
+            let command = mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    pub(crate) fn transfer_pending(&self) -> bool {
+        self.transfer.is_some()
+    }
+
+    // Complete a VRAM transfer with the data displayed on the screen.
+    pub(crate) fn transfer(&mut self, data: &[u8; TRANSFER_SIZE]) {
+        match self.transfer.take() {
+            Some(Transfer::Pal) => {
+                for (i, pal) in self.sys_pal.iter_mut().enumerate() {
+                    for (c, color) in pal.iter_mut().enumerate() {
+                        *color = word(data, 8 * i + 2 * c);
+                    }
+                }
+            }
+            Some(Transfer::Chr(bank)) => {
+                self.tiles[bank * TRANSFER_SIZE..(bank + 1) * TRANSFER_SIZE].copy_from_slice(data)
+            }
+            Some(Transfer::Pct) => {
+                for (i, entry) in self.map.iter_mut().enumerate() {
+                    *entry = word(data, 2 * i);
+                }
+                for (p, pal) in self.border_pal.iter_mut().enumerate() {
+                    for (c, color) in pal.iter_mut().enumerate() {
+                        *color = word(data, 0x800 + 32 * p + 2 * c);
+                    }
+                }
+            }
+            Some(Transfer::Attr) => {
+                let len = self.attr_files.len();
+                self.attr_files.copy_from_slice(&data[..len])
+            }
+            None => {}
+        }
+    }
+
+    // Colorize the screen and compose the frame with the border.
+    pub(crate) fn render(&mut self,
+                         shades: &[[u8; LCD_WIDTH]; LCD_HEIGHT],
+                         screen: &mut [[Color; LCD_WIDTH]; LCD_HEIGHT]) {
+        for (y, row) in screen.iter_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = match self.mask {
+                    Mask::None => {
+                        let pal = self.attr[y / 8][x / 8] as usize;
+                        rgb(self.pal[pal][shades[y][x] as usize])
+                    }
+                    Mask::Freeze => self.frame[SCREEN_Y + y][SCREEN_X + x],
+                    Mask::Black => [0, 0, 0],
+                    Mask::Color0 => rgb(self.pal[0][0]),
+                };
+            }
+        }
+
+        self.draw_border();
+        for (y, row) in screen.iter().enumerate() {
+            self.frame[SCREEN_Y + y][SCREEN_X..SCREEN_X + LCD_WIDTH].copy_from_slice(row);
+        }
+    }
+
+    // Border tiles are in SNES format (4 bit planes, 32 bytes per tile). Color 0
+    // is transparent and shows the backdrop (color 0 of the screen palettes).
+    fn draw_border(&mut self) {
+        let backdrop = rgb(self.pal[0][0]);
+        for (i, entry) in self.map.iter().enumerate() {
+            let tile = 32 * (entry & 0xff) as usize;
+            let pal = (entry >> 10 & 0x3) as usize;
+            let (tx, ty) = (8 * (i % 32), 8 * (i / 32));
+            for row in 0..8 {
+                for col in 0..8 {
+                    let r = if entry & 0x8000 != 0 { 7 - row } else { row };
+                    let bit = if entry & 0x4000 != 0 { col } else { 7 - col };
+                    let plane = |offset: usize| (self.tiles[tile + offset + 2 * r] >> bit) & 0x1;
+                    let index = plane(0) | plane(1) << 1 | plane(16) << 2 | plane(17) << 3;
+                    self.frame[ty + row][tx + col] = if index == 0 {
+                        backdrop
+                    } else {
+                        rgb(self.border_pal[pal][index as usize])
+                    };
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_pals(0, 1, data),
+            PAL23 => self.set_pals(2, 3, data),
+            PAL03 => self.set_pals(0, 3, data),
+            PAL12 => self.set_pals(1, 2, data),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            PAL_SET => self.pal_set(data),
+            PAL_TRN => self.transfer = Some(Transfer::Pal),
+            MLT_REQ => {
+                self.players = match data[1] & 0x3 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                }
+            }
+            CHR_TRN => self.transfer = Some(Transfer::Chr((data[1] & 0x1) as usize)),
+            PCT_TRN => self.transfer = Some(Transfer::Pct),
+            ATTR_TRN => self.transfer = Some(Transfer::Attr),
+            ATTR_SET => {
+                self.attr_set((data[1] & 0x3f) as usize);
+                if data[1] & 0x40 != 0 {
+                    self.mask = Mask::None;
+                }
+            }
+            MASK_EN => {
+                self.mask = match data[1] & 0x3 {
+                    0 => Mask::None,
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    _ => Mask::Color0,
+                }
+            }
+            // sound, SNES code & the rest of commands are not emulated
+            _ => {}
+        }
+    }
+
+    // PAL01, PAL23, PAL03 & PAL12. Color 0 is shared by all palettes.
+    fn set_pals(&mut self, a: usize, b: usize, data: &[u8]) {
+        let color0 = word(data, 1);
+        for c in 1..4 {
+            self.pal[a][c] = word(data, 1 + 2 * c);
+            self.pal[b][c] = word(data, 7 + 2 * c);
+        }
+        for pal in self.pal.iter_mut() {
+            pal[0] = color0;
+        }
+    }
+
+    // Each data set defines a rectangle, and the palettes of the cells inside,
+    // outside and on its border.
+    fn attr_blk(&mut self, data: &[u8]) {
+        let sets = (data[1] & 0x1f) as usize;
+        for set in data[2..].chunks_exact(6).take(sets) {
+            let mut ctrl = set[0] & 0x7;
+            let inside = set[1] & 0x3;
+            let mut border = set[1] >> 2 & 0x3;
+            let outside = set[1] >> 4 & 0x3;
+            // if only the inside or the outside is changed, the border is too
+            match ctrl {
+                0x1 => border = inside,
+                0x4 => border = outside,
+                _ => {}
+            }
+            if ctrl == 0x1 || ctrl == 0x4 {
+                ctrl |= 0x2;
+            }
+            let (x1, y1, x2, y2) = (set[2] & 0x1f, set[3] & 0x1f, set[4] & 0x1f, set[5] & 0x1f);
+            for (y, row) in self.attr.iter_mut().enumerate() {
+                for (x, attr) in row.iter_mut().enumerate() {
+                    let (x, y) = (x as u8, y as u8);
+                    let in_rect = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_border = in_rect && (x == x1 || x == x2 || y == y1 || y == y2);
+                    if on_border {
+                        if ctrl & 0x2 != 0 {
+                            *attr = border;
+                        }
+                    } else if in_rect {
+                        if ctrl & 0x1 != 0 {
+                            *attr = inside;
+                        }
+                    } else if ctrl & 0x4 != 0 {
+                        *attr = outside;
+                    }
+                }
+            }
+        }
+    }
+
+    // Each data byte sets the palette of a row (bit 7 set) or a column.
+    fn attr_lin(&mut self, data: &[u8]) {
+        let sets = data[1] as usize;
+        for line in data[2..].iter().take(sets) {
+            let n = (line & 0x1f) as usize;
+            let pal = line >> 5 & 0x3;
+            if line & 0x80 != 0 {
+                if let Some(row) = self.attr.get_mut(n) {
+                    *row = [pal; CELLS_X];
+                }
+            } else if n < CELLS_X {
+                for row in self.attr.iter_mut() {
+                    row[n] = pal;
+                }
+            }
+        }
+    }
+
+    // Divide the screen in two, with a line in between.
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0x3;
+        let before = data[1] >> 2 & 0x3;
+        let line = data[1] >> 4 & 0x3;
+        let div = (data[2] & 0x1f) as usize;
+        for (y, row) in self.attr.iter_mut().enumerate() {
+            for (x, attr) in row.iter_mut().enumerate() {
+                let pos = if data[1] & 0x40 != 0 { y } else { x };
+                *attr = match pos {
+                    pos if pos < div => before,
+                    pos if pos == div => line,
+                    _ => after,
+                };
+            }
+        }
+    }
+
+    // Set the palettes of consecutive cells, 2 bits per cell.
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = ((data[1] & 0x1f) as usize, (data[2] & 0x1f) as usize);
+        let cells = (word(data, 3) as usize).min(CELLS_X * CELLS_Y);
+        let vertical = data[5] & 0x1 != 0;
+        for i in 0..cells {
+            let byte = match data.get(6 + i / 4) {
+                Some(byte) => byte,
+                None => break,
+            };
+            if x >= CELLS_X || y >= CELLS_Y {
+                break;
+            }
+            self.attr[y][x] = byte >> (6 - 2 * (i % 4)) & 0x3;
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    // Load the 4 palettes from the system palettes (transferred with PAL_TRN).
+    fn pal_set(&mut self, data: &[u8]) {
+        for p in 0..4 {
+            let index = (word(data, 1 + 2 * p) & 0x1ff) as usize;
+            self.pal[p] = self.sys_pal[index];
+        }
+        let color0 = self.pal[0][0];
+        for pal in self.pal.iter_mut() {
+            pal[0] = color0;
+        }
+        if data[9] & 0x80 != 0 {
+            self.attr_set((data[9] & 0x3f) as usize);
+        }
+        if data[9] & 0x40 != 0 {
+            self.mask = Mask::None;
+        }
+    }
+
+    // Load one of the attribute files (transferred with ATTR_TRN).
+    fn attr_set(&mut self, file: usize) {
+        if file >= ATTR_FILES {
+            return;
+        }
+        let file = &self.attr_files[file * ATTR_FILE_SIZE..(file + 1) * ATTR_FILE_SIZE];
+        for i in 0..CELLS_X * CELLS_Y {
+            self.attr[i / CELLS_X][i % CELLS_X] = file[i / 4] >> (6 - 2 * (i % 4)) & 0x3;
+        }
+    }
+}
+
+fn word(data: &[u8], offset: usize) -> u16 {
+    u16::from(data[offset]) | u16::from(data[offset + 1]) << 8
+}
+
+// RGB555 to RGB888
+fn rgb(color: u16) -> Color {
+    [(0xff * (color & 0x1f) / 0x1f) as u8,
+     (0xff * ((color >> 5) & 0x1f) / 0x1f) as u8,
+     (0xff * ((color >> 10) & 0x1f) / 0x1f) as u8]
+}
+
+// The frame isn't saved (a frozen screen shows whatever was last drawn).
+impl State for Sgb {
+    fn save_state(&self, state: &mut Writer) {
+        state.u8(self.command.len() as u8);
+        state.bytes(&self.command);
+        for color in self.pal.iter().chain(self.sys_pal.iter()).flatten() {
+            state.u16(*color);
+        }
+        for row in self.attr.iter() {
+            state.bytes(row);
+        }
+        state.bytes(&self.attr_files[..]);
+        state.bytes(&self.tiles[..]);
+        for tile in self.map.iter() {
+            state.u16(*tile);
+        }
+        for color in self.border_pal.iter().flatten() {
+            state.u16(*color);
+        }
+        state.u8(self.mask as u8);
+        state.u8(self.players);
+        state.u8(match self.transfer {
+                 None => 0xff,
+                 Some(Transfer::Pal) => 0,
+                 Some(Transfer::Chr(bank)) => 1 + bank as u8,
+                 Some(Transfer::Pct) => 3,
+                 Some(Transfer::Attr) => 4,
+             });
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.command.resize(state.u8()? as usize, 0);
+        state.bytes(&mut self.command)?;
+        for color in self.pal.iter_mut().chain(self.sys_pal.iter_mut()).flatten() {
+            *color = state.u16()?;
+        }
+        for row in self.attr.iter_mut() {
+            state.bytes(row)?;
+        }
+        state.bytes(&mut self.attr_files[..])?;
+        state.bytes(&mut self.tiles[..])?;
+        for tile in self.map.iter_mut() {
+            *tile = state.u16()?;
+        }
+        for color in self.border_pal.iter_mut().flatten() {
+            *color = state.u16()?;
+        }
+        self.mask = match state.u8()? {
+            0 => Mask::None,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(Error::InvalidData),
+        };
+        self.players = state.u8()?;
+        self.transfer = match state.u8()? {
+            0xff => None,
+            0 => Some(Transfer::Pal),
+            bank @ 1..=2 => Some(Transfer::Chr(bank as usize - 1)),
+            3 => Some(Transfer::Pct),
+            4 => Some(Transfer::Attr),
+            _ => return Err(Error::InvalidData),
+        };
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::sgb::{Mask, Sgb, PACKET_SIZE};
+
+    fn packet(data: &[u8]) -> [u8; PACKET_SIZE] {
+        let mut packet = [0; PACKET_SIZE];
+        packet[..data.len()].copy_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn pal01() {
+        let mut sgb = Sgb::default();
+        // color 0 = white, palette 0 color 1 = red, palette 1 color 3 = blue
+        sgb.receive(&packet(&[0x01, 0xff, 0x7f, 0x1f, 0x00, 0, 0, 0, 0, 0, 0, 0, 0, 0x00, 0x7c]));
+        assert_eq!([0xff, 0xff, 0xff], sgb.palette(0)[0]);
+        assert_eq!([0xff, 0xff, 0xff], sgb.palette(3)[0]);
+        assert_eq!([0xff, 0x00, 0x00], sgb.palette(0)[1]);
+        assert_eq!([0x00, 0x00, 0xff], sgb.palette(1)[3]);
+    }
+
+    #[test]
+    fn attr_blk() {
+        let mut sgb = Sgb::default();
+        // inside palette 1, border 2, outside 3
+        sgb.receive(&packet(&[0x21, 0x01, 0x07, 0x39, 2, 2, 4, 4]));
+        assert_eq!(3, sgb.attr()[0][0]);
+        assert_eq!(2, sgb.attr()[2][2]);
+        assert_eq!(1, sgb.attr()[3][3]);
+        assert_eq!(2, sgb.attr()[4][3]);
+        assert_eq!(3, sgb.attr()[5][5]);
+    }
+
+    #[test]
+    fn attr_chr() {
+        let mut sgb = Sgb::default();
+        // 2 packets, 5 cells starting at (18, 0)
+        sgb.receive(&packet(&[0x3a, 18, 0, 5, 0, 0, 0b0001_1011, 0b0100_0000]));
+        assert!(sgb.command.len() == PACKET_SIZE);
+        sgb.receive(&packet(&[]));
+        assert_eq!([0, 1], sgb.attr()[0][18..]);
+        assert_eq!([2, 3, 1], sgb.attr()[1][..3]);
+    }
+
+    #[test]
+    fn mask_en() {
+        let mut sgb = Sgb::default();
+        sgb.receive(&packet(&[0xb9, 0x02]));
+        assert_eq!(Mask::Black, sgb.mask());
+    }
+
+    #[test]
+    fn transfer() {
+        let mut sgb = Sgb::default();
+        // CHR_TRN to the upper half of the tiles
+        sgb.receive(&packet(&[0x99, 0x01]));
+        assert!(sgb.transfer_pending());
+        sgb.transfer(&[0xaa; 0x1000]);
+        assert!(!sgb.transfer_pending());
+        assert_eq!(0x00, sgb.tiles[0x0fff]);
+        assert_eq!(0xaa, sgb.tiles[0x1000]);
+    }
+}
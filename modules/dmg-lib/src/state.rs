@@ -0,0 +1,195 @@
+//! Savestates.
+//!
+//! The state of every component is written as a flat sequence of
+//! little-endian values, in a fixed order. Things that aren't part of the
+//! emulated hardware (the video & audio output, user palettes, channel masks,
+//! the VGM log...) are left out, and so is the boot ROM, which must be provided
+//! again when building the emulator the state is loaded into.
+use crate::Model;
+
+const MAGIC: &[u8; 4] = b"DMGS";
+const VERSION: u8 = 1;
+
+/// Errors when loading savestates.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// The data doesn't start with a savestate header.
+    InvalidHeader,
+    /// The savestate was written by an incompatible version of the emulator.
+    Version(u8),
+    /// The savestate was taken on a different hardware model.
+    Model,
+    /// The data ended before the whole state was read.
+    UnexpectedEof,
+    /// The data contains an invalid value.
+    InvalidData,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidHeader => write!(f, "Invalid savestate header"),
+            Error::Version(v) => write!(f, "Unsupported savestate version ({})", v),
+            Error::Model => write!(f, "The savestate was taken on a different model"),
+            Error::UnexpectedEof => write!(f, "Unexpected end of savestate data"),
+            Error::InvalidData => write!(f, "Invalid savestate data"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// State of an emulated component.
+pub(crate) trait State {
+    fn save_state(&self, state: &mut Writer);
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error>;
+}
+
+/// Savestate data being written.
+#[derive(Default)]
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new(model: Model) -> Self {
+        let mut writer = Self::default();
+        writer.bytes(MAGIC);
+        writer.u8(VERSION);
+        writer.u8(model as u8);
+        writer
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, data: u8) {
+        self.data.push(data);
+    }
+
+    pub fn u16(&mut self, data: u16) {
+        self.data.extend_from_slice(&data.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, data: u64) {
+        self.data.extend_from_slice(&data.to_le_bytes());
+    }
+
+    pub fn bool(&mut self, data: bool) {
+        self.u8(data as u8);
+    }
+
+    pub fn bytes(&mut self, data: &[u8]) {
+        self.data.extend_from_slice(data);
+    }
+}
+
+/// Savestate data being read.
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8], model: Model) -> Result<Self, Error> {
+        if data.len() < MAGIC.len() + 2 || &data[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidHeader);
+        }
+        let mut reader = Self { data: &data[MAGIC.len()..] };
+        match reader.u8()? {
+            VERSION => {}
+            version => return Err(Error::Version(version)),
+        }
+        if reader.u8()? != model as u8 {
+            return Err(Error::Model);
+        }
+        Ok(reader)
+    }
+
+    // Read data without a savestate header.
+    pub(crate) fn from_bytes(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        let mut data = [0];
+        self.bytes(&mut data)?;
+        Ok(data[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let mut data = [0; 2];
+        self.bytes(&mut data)?;
+        Ok(u16::from_le_bytes(data))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        let mut data = [0; 8];
+        self.bytes(&mut data)?;
+        Ok(u64::from_le_bytes(data))
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(Error::InvalidData),
+        }
+    }
+
+    /// Fill the given buffer with the next bytes of the savestate.
+    pub fn bytes(&mut self, data: &mut [u8]) -> Result<(), Error> {
+        if self.data.len() < data.len() {
+            return Err(Error::UnexpectedEof);
+        }
+        let (head, tail) = self.data.split_at(data.len());
+        data.copy_from_slice(head);
+        self.data = tail;
+        Ok(())
+    }
+}
+
+/// Hash of the given data (64-bit FNV-1a). Unlike the hashers of the standard
+/// library, the result doesn't change between runs or platforms, so it can be
+/// stored.
+pub fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+                   (hash ^ u64::from(*b)).wrapping_mul(0x0000_0100_0000_01b3)
+               })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        state::{hash, Error, Reader, Writer},
+        Model,
+    };
+
+    #[test]
+    fn header() {
+        let mut writer = Writer::new(Model::Dmg);
+        writer.u16(0x1234);
+        writer.bool(true);
+        let data = writer.into_bytes();
+
+        let mut reader = Reader::new(&data, Model::Dmg).unwrap();
+        assert_eq!(Ok(0x1234), reader.u16());
+        assert_eq!(Ok(true), reader.bool());
+        assert_eq!(Err(Error::UnexpectedEof), reader.u8());
+
+        assert_eq!(Some(Error::Model), Reader::new(&data, Model::Cgb).err());
+        assert_eq!(Some(Error::InvalidHeader),
+                   Reader::new(&data[1..], Model::Dmg).err());
+    }
+
+    #[test]
+    fn fnv() {
+        assert_eq!(0xcbf2_9ce4_8422_2325, hash(&[]));
+        assert_eq!(0xaf63_dc4c_8601_ec8c, hash(b"a"));
+    }
+}
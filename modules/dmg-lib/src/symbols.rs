@@ -0,0 +1,228 @@
+//! Debug symbols.
+//!
+//! Loads the symbol (`.sym`) and map (`.map`) files written by the RGBDS
+//! linker, to show labels instead of addresses in the debugging tools.
+//!
+//! ```
+//! use dmg_lib::symbols::Symbols;
+//!
+//! let mut symbols = Symbols::new();
+//! symbols.parse_sym("00:0150 Main\n00:0153 Main.loop\n").unwrap();
+//!
+//! assert_eq!("Main.loop+2", symbols.format(0, 0x0155));
+//! assert_eq!(Some(0x0150), symbols.get("Main").map(|s| s.addr));
+//! ```
+use crate::{
+    apu::device::Audio, cartridge::Cartridge, debug::Breakpoint, device::Device, mmu::Mmu,
+    ppu::Video,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+};
+
+/// Error parsing a symbol or map file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Error {
+    /// Invalid line (1-based line number).
+    Syntax(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Syntax(line) => write!(f, "Syntax error in symbol file (line {})", line),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A label.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Symbol {
+    pub bank: usize,
+    pub addr: u16,
+    pub name: String,
+}
+
+impl Symbol {
+    /// Breakpoint on the label.
+    pub fn breakpoint(&self) -> Breakpoint {
+        Breakpoint::new(self.addr).bank(self.bank)
+    }
+}
+
+/// Table of labels, indexed by address and by name.
+#[derive(Debug, Default, Clone)]
+pub struct Symbols {
+    // (bank, address) -> name
+    addrs: BTreeMap<(usize, u16), String>,
+    // name -> (bank, address)
+    names: HashMap<String, (usize, u16)>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Add a label. When an address has more than one label, the first one
+    /// added is the one used to resolve it.
+    pub fn insert(&mut self, bank: usize, addr: u16, name: &str) {
+        self.addrs
+            .entry((bank, addr))
+            .or_insert_with(|| name.to_string());
+        self.names.insert(name.to_string(), (bank, addr));
+    }
+
+    /// Load the labels of a symbol file. Each line has the bank, the address
+    /// and the name of a label (`01:4000 Label`). Comments start with `;`.
+    pub fn parse_sym(&mut self, text: &str) -> Result<(), Error> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = Error::Syntax(i + 1);
+            let (location, name) = line.split_once(char::is_whitespace).ok_or(error)?;
+            let (bank, addr) = location.split_once(':').ok_or(error)?;
+            let bank = usize::from_str_radix(bank, 16).map_err(|_| error)?;
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| error)?;
+            self.insert(bank, addr, name.trim());
+        }
+        Ok(())
+    }
+
+    /// Load the labels of a map file. Only the bank headers (`ROMX bank #1:`)
+    /// and the symbol lines (`$4000 = Label`) are used, the rest is ignored.
+    pub fn parse_map(&mut self, text: &str) -> Result<(), Error> {
+        let mut bank = 0;
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let error = Error::Syntax(i + 1);
+            if let Some(header) = line.strip_suffix(':') {
+                if let Some((_, n)) = header.split_once(" bank #") {
+                    bank = n.parse().map_err(|_| error)?;
+                }
+            } else if let Some(symbol) = line.strip_prefix('$') {
+                let (addr, name) = symbol.split_once('=').ok_or(error)?;
+                let addr = u16::from_str_radix(addr.trim(), 16).map_err(|_| error)?;
+                self.insert(bank, addr, name.trim());
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the label with the given name.
+    pub fn get(&self, name: &str) -> Option<Symbol> {
+        self.names.get(name).map(|&(bank, addr)| Symbol { bank,
+                                                          addr,
+                                                          name: name.to_string() })
+    }
+
+    /// Return the label at the given address, if any.
+    pub fn label(&self, bank: usize, addr: u16) -> Option<&str> {
+        self.addrs.get(&(bank, addr)).map(String::as_str)
+    }
+
+    /// Return the closest label at or before the given address, within the
+    /// same memory region (ROM bank, WRAM, HRAM...), and the offset from it.
+    pub fn lookup(&self, bank: usize, addr: u16) -> Option<(&str, u16)> {
+        let start = region(addr);
+        self.addrs
+            .range((bank, start)..=(bank, addr))
+            .next_back()
+            .map(|((_, label), name)| (name.as_str(), addr - label))
+    }
+
+    /// Format an address as `Label+offset`, or as `$XXXX` if there is no
+    /// label for it.
+    pub fn format(&self, bank: usize, addr: u16) -> String {
+        match self.lookup(bank, addr) {
+            Some((name, 0)) => name.to_string(),
+            Some((name, offset)) => format!("{}+{}", name, offset),
+            None => format!("${:04x}", addr),
+        }
+    }
+}
+
+// Start of the memory region of the address.
+fn region(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x3fff => 0x0000,
+        0x4000..=0x7fff => 0x4000,
+        0x8000..=0x9fff => 0x8000,
+        0xa000..=0xbfff => 0xa000,
+        0xc000..=0xcfff => 0xc000,
+        0xd000..=0xdfff => 0xd000,
+        0xe000..=0xfdff => 0xe000,
+        0xfe00..=0xfeff => 0xfe00,
+        0xff00..=0xff7f => 0xff00,
+        _ => 0xff80,
+    }
+}
+
+/// Bank mapped at the given address (the ROM bank in 4000-7FFF, the WRAM bank
+/// in D000-DFFF), as used by the symbol files. The rest of the addresses are
+/// in bank 0.
+pub fn bank<C: Cartridge, V: Video, D: Audio>(mmu: &Mmu<C, V, D>, addr: u16) -> usize {
+    match addr {
+        0x4000..=0x7fff => mmu.cartridge().rom_bank(),
+        0xd000..=0xdfff => usize::from(mmu.wram().read(0xff70) & 0x7).max(1),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::symbols::{Error, Symbols};
+
+    #[test]
+    fn sym() {
+        let mut symbols = Symbols::new();
+        let text = "; File generated by rgblink\n\
+                    00:0150 Main\n\
+                    00:0153 Main.loop\n\
+                    01:4000 Banked\n\
+                    00:c000 wCounter\n";
+        symbols.parse_sym(text).unwrap();
+        assert_eq!(4, symbols.len());
+
+        assert_eq!(Some(("Main.loop", 0)), symbols.lookup(0, 0x0153));
+        assert_eq!(Some(("Banked", 0x10)), symbols.lookup(1, 0x4010));
+        assert_eq!(None, symbols.lookup(2, 0x4010));
+        assert_eq!(Some("wCounter"), symbols.label(0, 0xc000));
+        // different region
+        assert_eq!("$8000", symbols.format(0, 0x8000));
+
+        let banked = symbols.get("Banked").unwrap();
+        assert_eq!((1, 0x4000), (banked.bank, banked.addr));
+        assert_eq!(Some(1), banked.breakpoint().bank);
+
+        assert_eq!(Err(Error::Syntax(2)), symbols.parse_sym("\n00:zz Foo"));
+    }
+
+    #[test]
+    fn map() {
+        let text = "ROM0 bank #0:\n\
+                    \tSECTION: $0000-$00ff ($0100 bytes) [\"Header\"]\n\
+                    \t         $0150 = Main\n\
+                    \tEMPTY: $0200 bytes\n\
+                    ROMX bank #3:\n\
+                    \tSECTION: $4000-$40ff ($0100 bytes) [\"Code\"]\n\
+                    \t         $4020 = Routine\n";
+        let mut symbols = Symbols::new();
+        symbols.parse_map(text).unwrap();
+        assert_eq!(Some("Main"), symbols.label(0, 0x0150));
+        assert_eq!(Some("Routine"), symbols.label(3, 0x4020));
+    }
+}
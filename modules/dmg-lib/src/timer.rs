@@ -1,4 +1,10 @@
-use crate::{clock::Clock, device::Device, interrupt::Flag, CLOCK};
+use crate::{
+    clock::Clock,
+    device::Device,
+    interrupt::Flag,
+    state::{Error, Reader, State, Writer},
+    CLOCK,
+};
 
 /// DMG timer emulation.
 pub struct Timer {
@@ -32,6 +38,12 @@ impl Timer {
         }
     }
 
+    // Writing to DIV resets it, so the initial (post boot) value must be set
+    // directly.
+    pub(crate) fn set_div(&mut self, div: u8) {
+        self.div = div;
+    }
+
     pub(crate) fn take_timer_int(&mut self) -> Option<Flag> {
         self.tima_int.take()
     }
@@ -90,5 +102,32 @@ impl Device for Timer {
     }
 }
 
+impl State for Timer {
+    fn save_state(&self, state: &mut Writer) {
+        state.bytes(&[self.div, self.tima, self.tma, self.tac]);
+        self.div_clock.save_state(state);
+        self.tima_clock.save_state(state);
+        state.bool(self.tima_int.is_some());
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        let mut data = [0; 4];
+        state.bytes(&mut data)?;
+        let [div, tima, tma, tac] = data;
+        self.div = div;
+        self.tima = tima;
+        self.tma = tma;
+        self.tac = tac;
+        self.div_clock.load_state(state)?;
+        self.tima_clock.load_state(state)?;
+        self.tima_int = if state.bool()? {
+            Some(Flag::Timer)
+        } else {
+            None
+        };
+        Ok(())
+    }
+}
+
 #[cfg(test)]
 mod tests {}
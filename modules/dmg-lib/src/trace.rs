@@ -0,0 +1,328 @@
+//! Instruction traces.
+//!
+//! Logs the state of the CPU before each executed instruction, in the format of
+//! [gameboy-doctor], so traces can be compared against other emulators:
+//!
+//! ```text
+//! A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//! ```
+//!
+//! The ROM bank, the elapsed cycles, the LY register, the label of PC and the
+//! disassembled instruction can be appended to each line, at the cost of
+//! compatibility with the tool.
+//!
+//! [gameboy-doctor]: https://github.com/robert/gameboy-doctor
+use crate::{cpu::registers::Registers, device::Device, disasm, symbols::Symbols};
+use std::{
+    io::{self, Write},
+    ops::RangeInclusive,
+};
+
+// Bytes at PC, to disassemble the instruction.
+struct PcMem {
+    pc: u16,
+    bytes: [u8; 4],
+}
+
+impl Device for PcMem {
+    fn read(&self, addr: u16) -> u8 {
+        let offset = addr.wrapping_sub(self.pc) as usize;
+        self.bytes.get(offset).copied().unwrap_or(0xff)
+    }
+
+    fn write(&mut self, _: u16, _: u8) {}
+}
+
+/// Trace sink (see [`Mmu::start_trace`]).
+///
+/// [`Mmu::start_trace`]: #
+pub struct Trace {
+    out: Box<dyn Write>,
+    bank: bool,
+    cycles: bool,
+    ly: bool,
+    disasm: bool,
+    symbols: Option<Symbols>,
+    pc: Option<RangeInclusive<u16>>,
+    only_bank: Option<usize>,
+    // cycles of the 4MHz clock since the trace started
+    elapsed: u64,
+    // the first error writing the trace (stops the logging)
+    error: Option<io::Error>,
+}
+
+impl Trace {
+    /// Trace in the plain gameboy-doctor format into the given output.
+    pub fn new<W: Write + 'static>(out: W) -> Self {
+        Self { out: Box::new(out),
+               bank: false,
+               cycles: false,
+               ly: false,
+               disasm: false,
+               symbols: None,
+               pc: None,
+               only_bank: None,
+               elapsed: 0,
+               error: None }
+    }
+
+    /// Append the ROM bank mapped at PC (`BANK:01`).
+    pub fn bank(mut self) -> Self {
+        self.bank = true;
+        self
+    }
+
+    /// Append the cycles of the 4MHz clock elapsed since the trace started
+    /// (`CYCLES:1234`).
+    pub fn cycles(mut self) -> Self {
+        self.cycles = true;
+        self
+    }
+
+    /// Append the LY register (`LY:90`).
+    pub fn ly(mut self) -> Self {
+        self.ly = true;
+        self
+    }
+
+    /// Append the disassembled instruction (`; ld a, b`).
+    pub fn disasm(mut self) -> Self {
+        self.disasm = true;
+        self
+    }
+
+    /// Append the label of PC (`LABEL:Main.loop+2`), and use labels for the
+    /// addresses of the disassembled instruction.
+    pub fn symbols(mut self, symbols: Symbols) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    /// Only log the instructions with a PC in the given range.
+    pub fn pc_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.pc = Some(range);
+        self
+    }
+
+    /// Only log the instructions in the given ROM bank (bank 0 for PCs outside
+    /// of 4000-7FFF).
+    pub fn only_bank(mut self, bank: usize) -> Self {
+        self.only_bank = Some(bank);
+        self
+    }
+
+    /// Flush the output and return it, or the first error writing the trace.
+    pub fn finish(mut self) -> io::Result<Box<dyn Write>> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    // Called by the MMU after the CPU is stepped.
+    pub(crate) fn step(&mut self, cycles: u64) {
+        self.elapsed += cycles;
+    }
+
+    // Called by the MMU before an instruction is executed. `pcmem` holds the
+    // bytes at PC, and `banks` the mapped ROM & WRAM banks.
+    pub(crate) fn log(&mut self, reg: &Registers, pcmem: [u8; 4], banks: (usize, usize), ly: u8) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Some(range) = &self.pc {
+            if !range.contains(&reg.pc) {
+                return;
+            }
+        }
+        // the ROM bank PC belongs to
+        let bank = match reg.pc {
+            0x4000..=0x7fff => banks.0,
+            _ => 0,
+        };
+        if self.only_bank.is_some_and(|only| only != bank) {
+            return;
+        }
+        if let Err(err) = self.write(reg, pcmem, bank, banks, ly) {
+            self.error = Some(err);
+        }
+    }
+
+    fn write(&mut self,
+             reg: &Registers,
+             pcmem: [u8; 4],
+             bank: usize,
+             (rom_bank, wram_bank): (usize, usize),
+             ly: u8)
+             -> io::Result<()> {
+        write!(self.out,
+               "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} \
+                PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+               reg.a,
+               reg.f,
+               reg.b,
+               reg.c,
+               reg.d,
+               reg.e,
+               reg.h,
+               reg.l,
+               reg.sp,
+               reg.pc,
+               pcmem[0],
+               pcmem[1],
+               pcmem[2],
+               pcmem[3])?;
+        if self.bank {
+            write!(self.out, " BANK:{:02X}", bank)?;
+        }
+        if self.ly {
+            write!(self.out, " LY:{:02X}", ly)?;
+        }
+        if self.cycles {
+            write!(self.out, " CYCLES:{}", self.elapsed)?;
+        }
+        if let Some(symbols) = &self.symbols {
+            let label_bank = match reg.pc {
+                0xd000..=0xdfff => wram_bank,
+                _ => bank,
+            };
+            write!(self.out, " LABEL:{}", symbols.format(label_bank, reg.pc))?;
+        }
+        if self.disasm {
+            let mem = PcMem { pc: reg.pc,
+                              bytes: pcmem };
+            let instr = disasm::decode(&mem, reg.pc);
+            match &self.symbols {
+                Some(symbols) => write!(self.out,
+                                        " ; {}",
+                                        instr.with_symbols(symbols, rom_bank, wram_bank))?,
+                None => write!(self.out, " ; {}", instr)?,
+            }
+        }
+        writeln!(self.out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{cartridge::Rom, symbols::Symbols, trace::Trace, Builder};
+    use std::{
+        cell::RefCell,
+        io::{self, Write},
+        rc::Rc,
+    };
+
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn doctor() {
+        let mut rom = vec![0; 0x8000];
+        // 0100: LD HL,$C000
+        // 0103: INC A
+        // 0104: LD (HL),A
+        // 0105: INC L
+        // 0106: JR $0103
+        rom[0x0100..0x0108].copy_from_slice(&[0x21, 0x00, 0xc0, 0x3c, 0x77, 0x2c, 0x18, 0xfb]);
+        let mut dmg = Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                                        .build();
+
+        let out = Shared::default();
+        let trace = Trace::new(out.clone()).pc_range(0x0100..=0x0104)
+                                           .cycles()
+                                           .disasm();
+        dmg.mmu_mut().start_trace(trace);
+        for _ in 0..7 {
+            dmg.step_instruction();
+        }
+        dmg.mmu_mut().stop_trace().unwrap().finish().unwrap();
+
+        let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(vec!["A:01 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 \
+                         PCMEM:21,00,C0,3C CYCLES:0 ; ld hl, $c000",
+                        "A:01 F:80 B:00 C:13 D:00 E:D8 H:C0 L:00 SP:FFFE PC:0103 \
+                         PCMEM:3C,77,2C,18 CYCLES:12 ; inc a",
+                        "A:02 F:00 B:00 C:13 D:00 E:D8 H:C0 L:00 SP:FFFE PC:0104 \
+                         PCMEM:77,2C,18,FB CYCLES:16 ; ld [hl], a",
+                        "A:02 F:00 B:00 C:13 D:00 E:D8 H:C0 L:01 SP:FFFE PC:0103 \
+                         PCMEM:3C,77,2C,18 CYCLES:40 ; inc a"],
+                   lines[..4]);
+    }
+
+    #[test]
+    fn symbols() {
+        let mut rom = vec![0; 0x8000];
+        // 0100: JP $0150
+        // 0150: INC A
+        // 0151: JR $0150
+        rom[0x0100..0x0103].copy_from_slice(&[0xc3, 0x50, 0x01]);
+        rom[0x0150..0x0153].copy_from_slice(&[0x3c, 0x18, 0xfd]);
+        let mut dmg = Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                                        .build();
+
+        let mut symbols = Symbols::new();
+        symbols.parse_sym("00:0100 Entry\n00:0150 Main\n").unwrap();
+        let out = Shared::default();
+        dmg.mmu_mut()
+           .start_trace(Trace::new(out.clone()).symbols(symbols).disasm());
+        for _ in 0..3 {
+            dmg.step_instruction();
+        }
+        dmg.mmu_mut().stop_trace().unwrap().finish().unwrap();
+
+        let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let lines: Vec<_> = text.lines()
+                                .map(|line| line.split(" PCMEM:").nth(1).unwrap())
+                                .collect();
+        assert_eq!(vec!["C3,50,01,00 LABEL:Entry ; jp Main",
+                        "3C,18,FD,00 LABEL:Main ; inc a",
+                        "18,FD,00,00 LABEL:Main+1 ; jr Main"],
+                   lines);
+    }
+
+    #[test]
+    fn wram_bank() {
+        let mut rom = vec![0u8; 0x8000];
+        // 0100: LD A,$02
+        // 0102: LDH ($70),A
+        // 0104: LD HL,$D000
+        // 0107: LD (HL),$18
+        // 0109: INC HL
+        // 010A: LD (HL),$FE
+        // 010C: JP $D000
+        rom[0x0100..0x010f].copy_from_slice(&[0x3e, 0x02, 0xe0, 0x70, 0x21, 0x00, 0xd0, 0x36,
+                                              0x18, 0x23, 0x36, 0xfe, 0xc3, 0x00, 0xd0]);
+        rom[0x0143] = 0x80;
+        let mut dmg = Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                                        .build();
+
+        let mut symbols = Symbols::new();
+        symbols.parse_sym("01:d000 wBank1\n02:d000 wBank2\n")
+               .unwrap();
+        let out = Shared::default();
+        dmg.mmu_mut()
+           .start_trace(Trace::new(out.clone()).symbols(symbols).disasm());
+        for _ in 0..8 {
+            dmg.step_instruction();
+        }
+        dmg.mmu_mut().stop_trace().unwrap().finish().unwrap();
+
+        // labels & operands are looked up in the WRAM bank selected by SVBK
+        let text = String::from_utf8(out.0.borrow().clone()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines[6].ends_with(" ; jp wBank2"));
+        assert!(lines[7].ends_with(" LABEL:wBank2 ; jr wBank2"));
+    }
+}
@@ -0,0 +1,250 @@
+//! VGM logging of APU register writes.
+//!
+//! Writes to the sound registers (FF10-FF3F) are recorded along with the time
+//! they happened at, and exported as a VGM (version 1.61) file using the Game
+//! Boy DMG chip commands.
+//!
+//! ```no_run
+//! # let mut dmg = dmg_lib::GameBoy::default();
+//! dmg.mmu_mut().start_vgm_log();
+//! for _ in 0..60 * 60 {
+//!     dmg.emulate_frame();
+//! }
+//! let vgm = dmg.mmu_mut().stop_vgm_log().unwrap();
+//! vgm.export(std::fs::File::create("music.vgm").unwrap()).unwrap();
+//! ```
+use crate::CLOCK;
+use std::io::{self, Write};
+
+const VERSION: u32 = 0x0000_0161;
+const HEADER_SIZE: usize = 0x100;
+const SAMPLE_RATE: u64 = 44100;
+
+// VGM commands
+const CMD_DMG_WRITE: u8 = 0xb3;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_735: u8 = 0x62;
+const CMD_WAIT_882: u8 = 0x63;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_END: u8 = 0x66;
+
+/// An APU register write.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RegWrite {
+    /// Cycle (4MHz clock) since the logger was started.
+    pub cycle: u64,
+    /// Register address (FF10-FF3F).
+    pub addr: u16,
+    /// Written value.
+    pub data: u8,
+}
+
+/// Log of APU register writes.
+#[derive(Debug, Clone)]
+pub struct VgmLog {
+    cycles: u64,
+    loop_cycle: Option<u64>,
+    title: String,
+    init: Vec<(u16, u8)>,
+    writes: Vec<RegWrite>,
+}
+
+impl VgmLog {
+    // The initial state of the registers is stored so the log can be played back
+    // from any point of the emulation.
+    pub(crate) fn new(title: String, registers: &[u8; 0x30]) -> Self {
+        // power on the APU, then restore the rest of the registers (without
+        // triggering the channels)
+        let mut init = vec![(0xff26, registers[0x16] & 0x80)];
+        for (i, data) in registers.iter().copied().enumerate() {
+            let addr = 0xff10 + i as u16;
+            match addr {
+                0xff15 | 0xff1f | 0xff26..=0xff2f => {}
+                0xff14 | 0xff19 | 0xff1e | 0xff23 => init.push((addr, data & 0x7f)),
+                _ => init.push((addr, data)),
+            }
+        }
+        Self { cycles: 0,
+               loop_cycle: None,
+               title,
+               init,
+               writes: Vec::new() }
+    }
+
+    pub(crate) fn step(&mut self, cycles: u64) {
+        self.cycles += cycles;
+    }
+
+    pub(crate) fn log(&mut self, addr: u16, data: u8) {
+        self.writes.push(RegWrite { cycle: self.cycles,
+                                  addr,
+                                  data });
+    }
+
+    /// Mark the current point of the log as the loop point.
+    pub fn mark_loop(&mut self) {
+        self.loop_cycle = Some(self.cycles);
+    }
+
+    /// Returns the logged writes.
+    pub fn writes(&self) -> &[RegWrite] {
+        &self.writes
+    }
+
+    /// Returns the length of the log in cycles of the 4MHz clock.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Export the log as a VGM file. The GD3 tag is filled from the cartridge
+    /// title.
+    pub fn export<W: Write>(&self, mut write: W) -> io::Result<()> {
+        let mut data = Vec::new();
+        let mut sample = 0;
+        let mut loop_offset = None;
+
+        for (addr, data_) in &self.init {
+            data.extend_from_slice(&[CMD_DMG_WRITE, (addr - 0xff10) as u8, *data_]);
+        }
+        for w in &self.writes {
+            if let Some(loop_cycle) = self.loop_cycle {
+                if loop_offset.is_none() && w.cycle >= loop_cycle {
+                    wait(&mut data, &mut sample, to_samples(loop_cycle));
+                    loop_offset = Some((data.len(), sample));
+                }
+            }
+            wait(&mut data, &mut sample, to_samples(w.cycle));
+            data.extend_from_slice(&[CMD_DMG_WRITE, (w.addr - 0xff10) as u8, w.data]);
+        }
+        if let (None, Some(loop_cycle)) = (loop_offset, self.loop_cycle) {
+            wait(&mut data, &mut sample, to_samples(loop_cycle));
+            loop_offset = Some((data.len(), sample));
+        }
+        wait(&mut data, &mut sample, to_samples(self.cycles));
+        data.push(CMD_END);
+
+        let gd3 = self.gd3();
+        let gd3_offset = HEADER_SIZE + data.len();
+        let eof = gd3_offset + gd3.len();
+
+        let mut header = [0u8; HEADER_SIZE];
+        header[..4].copy_from_slice(b"Vgm ");
+        let mut set = |offset: usize, value: u32| {
+            header[offset..offset + 4].copy_from_slice(&value.to_le_bytes())
+        };
+        set(0x04, (eof - 0x04) as u32);
+        set(0x08, VERSION);
+        set(0x14, (gd3_offset - 0x14) as u32);
+        set(0x18, sample as u32);
+        if let Some((offset, loop_sample)) = loop_offset {
+            set(0x1c, (HEADER_SIZE + offset - 0x1c) as u32);
+            set(0x20, (sample - loop_sample) as u32);
+        }
+        set(0x34, (HEADER_SIZE - 0x34) as u32);
+        set(0x80, CLOCK as u32);
+
+        write.write_all(&header)?;
+        write.write_all(&data)?;
+        write.write_all(&gd3)?;
+        Ok(())
+    }
+
+    // Track name, game name, system name and author (english and japanese), release
+    // date, VGM creator and notes. Strings are UTF-16 and null terminated.
+    fn gd3(&self) -> Vec<u8> {
+        let strings = ["",
+                       "",
+                       &self.title,
+                       "",
+                       "Nintendo Game Boy",
+                       "",
+                       "",
+                       "",
+                       "",
+                       "dmg-lib",
+                       ""];
+        let mut body = Vec::new();
+        for string in strings.iter() {
+            for c in string.encode_utf16().chain(Some(0)) {
+                body.extend_from_slice(&c.to_le_bytes());
+            }
+        }
+        let mut gd3 = Vec::with_capacity(12 + body.len());
+        gd3.extend_from_slice(b"Gd3 ");
+        gd3.extend_from_slice(&0x0000_0100u32.to_le_bytes());
+        gd3.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        gd3.extend_from_slice(&body);
+        gd3
+    }
+}
+
+fn to_samples(cycles: u64) -> u64 {
+    cycles * SAMPLE_RATE / CLOCK
+}
+
+// Emit wait commands until the given sample.
+fn wait(data: &mut Vec<u8>, sample: &mut u64, target: u64) {
+    while *sample < target {
+        let n = (target - *sample).min(0xffff);
+        match n {
+            735 => data.push(CMD_WAIT_735),
+            882 => data.push(CMD_WAIT_882),
+            1..=16 => data.push(CMD_WAIT_SHORT | (n - 1) as u8),
+            _ => {
+                data.push(CMD_WAIT);
+                data.extend_from_slice(&(n as u16).to_le_bytes());
+            }
+        }
+        *sample += n;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{vgm::VgmLog, CLOCK};
+
+    fn u32_at(vgm: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([vgm[offset], vgm[offset + 1], vgm[offset + 2], vgm[offset + 3]])
+    }
+
+    #[test]
+    fn export() {
+        let mut log = VgmLog::new("TETRIS".to_string(), &[0; 0x30]);
+        log.log(0xff26, 0x80);
+        log.step(CLOCK / 60 + 1);
+        log.mark_loop();
+        log.log(0xff12, 0xf0);
+        log.step(CLOCK / 100);
+        log.log(0xff14, 0x87);
+        log.step(CLOCK);
+
+        let mut vgm = Vec::new();
+        log.export(&mut vgm).unwrap();
+
+        assert_eq!(b"Vgm ", &vgm[..4]);
+        assert_eq!(vgm.len() as u32 - 4, u32_at(&vgm, 0x04));
+        assert_eq!(0x161, u32_at(&vgm, 0x08));
+        assert_eq!(4_194_304, u32_at(&vgm, 0x80));
+        assert_eq!(0xcc, u32_at(&vgm, 0x34));
+
+        let total = u32_at(&vgm, 0x18);
+        assert_eq!((CLOCK / 60 + 1 + CLOCK / 100 + CLOCK) * 44100 / CLOCK, total as u64);
+        assert_eq!(total - 735, u32_at(&vgm, 0x20));
+
+        // skip initial register dump (NR52 + 36 registers)
+        let data = &vgm[0x100 + 3 * 37..];
+        assert_eq!(&[0xb3, 0x16, 0x80, 0x62], &data[..4]);
+
+        // loop point lands on the NR12 write
+        let loop_offset = 0x1c + u32_at(&vgm, 0x1c) as usize;
+        assert_eq!(&[0xb3, 0x02, 0xf0], &vgm[loop_offset..loop_offset + 3]);
+
+        // 10ms = 441 samples
+        assert_eq!(&[0x61, 0xb9, 0x01, 0xb3, 0x04, 0x87], &data[7..13]);
+
+        let gd3 = 0x14 + u32_at(&vgm, 0x14) as usize;
+        assert_eq!(b"Gd3 ", &vgm[gd3..gd3 + 4]);
+        // empty track names, followed by the game name
+        assert_eq!(&[0, 0, 0, 0, b'T', 0, b'E', 0], &vgm[gd3 + 12..gd3 + 20]);
+    }
+}
@@ -1,4 +1,7 @@
-use crate::device::Device;
+use crate::{
+    device::Device,
+    state::{Error, Reader, State, Writer},
+};
 
 const SIZE: usize = 0x2000;
 
@@ -65,3 +68,20 @@ impl Device for VRam {
         }
     }
 }
+
+impl State for VRam {
+    fn save_state(&self, state: &mut Writer) {
+        state.u8(self.vbk);
+        for bank in self.vram.iter() {
+            state.bytes(bank);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.vbk = state.u8()?;
+        for bank in self.vram.iter_mut() {
+            state.bytes(bank)?;
+        }
+        Ok(())
+    }
+}
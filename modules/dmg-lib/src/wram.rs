@@ -1,4 +1,7 @@
-use crate::device::Device;
+use crate::{
+    device::Device,
+    state::{Error, Reader, State, Writer},
+};
 
 const SIZE: usize = 0x1000;
 
@@ -79,3 +82,20 @@ impl Device for WRam {
         }
     }
 }
+
+impl State for WRam {
+    fn save_state(&self, state: &mut Writer) {
+        state.u8(self.svbk);
+        for bank in self.wram.iter() {
+            state.bytes(bank);
+        }
+    }
+
+    fn load_state(&mut self, state: &mut Reader) -> Result<(), Error> {
+        self.svbk = state.u8()?;
+        for bank in self.wram.iter_mut() {
+            state.bytes(bank)?;
+        }
+        Ok(())
+    }
+}
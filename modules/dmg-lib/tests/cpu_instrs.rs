@@ -1,27 +1,25 @@
-use dmg_backend_headless::ppu::{Buffer, HeadlessVideo};
-use dmg_lib::{cartridge::Mbc3, Builder};
-use std::time::{Duration, Instant};
+use dmg_backend_headless::HeadlessVideo;
+use dmg_lib::{cartridge, Builder};
+use std::{fs, path::Path};
+
+// Emulated time for all the tests to pass (~67 seconds).
+const FRAMES: u64 = 4000;
 
-#[cfg(todo)]
 #[test]
+#[ignore = "requires Blargg's tests/cpu_instrs.gb"]
 fn cpu_instrs() {
     static PPU: &[u8] = include_bytes!("cpu_instrs.bin");
-    static ROM: &[u8] = include_bytes!("cpu_instrs.gb");
 
-    let mut dmg = Builder::default().cartridge(Mbc3::new(ROM.into()))
+    // Blargg's test ROM isn't distributed with the sources
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/cpu_instrs.gb");
+    let rom = fs::read(&path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err));
+
+    let mut dmg = Builder::default().cartridge(cartridge::from_bytes(&rom).unwrap())
                                     .video(HeadlessVideo::new())
                                     .build();
-
-    let time = Instant::now();
-    loop {
-        if time.elapsed() > Duration::new(16, 0) {
-            break;
-        }
+    for _ in 0..FRAMES {
         dmg.emulate_frame();
     }
 
-    let video = dmg.mmu().ppu().video();
-    let buf = unsafe { std::slice::from_raw_parts(video.as_ptr(), 160 * 144 * 3) };
-
-    assert_eq!(PPU, buf)
+    assert_eq!(PPU, &dmg.mmu().ppu().video().to_rgb()[..]);
 }
@@ -0,0 +1,434 @@
+//! Scripting of the emulator with [Rhai].
+//!
+//! The top level statements of the script run once, when it is loaded. Then
+//! the following functions are called, if the script defines them:
+//!
+//! - `on_frame()` at the start of every frame.
+//! - `on_break(id, pc)` when a breakpoint or a watchpoint is hit.
+//!
+//! State can be kept between calls in the properties of `this` (an object
+//! map). The script controls the emulator through these functions:
+//!
+//! | Function | |
+//! |---|---|
+//! | `frame()` | Number of emulated frames |
+//! | `read(addr)`, `read16(addr)` | Read memory (16-bit values are little endian) |
+//! | `write(addr, value)` | Write memory |
+//! | `reg(name)` | Read a CPU register (`"a"`, `"hl"`, `"pc"`...) |
+//! | `press(key)`, `release(key)` | Press or release a key (`"a"`, `"start"`, `"up"`...) |
+//! | `breakpoint(addr)` | Add a breakpoint, and return its id |
+//! | `watch(start, end, mode)` | Add a watchpoint (mode `"r"`, `"w"`, `"rw"`...), and return its id |
+//! | `remove_breakpoint(id)` | Remove a breakpoint or a watchpoint |
+//! | `save_state(slot)`, `load_state(slot)` | Save or load the state to/from a slot (in memory) |
+//! | `screenshot(path)` | Write the last frame to a PNG (`.png`) or PPM file |
+//! | `exit()` | Stop the script (and the emulation) |
+//!
+//! ```rhai
+//! let id = watch(0xc0a0, 0xc0a0, "w");
+//!
+//! fn on_frame() {
+//!     if frame() == 60 {
+//!         press("start");
+//!     }
+//! }
+//!
+//! fn on_break(id, pc) {
+//!     print(`Lives written from ${pc}: ${read(0xc0a0)}`);
+//! }
+//! ```
+//!
+//! [Rhai]: https://rhai.rs
+use dmg_backend_headless::HeadlessVideo;
+use dmg_lib::{
+    apu::device::Audio,
+    cartridge::Cartridge,
+    debug::{Breakpoint, Debugger, Watchpoint},
+    joypad::{Btn, Dir, Key},
+    ppu::Video,
+    GameBoy,
+};
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, ParseError, Scope, AST};
+use std::{
+    cell::{Ref, RefCell, RefMut},
+    collections::HashMap,
+    convert::TryFrom,
+    fmt, fs, io,
+    path::Path,
+    rc::Rc,
+};
+
+type FnResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Error loading or running a script.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// Syntax or runtime error.
+    Script(Box<EvalAltResult>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Script(err) => write!(f, "Script error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<Box<EvalAltResult>> for Error {
+    fn from(err: Box<EvalAltResult>) -> Self {
+        Error::Script(err)
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(err: ParseError) -> Self {
+        Error::Script(err.into())
+    }
+}
+
+// State shared with the functions registered in the engine.
+struct State<C: Cartridge, V: Video, D: Audio> {
+    dmg: GameBoy<C, HeadlessVideo<V>, D>,
+    frame: u64,
+    slots: HashMap<i64, Vec<u8>>,
+    exit: bool,
+}
+
+/// A loaded script, driving the emulation.
+pub struct Script<C: Cartridge, V: Video, D: Audio> {
+    engine: Engine,
+    ast: AST,
+    scope: Scope<'static>,
+    this: Dynamic,
+    state: Rc<RefCell<State<C, V, D>>>,
+}
+
+impl<C, V, D> Script<C, V, D>
+    where C: Cartridge + 'static,
+          V: Video + 'static,
+          D: Audio + 'static
+{
+    /// Compile the script and run its top level statements.
+    pub fn new(dmg: GameBoy<C, HeadlessVideo<V>, D>, source: &str) -> Result<Self, Error> {
+        let state = Rc::new(RefCell::new(State { dmg,
+                                                 frame: 0,
+                                                 slots: HashMap::new(),
+                                                 exit: false }));
+        let mut engine = Engine::new();
+        register(&mut engine, &state);
+        let ast = engine.compile(source)?;
+        let mut scope = Scope::new();
+        engine.run_ast_with_scope(&mut scope, &ast)?;
+        Ok(Self { engine,
+                  ast,
+                  scope,
+                  this: Map::new().into(),
+                  state })
+    }
+
+    /// Load a script from a file (see [`Script::new`]).
+    ///
+    /// [`Script::new`]: #
+    pub fn from_file<P: AsRef<Path>>(dmg: GameBoy<C, HeadlessVideo<V>, D>,
+                                     path: P)
+                                     -> Result<Self, Error> {
+        Self::new(dmg, &fs::read_to_string(path)?)
+    }
+
+    pub fn emulator(&self) -> Ref<'_, GameBoy<C, HeadlessVideo<V>, D>> {
+        Ref::map(self.state.borrow(), |state| &state.dmg)
+    }
+
+    pub fn emulator_mut(&self) -> RefMut<'_, GameBoy<C, HeadlessVideo<V>, D>> {
+        RefMut::map(self.state.borrow_mut(), |state| &mut state.dmg)
+    }
+
+    /// Number of emulated frames.
+    pub fn frame(&self) -> u64 {
+        self.state.borrow().frame
+    }
+
+    /// Return `true` if the script has called `exit()`.
+    pub fn finished(&self) -> bool {
+        self.state.borrow().exit
+    }
+
+    /// Call `on_frame`, then emulate a frame, calling `on_break` on every
+    /// breakpoint or watchpoint hit. Does nothing once the script has finished.
+    pub fn emulate_frame(&mut self) -> Result<(), Error> {
+        if self.finished() {
+            return Ok(());
+        }
+        self.call("on_frame", ())?;
+        while !self.finished() {
+            let step = self.emulator_mut().emulate_frame();
+            if let Some(hit) = step.hit {
+                self.call("on_break", (hit.id as i64, i64::from(hit.pc)))?;
+            }
+            if step.frame {
+                self.state.borrow_mut().frame += 1;
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    // Call a function of the script, if defined.
+    fn call(&mut self, name: &str, args: impl FuncArgs) -> Result<(), Error> {
+        if !self.ast.iter_functions().any(|f| f.name == name) {
+            return Ok(());
+        }
+        let options = CallFnOptions::new().eval_ast(false)
+                                          .bind_this_ptr(&mut self.this);
+        let _: Dynamic =
+            self.engine
+                .call_fn_with_options(options, &mut self.scope, &self.ast, name, args)?;
+        Ok(())
+    }
+}
+
+fn to_addr(addr: i64) -> FnResult<u16> {
+    u16::try_from(addr).map_err(|_| format!("Invalid address: {}", addr).into())
+}
+
+fn to_byte(value: i64) -> FnResult<u8> {
+    u8::try_from(value).map_err(|_| format!("Invalid byte: {}", value).into())
+}
+
+fn to_key(key: &str) -> FnResult<Key> {
+    match key {
+        "a" => Ok(Key::Btn(Btn::A)),
+        "b" => Ok(Key::Btn(Btn::B)),
+        "start" => Ok(Key::Btn(Btn::Start)),
+        "select" => Ok(Key::Btn(Btn::Select)),
+        "up" => Ok(Key::Dir(Dir::Up)),
+        "down" => Ok(Key::Dir(Dir::Down)),
+        "left" => Ok(Key::Dir(Dir::Left)),
+        "right" => Ok(Key::Dir(Dir::Right)),
+        _ => Err(format!("Unknown key: {}", key).into()),
+    }
+}
+
+// Register the functions of the API.
+fn register<C, V, D>(engine: &mut Engine, state: &Rc<RefCell<State<C, V, D>>>)
+    where C: Cartridge + 'static,
+          V: Video + 'static,
+          D: Audio + 'static
+{
+    let s = state.clone();
+    engine.register_fn("frame", move || s.borrow().frame as i64);
+
+    let s = state.clone();
+    engine.register_fn("read", move |addr: i64| -> FnResult<i64> {
+              Ok(s.borrow().dmg.mmu().peek(to_addr(addr)?).into())
+          });
+    let s = state.clone();
+    engine.register_fn("read16", move |addr: i64| -> FnResult<i64> {
+              let addr = to_addr(addr)?;
+              let mmu = s.borrow();
+              let mmu = mmu.dmg.mmu();
+              Ok(u16::from_le_bytes([mmu.peek(addr), mmu.peek(addr.wrapping_add(1))]).into())
+          });
+    let s = state.clone();
+    engine.register_fn("write", move |addr: i64, value: i64| -> FnResult<()> {
+              use dmg_lib::device::Device;
+              let (addr, value) = (to_addr(addr)?, to_byte(value)?);
+              s.borrow_mut().dmg.mmu_mut().write(addr, value);
+              Ok(())
+          });
+
+    let s = state.clone();
+    engine.register_fn("reg", move |name: &str| -> FnResult<i64> {
+              let state = s.borrow();
+              let reg = state.dmg.cpu().reg();
+              let value = match name {
+                  "a" => reg.a.into(),
+                  "f" => reg.f.into(),
+                  "b" => reg.b.into(),
+                  "c" => reg.c.into(),
+                  "d" => reg.d.into(),
+                  "e" => reg.e.into(),
+                  "h" => reg.h.into(),
+                  "l" => reg.l.into(),
+                  "af" => reg.af(),
+                  "bc" => reg.bc(),
+                  "de" => reg.de(),
+                  "hl" => reg.hl(),
+                  "sp" => reg.sp,
+                  "pc" => reg.pc,
+                  _ => return Err(format!("Unknown register: {}", name).into()),
+              };
+              Ok(value.into())
+          });
+
+    let s = state.clone();
+    engine.register_fn("press", move |key: &str| -> FnResult<()> {
+              s.borrow_mut()
+               .dmg
+               .mmu_mut()
+               .joypad_mut()
+               .press(to_key(key)?);
+              Ok(())
+          });
+    let s = state.clone();
+    engine.register_fn("release", move |key: &str| -> FnResult<()> {
+              s.borrow_mut()
+               .dmg
+               .mmu_mut()
+               .joypad_mut()
+               .release(to_key(key)?);
+              Ok(())
+          });
+
+    let s = state.clone();
+    engine.register_fn("breakpoint", move |addr: i64| -> FnResult<i64> {
+              let breakpoint = Breakpoint::new(to_addr(addr)?);
+              Ok(debugger(&mut s.borrow_mut().dmg).add_breakpoint(breakpoint) as i64)
+          });
+    let s = state.clone();
+    engine.register_fn("watch",
+                       move |start: i64, end: i64, mode: &str| -> FnResult<i64> {
+                           let mut watchpoint = Watchpoint::new(to_addr(start)?..=to_addr(end)?);
+                           for c in mode.chars() {
+                               watchpoint =
+                                   match c {
+                                       'r' => watchpoint.read(),
+                                       'w' => watchpoint.write(),
+                                       'x' => watchpoint.execute(),
+                                       _ => return Err(format!("Invalid watchpoint mode: {}",
+                                                               mode).into()),
+                                   };
+                           }
+                           Ok(debugger(&mut s.borrow_mut().dmg).add_watchpoint(watchpoint) as i64)
+                       });
+    let s = state.clone();
+    engine.register_fn("remove_breakpoint", move |id: i64| {
+              let mut state = s.borrow_mut();
+              match state.dmg.mmu_mut().debugger_mut() {
+                  Some(debugger) if id >= 0 => debugger.remove(id as usize),
+                  _ => false,
+              }
+          });
+
+    let s = state.clone();
+    engine.register_fn("save_state", move |slot: i64| {
+              let mut state = s.borrow_mut();
+              let data = state.dmg.save_state();
+              state.slots.insert(slot, data);
+          });
+    let s = state.clone();
+    engine.register_fn("load_state", move |slot: i64| -> FnResult<bool> {
+              let mut state = s.borrow_mut();
+              let data = match state.slots.get(&slot) {
+                  Some(data) => data.clone(),
+                  None => return Ok(false),
+              };
+              state.dmg
+                   .load_state(&data)
+                   .map_err(|err| format!("Error loading state: {}", err))?;
+              Ok(true)
+          });
+
+    let s = state.clone();
+    engine.register_fn("screenshot", move |path: &str| -> FnResult<()> {
+              let state = s.borrow();
+              state.dmg
+                   .mmu()
+                   .ppu()
+                   .video()
+                   .save(path)
+                   .map_err(|err| err.to_string().into())
+          });
+
+    let s = state.clone();
+    engine.register_fn("exit", move || s.borrow_mut().exit = true);
+}
+
+// The debugger of the emulator, attached if needed.
+fn debugger<C: Cartridge, V: Video, D: Audio>(dmg: &mut GameBoy<C, V, D>) -> &mut Debugger {
+    if dmg.mmu().debugger().is_none() {
+        dmg.mmu_mut().attach_debugger(Debugger::new());
+    }
+    dmg.mmu_mut().debugger_mut().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Script;
+    use dmg_backend_headless::HeadlessVideo;
+    use dmg_lib::{cartridge::Rom, Builder};
+
+    #[test]
+    fn script() {
+        let mut rom = vec![0; 0x8000];
+        // 0100: LD HL,$C000
+        // 0103: INC A
+        // 0104: LD (HL),A
+        // 0105: INC L
+        // 0106: JR $0103
+        rom[0x0100..0x0108].copy_from_slice(&[0x21, 0x00, 0xc0, 0x3c, 0x77, 0x2c, 0x18, 0xfb]);
+        let dmg = Builder::default().cartridge(Rom::new(rom.into_boxed_slice()))
+                                    .video(HeadlessVideo::new())
+                                    .build();
+
+        let source = r#"
+            breakpoint(0x0103);
+
+            fn on_frame() {
+                switch frame() {
+                    1 => { write(0xd001, 1); save_state(0); }
+                    2 => { write(0xd001, 2); load_state(0); }
+                    3 => exit(),
+                }
+            }
+
+            fn on_break(id, pc) {
+                remove_breakpoint(id);
+                this.pc = pc;
+                write(0xd000, reg("hl") >> 8);
+            }
+        "#;
+        let mut script = Script::new(dmg, source).unwrap();
+        for _ in 0..5 {
+            script.emulate_frame().unwrap();
+        }
+        assert!(script.finished());
+        assert_eq!(3, script.frame());
+
+        let dmg = script.emulator();
+        assert_eq!(0xc0, dmg.mmu().peek(0xd000));
+        assert_eq!(1, dmg.mmu().peek(0xd001));
+        assert_eq!(Some(0x0103),
+                   script.this.read_lock::<rhai::Map>().unwrap()["pc"].as_int()
+                                                                      .ok());
+    }
+
+    #[test]
+    fn io() {
+        let dmg = Builder::default().cartridge(Rom::new(vec![0; 0x8000].into_boxed_slice()))
+                                    .video(HeadlessVideo::new())
+                                    .build();
+
+        // reading the unused registers mustn't abort the emulator
+        let source = r#"
+            for addr in 0xff00..0x10000 {
+                read(addr);
+                read16(addr);
+            }
+            if read(0xff27) != 0xff || read16(0xff2e) != 0xffff {
+                throw "unused APU registers";
+            }
+        "#;
+        assert!(Script::new(dmg, source).is_ok());
+    }
+}
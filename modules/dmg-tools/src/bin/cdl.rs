@@ -0,0 +1,81 @@
+#![deny(dead_code)]
+#![deny(unused_imports)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+#![deny(clippy::style)]
+#![deny(clippy::correctness)]
+#![deny(clippy::complexity)]
+#![deny(clippy::perf)]
+use dmg_lib::{
+    cartridge,
+    cdl::{self, CodeDataLog},
+    movie::Movie,
+    Builder,
+};
+use std::{env, fs, path::Path, process};
+
+const USAGE: &str = "Usage: cdl <rom> <frames|movie> <output.cdl>";
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (rom, input, output) = match &args[..] {
+        [rom, input, output] => (rom, input, output),
+        _ => fail(USAGE),
+    };
+
+    let rom = fs::read(rom).expect("Error reading ROM file");
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| fail("Unsupported cartridge"));
+    let builder = Builder::default().cartridge(cartridge);
+
+    // run for a number of frames, or play a movie (to measure its coverage)
+    let (mut dmg, frames) = match input.parse::<usize>() {
+        Ok(frames) => (builder.try_build().unwrap_or_else(|err| fail(err)), frames),
+        Err(_) => {
+            let movie = fs::read(input).expect("Error reading movie file");
+            let movie = Movie::from_bytes(&movie).unwrap_or_else(|err| fail(err));
+            if movie.boot().boot_rom {
+                fail("Movies recorded from the boot ROM are not supported");
+            }
+            let frames = movie.len();
+            let mut dmg = movie.boot()
+                               .configure(builder)
+                               .try_build()
+                               .unwrap_or_else(|err| fail(err));
+            dmg.play_movie(&rom, movie).unwrap_or_else(|err| fail(err));
+            (dmg, frames)
+        }
+    };
+
+    // an existing log is extended
+    let log = if Path::new(output).exists() {
+        let bytes = fs::read(output).expect("Error reading CDL file");
+        if bytes.len() != rom.len() {
+            fail("The CDL file doesn't match the size of the ROM");
+        }
+        CodeDataLog::from_bytes(&bytes)
+    } else {
+        CodeDataLog::new(rom.len())
+    };
+    dmg.mmu_mut().start_cdl(log);
+    for _ in 0..frames {
+        dmg.emulate_frame();
+    }
+    let log = dmg.mmu_mut().stop_cdl().unwrap();
+    fs::write(output, log.to_bytes()).expect("Error writing CDL file");
+
+    let percent = |flags| 100.0 * log.count(flags) as f64 / log.len().max(1) as f64;
+    eprintln!("Code/data log\n========================");
+    eprintln!("Frames ................. {}", frames);
+    eprintln!("Code ................... {:.2}%",
+              percent(cdl::CODE | cdl::OPERAND));
+    eprintln!("Data ................... {:.2}%",
+              percent(cdl::DATA | cdl::DMA));
+    eprintln!("Unused ................. {:.2}%",
+              100.0 - percent(cdl::CODE | cdl::OPERAND | cdl::DATA | cdl::DMA));
+}
@@ -0,0 +1,47 @@
+#![deny(dead_code)]
+#![deny(unused_imports)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+#![deny(clippy::style)]
+#![deny(clippy::correctness)]
+#![deny(clippy::complexity)]
+#![deny(clippy::perf)]
+use dmg_lib::{
+    cdl::{self, CodeDataLog},
+    symbols::Symbols,
+};
+use std::{
+    env, fs,
+    io::{self, BufWriter},
+    process,
+};
+
+const USAGE: &str = "Usage: disasm <rom> <log.cdl> [symbols.sym]";
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (rom, log, sym) = match &args[..] {
+        [rom, log] => (rom, log, None),
+        [rom, log, sym] => (rom, log, Some(sym)),
+        _ => fail(USAGE),
+    };
+
+    let rom = fs::read(rom).expect("Error reading ROM file");
+    let log = CodeDataLog::from_bytes(&fs::read(log).expect("Error reading CDL file"));
+    let symbols = sym.map(|sym| {
+                         let text = fs::read_to_string(sym).expect("Error reading symbol file");
+                         let mut symbols = Symbols::new();
+                         symbols.parse_sym(&text).unwrap_or_else(|err| fail(err));
+                         symbols
+                     });
+
+    let stdout = io::stdout();
+    cdl::disassemble(&rom, &log, symbols.as_ref(), BufWriter::new(stdout.lock()))
+        .expect("Error writing disassembly");
+}
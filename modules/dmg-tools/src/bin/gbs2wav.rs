@@ -0,0 +1,110 @@
+#![deny(dead_code)]
+#![deny(unused_imports)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+#![deny(clippy::style)]
+#![deny(clippy::correctness)]
+#![deny(clippy::complexity)]
+#![deny(clippy::perf)]
+use dmg_backend_headless::apu::WavRecorder;
+use dmg_lib::{
+    apu::device::Stereo44100,
+    gbs::{Header, Player},
+};
+use std::{
+    env,
+    fs::{self, File},
+    io::BufWriter,
+    process,
+};
+
+const USAGE: &str = "Usage: gbs2wav <file.gbs> <track> <seconds> <output.wav> [--stems]";
+
+// 4MHz clock cycles in a second.
+const CLOCK: u64 = 4_194_304;
+
+struct Args {
+    gbs: String,
+    track: u8,
+    seconds: u64,
+    output: String,
+    stems: bool,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut args = env::args().skip(1);
+    let gbs = args.next()?;
+    let track = args.next()?.parse().ok()?;
+    let seconds = args.next()?.parse().ok()?;
+    let output = args.next()?;
+    let stems = match args.next().as_deref() {
+        Some("--stems") => true,
+        Some(_) => return None,
+        None => false,
+    };
+    Some(Args { gbs,
+                track,
+                seconds,
+                output,
+                stems })
+}
+
+fn display(header: &Header) {
+    eprintln!("GBS\n========================");
+    eprintln!("Title .................. `{}`", header.title);
+    eprintln!("Author ................. `{}`", header.author);
+    eprintln!("Copyright .............. `{}`", header.copyright);
+    eprintln!("Songs .................. {}", header.songs);
+    eprintln!("Load address ........... {:04X}h", header.load_addr);
+    eprintln!("Init address ........... {:04X}h", header.init_addr);
+    eprintln!("Play address ........... {:04X}h", header.play_addr);
+    eprintln!("Stack pointer .......... {:04X}h", header.sp);
+    eprintln!("TMA / TAC .............. {:02X}h / {:02X}h", header.tma, header.tac);
+    eprintln!();
+}
+
+fn create(path: &str) -> BufWriter<File> {
+    BufWriter::new(File::create(path).expect("Error creating output file"))
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|| {
+                               eprintln!("{}", USAGE);
+                               process::exit(1)
+                           });
+
+    let gbs = fs::read(&args.gbs).expect("Error reading GBS file");
+    let mut player = Player::<Stereo44100<i16>>::from_bytes(&gbs).unwrap_or_else(|err| {
+                                                                      eprintln!("{}", err);
+                                                                      process::exit(1)
+                                                                  });
+    display(player.header());
+
+    let songs = player.header().songs;
+    if args.track == 0 || args.track > songs {
+        eprintln!("Invalid track {} (the file contains {} songs)", args.track, songs);
+        process::exit(1)
+    }
+    player.start(args.track - 1);
+
+    let mut recorder = WavRecorder::new(player.apu(), create(&args.output)).expect("Error writing WAV file");
+    if args.stems {
+        let stem = args.output.trim_end_matches(".wav");
+        let stems = [create(&format!("{}.square1.wav", stem)),
+                     create(&format!("{}.square2.wav", stem)),
+                     create(&format!("{}.wave.wav", stem)),
+                     create(&format!("{}.noise.wav", stem))];
+        recorder = recorder.with_stems(stems)
+                           .expect("Error writing WAV file");
+    }
+
+    // render in small steps, so the APU sample buffer never fills up
+    for _ in 0..args.seconds * 60 {
+        player.run(CLOCK / 60);
+        recorder.record().expect("Error writing WAV file");
+    }
+    recorder.finish().expect("Error writing WAV file");
+
+    eprintln!("Track {} ({} seconds) written to `{}`", args.track, args.seconds, args.output);
+}
@@ -0,0 +1,93 @@
+#![deny(dead_code)]
+#![deny(unused_imports)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+#![deny(clippy::style)]
+#![deny(clippy::correctness)]
+#![deny(clippy::complexity)]
+#![deny(clippy::perf)]
+use dmg_backend_headless::HeadlessVideo;
+use dmg_lib::{
+    boot::BootRom,
+    cartridge,
+    movie::{Movie, Status},
+    Builder,
+};
+use std::{env, fs, process};
+
+// The last frame is written as a PNG if the output ends with .png, or as a PPM
+// otherwise.
+const USAGE: &str = "Usage: play_movie <rom> <movie> <output.png|ppm> [--boot-rom <file>]";
+
+struct Args {
+    rom: String,
+    movie: String,
+    output: String,
+    boot_rom: Option<String>,
+}
+
+fn parse_args() -> Option<Args> {
+    let mut args = env::args().skip(1);
+    let rom = args.next()?;
+    let movie = args.next()?;
+    let output = args.next()?;
+    let boot_rom = match args.next().as_deref() {
+        Some("--boot-rom") => Some(args.next()?),
+        Some(_) => return None,
+        None => None,
+    };
+    Some(Args { rom,
+                movie,
+                output,
+                boot_rom })
+}
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1)
+}
+
+fn main() {
+    let args = parse_args().unwrap_or_else(|| fail(USAGE));
+
+    let rom = fs::read(&args.rom).expect("Error reading ROM file");
+    let movie = fs::read(&args.movie).expect("Error reading movie file");
+    let movie = Movie::from_bytes(&movie).unwrap_or_else(|err| fail(err));
+    let frames = movie.len();
+
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| fail("Unsupported cartridge"));
+    let mut builder = movie.boot()
+                           .configure(Builder::default().cartridge(cartridge))
+                           .video(HeadlessVideo::new());
+    if movie.boot().boot_rom {
+        let path = args.boot_rom
+                       .as_ref()
+                       .unwrap_or_else(|| fail("The movie requires a boot ROM (--boot-rom)"));
+        let boot_rom = fs::read(path).expect("Error reading boot ROM file");
+        builder = builder.boot_rom(BootRom::from_bytes(&boot_rom).unwrap_or_else(|err| fail(err)));
+    }
+    let mut dmg = builder.try_build().unwrap_or_else(|err| fail(err));
+    dmg.play_movie(&rom, movie).unwrap_or_else(|err| fail(err));
+
+    eprintln!("Movie\n========================");
+    eprintln!("Model .................. {:?}", dmg.boot().model);
+    eprintln!("Frames ................. {}", frames);
+
+    for _ in 0..frames {
+        dmg.emulate_frame();
+    }
+    let status = dmg.movie_status();
+    eprintln!("State hash ............. {:016x}", dmg.state_hash());
+
+    dmg.mmu()
+       .ppu()
+       .video()
+       .save(&args.output)
+       .expect("Error writing output file");
+    eprintln!("Last frame written to `{}`", args.output);
+
+    if let Some(Status::Desync(frame)) = status {
+        fail(format!("Desync at frame {}", frame))
+    }
+}
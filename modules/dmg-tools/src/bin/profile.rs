@@ -0,0 +1,62 @@
+#![deny(dead_code)]
+#![deny(unused_imports)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+#![deny(clippy::style)]
+#![deny(clippy::correctness)]
+#![deny(clippy::complexity)]
+#![deny(clippy::perf)]
+use dmg_lib::{cartridge, profile::Profiler, symbols::Symbols, Builder};
+use std::{
+    env,
+    fs::{self, File},
+    io::BufWriter,
+    process,
+};
+
+const USAGE: &str = "Usage: profile <rom> <frames> <report.txt> <stacks.folded> [symbols.sym]";
+
+// functions & instructions listed in the report
+const TOP: usize = 32;
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (rom, frames, report, folded, sym) = match &args[..] {
+        [rom, frames, report, folded] => (rom, frames, report, folded, None),
+        [rom, frames, report, folded, sym] => (rom, frames, report, folded, Some(sym)),
+        _ => fail(USAGE),
+    };
+    let frames: usize = frames.parse().unwrap_or_else(|_| fail(USAGE));
+
+    let symbols = sym.map(|sym| {
+                         let text = fs::read_to_string(sym).expect("Error reading symbol file");
+                         let mut symbols = Symbols::new();
+                         symbols.parse_sym(&text).unwrap_or_else(|err| fail(err));
+                         symbols
+                     });
+
+    let rom = fs::read(rom).expect("Error reading ROM file");
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| fail("Unsupported cartridge"));
+    let mut dmg = Builder::default().cartridge(cartridge)
+                                    .try_build()
+                                    .unwrap_or_else(|err| fail(err));
+
+    dmg.mmu_mut().start_profile(Profiler::new());
+    for _ in 0..frames {
+        dmg.emulate_frame();
+    }
+    let profiler = dmg.mmu_mut().stop_profile().unwrap();
+
+    let file = File::create(report).expect("Error creating report file");
+    profiler.write_report(BufWriter::new(file), symbols.as_ref(), TOP)
+            .expect("Error writing report");
+    let file = File::create(folded).expect("Error creating folded stacks file");
+    profiler.write_folded(BufWriter::new(file), symbols.as_ref())
+            .expect("Error writing folded stacks");
+}
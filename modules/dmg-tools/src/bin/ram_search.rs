@@ -0,0 +1,150 @@
+#![deny(dead_code)]
+#![deny(unused_imports)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+#![deny(clippy::style)]
+#![deny(clippy::correctness)]
+#![deny(clippy::complexity)]
+#![deny(clippy::perf)]
+use dmg_lib::{
+    cartridge,
+    joypad::{Btn, Dir, Key},
+    search::{Compare, Kind, Search},
+    Builder,
+};
+use std::{
+    env, fs,
+    io::{self, BufRead},
+    process,
+};
+
+const USAGE: &str = "Usage: ram_search <rom> (commands are read from stdin, try `help`)";
+
+const HELP: &str = "\
+run <frames>            emulate frames
+press <key>             press a key (a, b, start, select, up, down, left, right)
+release <key>           release a key
+new [kind]              start a new search (u8, u16le, u16be, bcd8, bcd16le, bcd16be)
+eq | ne | inc | dec     keep the values equal, changed, increased or decreased
+= <value>               keep the values equal to the given one
+list                    list the candidates (up to 32)
+quit";
+
+// Candidates listed by the `list` command.
+const LIST: usize = 32;
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1)
+}
+
+fn parse_key(key: &str) -> Option<Key> {
+    match key {
+        "a" => Some(Key::Btn(Btn::A)),
+        "b" => Some(Key::Btn(Btn::B)),
+        "start" => Some(Key::Btn(Btn::Start)),
+        "select" => Some(Key::Btn(Btn::Select)),
+        "up" => Some(Key::Dir(Dir::Up)),
+        "down" => Some(Key::Dir(Dir::Down)),
+        "left" => Some(Key::Dir(Dir::Left)),
+        "right" => Some(Key::Dir(Dir::Right)),
+        _ => None,
+    }
+}
+
+fn parse_kind(kind: &str) -> Option<Kind> {
+    match kind {
+        "u8" => Some(Kind::U8),
+        "u16le" => Some(Kind::U16Le),
+        "u16be" => Some(Kind::U16Be),
+        "bcd8" => Some(Kind::Bcd8),
+        "bcd16le" => Some(Kind::Bcd16Le),
+        "bcd16be" => Some(Kind::Bcd16Be),
+        _ => None,
+    }
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let rom = match &args[..] {
+        [rom] => rom,
+        _ => fail(USAGE),
+    };
+
+    let rom = fs::read(rom).expect("Error reading ROM file");
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| fail("Unsupported cartridge"));
+    let mut dmg = Builder::default().cartridge(cartridge)
+                                    .try_build()
+                                    .unwrap_or_else(|err| fail(err));
+    let mut search = Search::new(dmg.mmu(), Kind::U8);
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = line.expect("Error reading command");
+        let words: Vec<_> = line.split_whitespace().collect();
+        let compare = match &words[..] {
+            [] => continue,
+            ["quit"] => break,
+            ["help"] => {
+                println!("{}", HELP);
+                None
+            }
+            ["run", frames] => {
+                match frames.parse::<usize>() {
+                    Ok(frames) => (0..frames).for_each(|_| {
+                                                 dmg.emulate_frame();
+                                             }),
+                    Err(_) => eprintln!("Invalid number of frames"),
+                }
+                None
+            }
+            ["press", key] | ["release", key] => {
+                match parse_key(key) {
+                    Some(key) if words[0] == "press" => dmg.mmu_mut().joypad_mut().press(key),
+                    Some(key) => dmg.mmu_mut().joypad_mut().release(key),
+                    None => eprintln!("Unknown key"),
+                }
+                None
+            }
+            ["new"] | ["new", _] => {
+                match words.get(1).map_or(Some(Kind::U8), |kind| parse_kind(kind)) {
+                    Some(kind) => {
+                        search = Search::new(dmg.mmu(), kind);
+                        println!("{} candidates ({})", search.len(), kind);
+                    }
+                    None => eprintln!("Unknown kind"),
+                }
+                None
+            }
+            ["eq"] => Some(Compare::Equal),
+            ["ne"] => Some(Compare::Changed),
+            ["inc"] => Some(Compare::Increased),
+            ["dec"] => Some(Compare::Decreased),
+            ["=", value] => match value.parse() {
+                Ok(value) => Some(Compare::Value(value)),
+                Err(_) => {
+                    eprintln!("Invalid value");
+                    None
+                }
+            },
+            ["list"] => {
+                for &(bank, addr) in search.candidates().iter().take(LIST) {
+                    let value = search.value((bank, addr)).unwrap_or(0);
+                    println!("{:02x}:{:04x} = {}", bank, addr, value);
+                }
+                if search.len() > LIST {
+                    println!("... ({} more)", search.len() - LIST);
+                }
+                None
+            }
+            _ => {
+                eprintln!("Unknown command (try `help`)");
+                None
+            }
+        };
+        if let Some(compare) = compare {
+            println!("{} candidates", search.filter(dmg.mmu(), compare));
+        }
+    }
+}
@@ -0,0 +1,45 @@
+#![deny(dead_code)]
+#![deny(unused_imports)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+#![deny(clippy::style)]
+#![deny(clippy::correctness)]
+#![deny(clippy::complexity)]
+#![deny(clippy::perf)]
+use dmg_backend_headless::HeadlessVideo;
+use dmg_lib::{cartridge, Builder};
+use dmg_script::Script;
+use std::{env, fs, process};
+
+const USAGE: &str = "Usage: script <rom> <script.rhai> [frames]";
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (rom, script, frames) = match &args[..] {
+        [rom, script] => (rom, script, None),
+        [rom, script, frames] => (rom, script, Some(frames)),
+        _ => fail(USAGE),
+    };
+    // without a limit, it runs until the script calls `exit()`
+    let frames = frames.map_or(u64::MAX, |frames| {
+                           frames.parse().unwrap_or_else(|_| fail(USAGE))
+                       });
+
+    let rom = fs::read(rom).expect("Error reading ROM file");
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| fail("Unsupported cartridge"));
+    let dmg = Builder::default().cartridge(cartridge)
+                                .video(HeadlessVideo::new())
+                                .try_build()
+                                .unwrap_or_else(|err| fail(err));
+
+    let mut script = Script::from_file(dmg, script).unwrap_or_else(|err| fail(err));
+    while !script.finished() && script.frame() < frames {
+        script.emulate_frame().unwrap_or_else(|err| fail(err));
+    }
+}
@@ -0,0 +1,68 @@
+#![deny(dead_code)]
+#![deny(unused_imports)]
+#![deny(unused_must_use)]
+#![deny(unused_variables)]
+#![deny(unused_mut)]
+#![deny(clippy::style)]
+#![deny(clippy::correctness)]
+#![deny(clippy::complexity)]
+#![deny(clippy::perf)]
+use dmg_lib::{cartridge, symbols::Symbols, trace::Trace, Builder};
+use std::{
+    env,
+    fs::{self, File},
+    io::BufWriter,
+    process,
+};
+
+const USAGE: &str = "Usage: trace <rom> <frames> <output.log> [--extra] [--symbols <file.sym>]";
+
+fn fail(err: impl std::fmt::Display) -> ! {
+    eprintln!("{}", err);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<_> = env::args().skip(1).collect();
+    let (rom, frames, output, flags) = match &args[..] {
+        [rom, frames, output, flags @ ..] => (rom, frames, output, flags),
+        _ => fail(USAGE),
+    };
+    let (mut extra, mut sym) = (false, None);
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        match flag.as_str() {
+            "--extra" => extra = true,
+            "--symbols" => sym = Some(flags.next().unwrap_or_else(|| fail(USAGE))),
+            _ => fail(USAGE),
+        }
+    }
+    let frames: usize = frames.parse().unwrap_or_else(|_| fail(USAGE));
+
+    let rom = fs::read(rom).expect("Error reading ROM file");
+    let cartridge = cartridge::from_bytes(&rom).unwrap_or_else(|_| fail("Unsupported cartridge"));
+    let mut dmg = Builder::default().cartridge(cartridge)
+                                    .try_build()
+                                    .unwrap_or_else(|err| fail(err));
+
+    let file = File::create(output).expect("Error creating output file");
+    let mut trace = Trace::new(BufWriter::new(file));
+    if extra {
+        trace = trace.bank().ly().cycles().disasm();
+    }
+    if let Some(sym) = sym {
+        let text = fs::read_to_string(sym).expect("Error reading symbol file");
+        let mut symbols = Symbols::new();
+        symbols.parse_sym(&text).unwrap_or_else(|err| fail(err));
+        trace = trace.symbols(symbols);
+    }
+    dmg.mmu_mut().start_trace(trace);
+    for _ in 0..frames {
+        dmg.emulate_frame();
+    }
+    dmg.mmu_mut()
+       .stop_trace()
+       .unwrap()
+       .finish()
+       .expect("Error writing trace");
+}